@@ -0,0 +1,458 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::app::MqttEvent;
+use crate::config::TopicAdapter;
+use crate::decode::{self, decode};
+use crate::discovery::kind_for;
+use crate::mqtt::decode_failed;
+use crate::units;
+
+/// The configured topic layout adapter.
+pub enum LayoutAdapter {
+    Statestream(StatestreamAdapter),
+    Zigbee2mqtt(Zigbee2MqttAdapter),
+    Tasmota(TasmotaAdapter),
+}
+
+impl LayoutAdapter {
+    /// Build the adapter selected in config; `Auto` has none.
+    pub fn new(adapter: TopicAdapter, base: &str) -> Option<Self> {
+        match adapter {
+            TopicAdapter::Auto => None,
+            TopicAdapter::Statestream => Some(Self::Statestream(StatestreamAdapter::new(base))),
+            TopicAdapter::Zigbee2mqtt => Some(Self::Zigbee2mqtt(Zigbee2MqttAdapter::new(base))),
+            TopicAdapter::Tasmota => Some(Self::Tasmota(TasmotaAdapter::new(base))),
+        }
+    }
+
+    /// Handle a publish. Returns `None` when the topic is outside the
+    /// adapter's layout, so other mappers can look at it.
+    pub fn handle(&mut self, topic: &str, payload: &[u8]) -> Option<Vec<MqttEvent>> {
+        match self {
+            Self::Statestream(adapter) => adapter.handle(topic, payload),
+            Self::Zigbee2mqtt(adapter) => adapter.handle(topic, payload),
+            Self::Tasmota(adapter) => adapter.handle(topic, payload),
+        }
+    }
+}
+
+/// Home Assistant `mqtt_statestream` adapter.
+///
+/// Statestream publishes `<base>/<domain>/<entity_id>/state` plus one topic per
+/// attribute (`.../unit_of_measurement`, `.../device_class`, ...). Attribute
+/// topics are cached per entity and paired with the state so the metric kind
+/// and unit conversion can use them; they are never mapped as values.
+pub struct StatestreamAdapter {
+    base: String,
+    entities: HashMap<String, EntityState>,
+}
+
+#[derive(Default)]
+struct EntityState {
+    unit: Option<String>,
+    device_class: Option<String>,
+    last_state: Option<Vec<u8>>,
+}
+
+impl StatestreamAdapter {
+    /// Create an adapter for the statestream base topic.
+    pub fn new(base: &str) -> Self {
+        Self {
+            base: normalize_base(base),
+            entities: HashMap::new(),
+        }
+    }
+
+    /// Handle a publish. Returns `None` when the topic is outside the
+    /// statestream layout, so other mappers can look at it.
+    pub fn handle(&mut self, topic: &str, payload: &[u8]) -> Option<Vec<MqttEvent>> {
+        let rest = topic.strip_prefix(&self.base)?.strip_prefix('/')?;
+        let mut parts = rest.splitn(3, '/');
+        let (domain, entity_id, leaf) = (parts.next()?, parts.next()?, parts.next()?);
+        if leaf.contains('/') {
+            return None;
+        }
+        let key = format!("{domain}/{entity_id}");
+        let state_topic = format!("{}/{key}/state", self.base);
+        let entity = self.entities.entry(key).or_default();
+
+        match leaf {
+            "state" => {
+                entity.last_state = Some(payload.to_vec());
+                Some(entity.events(&self.base, &state_topic, entity_id))
+            }
+            "unit_of_measurement" | "device_class" => {
+                let value = attribute_string(payload);
+                let slot = if leaf == "unit_of_measurement" {
+                    &mut entity.unit
+                } else {
+                    &mut entity.device_class
+                };
+                if *slot == value {
+                    return Some(Vec::new());
+                }
+                *slot = value;
+                // Re-emit the cached state now that it can be classified or
+                // converted correctly.
+                Some(entity.events(&self.base, &state_topic, entity_id))
+            }
+            _ => Some(Vec::new()),
+        }
+    }
+}
+
+impl EntityState {
+    /// Statestream hides Home Assistant devices, so every entity is reported
+    /// under the statestream base.
+    fn events(&self, device: &str, state_topic: &str, entity_id: &str) -> Vec<MqttEvent> {
+        let Some(payload) = &self.last_state else {
+            return Vec::new();
+        };
+        let Some(kind) = kind_for(
+            self.device_class.as_deref(),
+            self.unit.as_deref(),
+            entity_id,
+        ) else {
+            return Vec::new();
+        };
+        let event = match decode(payload, None) {
+            Ok(decoded) => {
+                let unit = self.unit.as_deref().or(decoded.unit.as_deref());
+                let (value, unit) = units::normalize_reading(kind, decoded.value, unit);
+                MqttEvent::Metric {
+                    topic: state_topic.to_string(),
+                    value,
+                    kind: kind.to_string(),
+                    unit,
+                    device: device.to_string(),
+                }
+            }
+            Err(err) => decode_failed(state_topic, kind, device.to_string(), &err),
+        };
+        vec![event]
+    }
+}
+
+/// Statestream attributes are JSON encoded (`"°C"`); fall back to raw text.
+fn attribute_string(payload: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(payload).trim().to_string();
+    let value = match serde_json::from_str::<Value>(&text) {
+        Ok(Value::String(s)) => s,
+        Ok(Value::Null) => return None,
+        _ => text,
+    };
+    (!value.is_empty()).then_some(value)
+}
+
+/// Zigbee2MQTT adapter.
+///
+/// Devices publish one JSON object per update on `<base>/<friendly_name>`;
+/// friendly names may contain slashes. Bridge topics and `/set`/`/get`
+/// requests are ignored, `/availability` is reported per device.
+pub struct Zigbee2MqttAdapter {
+    base: String,
+}
+
+/// Zigbee2MQTT exposes, their metric kinds and fixed units.
+const ZIGBEE2MQTT_FIELDS: &[(&str, &str, Option<&str>)] = &[
+    ("pm1", "pm1", Some("µg/m³")),
+    ("pm25", "pm25", Some("µg/m³")),
+    ("pm10", "pm10", Some("µg/m³")),
+    ("co2", "co2", Some("ppm")),
+    ("voc", "tvoc", Some("ppb")),
+    ("voc_index", "voc_index", None),
+    ("temperature", "temperature", Some("°C")),
+    ("humidity", "humidity", Some("%")),
+    ("pressure", "pressure", Some("hPa")),
+];
+
+impl Zigbee2MqttAdapter {
+    /// Create an adapter for the Zigbee2MQTT base topic.
+    pub fn new(base: &str) -> Self {
+        Self {
+            base: normalize_base(base),
+        }
+    }
+
+    /// Handle a publish. Returns `None` when the topic is outside the base.
+    pub fn handle(&mut self, topic: &str, payload: &[u8]) -> Option<Vec<MqttEvent>> {
+        let device = topic.strip_prefix(&self.base)?.strip_prefix('/')?;
+        if device.is_empty() || device == "bridge" || device.starts_with("bridge/") {
+            return Some(Vec::new());
+        }
+        if let Some(device) = device.strip_suffix("/availability") {
+            // Legacy availability is plain text, newer releases send JSON.
+            let text = String::from_utf8_lossy(payload);
+            let state = match serde_json::from_str::<Value>(&text) {
+                Ok(json) => json
+                    .get("state")
+                    .and_then(Value::as_str)
+                    .map(str::to_string),
+                Err(_) => Some(text.trim().to_string()),
+            };
+            let online = match state.as_deref() {
+                Some("online") => true,
+                Some("offline") => false,
+                _ => return Some(Vec::new()),
+            };
+            return Some(vec![MqttEvent::Availability {
+                device: device.to_string(),
+                online,
+            }]);
+        }
+        if ["/set", "/get"]
+            .iter()
+            .any(|suffix| device.ends_with(suffix))
+        {
+            return Some(Vec::new());
+        }
+
+        let json: Value = match serde_json::from_slice(payload) {
+            Ok(json @ Value::Object(_)) => json,
+            _ => return Some(Vec::new()),
+        };
+        let mut events: Vec<MqttEvent> = Vec::new();
+        for (key, kind, unit) in ZIGBEE2MQTT_FIELDS {
+            if events.iter().any(|evt| is_kind(evt, kind)) {
+                continue;
+            }
+            if let Some(evt) = json
+                .get(key)
+                .and_then(|v| field_event(topic, device, kind, v, *unit))
+            {
+                events.push(evt);
+            }
+        }
+        Some(events)
+    }
+}
+
+/// Tasmota telemetry adapter.
+///
+/// `<base>/<device>/SENSOR` carries one JSON object per attached sensor
+/// (`{"SCD30": {"CarbonDioxide": 612}, "TempUnit": "C"}`); when several
+/// sensors report a metric, the first by sensor name wins. `<base>/<device>/LWT` reports availability.
+pub struct TasmotaAdapter {
+    base: String,
+}
+
+impl TasmotaAdapter {
+    /// Create an adapter for the Tasmota telemetry prefix.
+    pub fn new(base: &str) -> Self {
+        Self {
+            base: normalize_base(base),
+        }
+    }
+
+    /// Handle a publish. Returns `None` when the topic is outside the base.
+    pub fn handle(&mut self, topic: &str, payload: &[u8]) -> Option<Vec<MqttEvent>> {
+        let rest = topic.strip_prefix(&self.base)?.strip_prefix('/')?;
+        let (device, leaf) = rest.rsplit_once('/')?;
+        match leaf {
+            "LWT" => {
+                let online = match String::from_utf8_lossy(payload).trim() {
+                    "Online" => true,
+                    "Offline" => false,
+                    _ => return Some(Vec::new()),
+                };
+                Some(vec![MqttEvent::Availability {
+                    device: device.to_string(),
+                    online,
+                }])
+            }
+            "SENSOR" => {
+                let Ok(Value::Object(json)) = serde_json::from_slice::<Value>(payload) else {
+                    return Some(vec![MqttEvent::DecodeFailed {
+                        topic: topic.to_string(),
+                        reason: "payload is not a JSON object".to_string(),
+                    }]);
+                };
+                let unit_of = |key| json.get(key).and_then(Value::as_str);
+                let (temp_unit, pressure_unit) = (unit_of("TempUnit"), unit_of("PressureUnit"));
+                let mut events: Vec<MqttEvent> = Vec::new();
+                for reading in json.values().filter_map(Value::as_object) {
+                    for (key, value) in reading {
+                        let Some((kind, unit)) = tasmota_field(key, temp_unit, pressure_unit)
+                        else {
+                            continue;
+                        };
+                        if events.iter().any(|evt| is_kind(evt, kind)) {
+                            continue;
+                        }
+                        if let Some(evt) = field_event(topic, device, kind, value, unit) {
+                            events.push(evt);
+                        }
+                    }
+                }
+                Some(events)
+            }
+            _ => Some(Vec::new()),
+        }
+    }
+}
+
+/// Metric kind and unit of a Tasmota sensor field.
+fn tasmota_field<'a>(
+    key: &str,
+    temp_unit: Option<&'a str>,
+    pressure_unit: Option<&'a str>,
+) -> Option<(&'static str, Option<&'a str>)> {
+    let field = match key.to_ascii_lowercase().as_str() {
+        "pm1" | "pm1.0" => ("pm1", Some("µg/m³")),
+        "pm2.5" => ("pm25", Some("µg/m³")),
+        "pm10" => ("pm10", Some("µg/m³")),
+        "carbondioxide" | "co2" => ("co2", Some("ppm")),
+        "tvoc" => ("tvoc", Some("ppb")),
+        "temperature" => ("temperature", Some(temp_unit.unwrap_or("C"))),
+        "humidity" => ("humidity", Some("%")),
+        "pressure" => ("pressure", Some(pressure_unit.unwrap_or("hPa"))),
+        _ => return None,
+    };
+    Some(field)
+}
+
+/// Turn one JSON field into a metric event, converting into dashboard units.
+fn field_event(
+    topic: &str,
+    device: &str,
+    kind: &str,
+    value: &Value,
+    unit: Option<&str>,
+) -> Option<MqttEvent> {
+    let (value, suffix) = match value {
+        Value::Number(n) => (n.as_f64()?, None),
+        Value::String(s) => {
+            let decoded = decode::parse_number(s)?;
+            (decoded.value, decoded.unit)
+        }
+        _ => return None,
+    };
+    let unit = unit.or(suffix.as_deref());
+    let (value, unit) = units::normalize_reading(kind, value, unit);
+    Some(MqttEvent::Metric {
+        topic: topic.to_string(),
+        value,
+        kind: kind.to_string(),
+        unit,
+        device: device.to_string(),
+    })
+}
+
+fn is_kind(evt: &MqttEvent, kind: &str) -> bool {
+    matches!(evt, MqttEvent::Metric { kind: k, .. } if k == kind)
+}
+
+fn normalize_base(base: &str) -> String {
+    base.trim()
+        .trim_end_matches("/#")
+        .trim_end_matches('/')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics(events: Vec<MqttEvent>) -> Vec<(String, f64)> {
+        events
+            .into_iter()
+            .filter_map(|evt| match evt {
+                MqttEvent::Metric { kind, value, .. } => Some((kind, value)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn state_is_paired_with_attributes_and_converted() {
+        let mut adapter = StatestreamAdapter::new("homeassistant/statestream");
+        let base = "homeassistant/statestream/sensor/office_climate";
+
+        let attr = adapter.handle(&format!("{base}/device_class"), br#""temperature""#);
+        assert_eq!(attr.map(metrics), Some(vec![]));
+
+        let state = adapter.handle(&format!("{base}/state"), b"20");
+        assert_eq!(
+            state.map(metrics),
+            Some(vec![("temperature".to_string(), 20.0)])
+        );
+
+        // Unit arrives after the state: the cached state is re-emitted converted.
+        let unit = adapter.handle(&format!("{base}/unit_of_measurement"), r#""°C""#.as_bytes());
+        assert_eq!(
+            unit.map(metrics),
+            Some(vec![("temperature".to_string(), 68.0)])
+        );
+    }
+
+    #[test]
+    fn attribute_topics_are_never_mapped_as_values() {
+        let mut adapter = StatestreamAdapter::new("statestream");
+
+        let events = adapter.handle("statestream/climate/hall/current_temperature", b"21");
+        assert_eq!(events.map(metrics), Some(vec![]));
+        assert!(adapter.handle("other/sensor/x/state", b"1").is_none());
+    }
+
+    #[test]
+    fn zigbee2mqtt_devices_report_several_metrics() {
+        let mut adapter = Zigbee2MqttAdapter::new("zigbee2mqtt");
+        let payload =
+            br#"{"pm25": 8, "co2": 540, "voc_index": 112, "temperature": 20, "linkquality": 90}"#;
+
+        let events = adapter.handle("zigbee2mqtt/Living room/air", payload);
+        assert_eq!(
+            events.map(metrics),
+            Some(vec![
+                ("pm25".to_string(), 8.0),
+                ("co2".to_string(), 540.0),
+                ("voc_index".to_string(), 112.0),
+                ("temperature".to_string(), 68.0),
+            ])
+        );
+
+        let events = adapter
+            .handle(
+                "zigbee2mqtt/Living room/air/availability",
+                br#"{"state":"offline"}"#,
+            )
+            .expect("in layout");
+        assert!(matches!(
+            events.as_slice(),
+            [MqttEvent::Availability { device, online: false }] if device == "Living room/air"
+        ));
+        assert_eq!(
+            adapter
+                .handle("zigbee2mqtt/bridge/state", b"online")
+                .map(metrics),
+            Some(vec![])
+        );
+    }
+
+    #[test]
+    fn tasmota_sensor_json_is_flattened() {
+        let mut adapter = TasmotaAdapter::new("tele");
+        let payload = br#"{"Time": "2024-01-01T00:00:00", "SCD30": {"CarbonDioxide": 701, "Temperature": 71.6, "Humidity": 40},
+            "PMS5003": {"CF1": 9, "PM2.5": 7, "PM10": 11}, "TempUnit": "F"}"#;
+
+        let mut mapped = metrics(
+            adapter
+                .handle("tele/kitchen/SENSOR", payload)
+                .expect("in layout"),
+        );
+        mapped.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            mapped,
+            vec![
+                ("co2".to_string(), 701.0),
+                ("humidity".to_string(), 40.0),
+                ("pm10".to_string(), 11.0),
+                ("pm25".to_string(), 7.0),
+                ("temperature".to_string(), 71.6),
+            ]
+        );
+        assert!(adapter.handle("stat/kitchen/POWER", b"ON").is_none());
+    }
+}
@@ -1,12 +1,17 @@
-use std::{sync::mpsc, thread::JoinHandle, time::Instant};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{LazyLock, mpsc},
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime},
+};
 use tracing::warn;
 
-use crate::{config, mqtt, secrets};
+use crate::metrics::{self, MetricRegistry};
+use crate::{capture, config, diagnostics, explorer, inspector, mqtt, secrets, tls, wizard};
 
-pub enum TestResult {
-    Ok,
-    Err(String),
-}
+/// Session details kept for the status details dialog.
+const MAX_SESSION_DETAILS: usize = 50;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
 pub enum MqttState {
@@ -24,19 +29,6 @@ impl MqttState {
     }
 }
 
-#[derive(Default, Clone, Debug)]
-pub struct Metrics {
-    pub pm1: Option<f64>,
-    pub pm25: Option<f64>,
-    pub pm10: Option<f64>,
-    pub tvoc: Option<f64>,
-    pub co2: Option<f64>,
-    pub temp: Option<f64>,
-    pub humidity: Option<f64>,
-    pub last_topic: Option<String>,
-    pub last_update: Option<Instant>,
-}
-
 /// Events emitted by the MQTT background thread.
 pub enum MqttEvent {
     /// MQTT connection established.
@@ -51,9 +43,43 @@ pub enum MqttEvent {
         value: f64,
         /// Normalized metric kind (e.g. "pm25").
         kind: String,
+        /// Display unit override from a mapping rule.
+        unit: Option<String>,
+        /// Device the reading belongs to.
+        device: String,
+    },
+    /// A mapped topic reported its sensor as `unavailable` or `unknown`.
+    Unavailable {
+        /// Full MQTT topic of the message.
+        topic: String,
+        /// Normalized metric kind (e.g. "pm25").
+        kind: String,
+        /// Device the sensor belongs to.
+        device: String,
+    },
+    /// A device reported its availability (ESPHome `<node>/status`).
+    Availability {
+        /// Device (ESPHome node) name.
+        device: String,
+        /// Whether the device reported itself online.
+        online: bool,
+    },
+    /// A mapped topic carried a payload that could not be decoded.
+    DecodeFailed {
+        /// Full MQTT topic of the message.
+        topic: String,
+        /// Why decoding failed.
+        reason: String,
     },
     /// Human-readable status update.
     Status(String),
+    /// Broker session detail such as an MQTT 5 reason code, kept for the
+    /// status details.
+    SessionDetail(String),
+    /// Certificate the broker presented on the first connection.
+    BrokerCertificate(tls::CertInfo),
+    /// Every publish the listener received, mapped or not.
+    Received(mqtt::Received),
 }
 
 /// Main application state and UI controller.
@@ -64,6 +90,8 @@ pub struct Air1App {
     pub cfg: config::AppConfig,
     /// In-memory MQTT password (optional).
     pub password: Option<String>,
+    /// In-memory passphrase for an encrypted client key (optional).
+    pub key_passphrase: Option<String>,
     /// Status message displayed in the UI.
     pub status: String,
     /// Timestamp of the last successful save.
@@ -72,11 +100,38 @@ pub struct Air1App {
     pub keyring_unavailable: bool,
     /// Whether an MQTT connection test is running.
     pub testing: bool,
-    pub test_rx: mpsc::Receiver<TestResult>,
-    pub test_tx: mpsc::Sender<TestResult>,
+    pub test_rx: mpsc::Receiver<diagnostics::Report>,
+    pub test_tx: mpsc::Sender<diagnostics::Report>,
+    /// Diagnostics of the last connection test, until the UI shows them.
+    pub diagnostics: Option<diagnostics::Report>,
     pub mqtt_rx: mpsc::Receiver<MqttEvent>,
     pub mqtt_tx: mpsc::Sender<MqttEvent>,
-    pub metrics: Metrics,
+    /// Latest readings per device.
+    pub devices: BTreeMap<String, MetricRegistry>,
+    /// Number of mapped messages whose payload could not be decoded.
+    pub decode_failures: u64,
+    /// Most recent decode failure as `topic: reason`.
+    pub last_decode_error: Option<String>,
+    /// Last reported availability per device.
+    pub device_availability: BTreeMap<String, bool>,
+    /// Unexpected extra sources as `(device, metric)` -> latest new topic.
+    pub source_conflicts: BTreeMap<(String, String), String>,
+    /// Recent broker session details (CONNACK/SUBACK/DISCONNECT), oldest first.
+    pub session_details: Vec<String>,
+    /// Broker certificate awaiting a trust-on-first-use decision.
+    pub pending_pin: Option<tls::CertInfo>,
+    /// Certificate the broker presented on the current connection.
+    pub broker_certificate: Option<tls::CertInfo>,
+    /// Certificates in the configured CA file, read when the listener starts.
+    pub ca_certificates: Vec<tls::CertInfo>,
+    /// Every topic received on the current subscriptions.
+    pub explorer: explorer::TopicExplorer,
+    /// Recent raw publishes for the traffic inspector.
+    pub traffic: inspector::TrafficLog,
+    /// Capture being replayed instead of listening to the broker.
+    pub replay: Option<(PathBuf, capture::ReplaySpeed)>,
+    /// Capture file receiving every publish, while recording.
+    pub recorder: Option<capture::Recorder>,
     pub mqtt_state: MqttState,
     pub connected: bool,
     pub mqtt_handle: Option<JoinHandle<()>>,
@@ -98,10 +153,18 @@ const DASHBOARD_SECTIONS: &[DashboardSectionDef] = &[
         id: "air_quality",
         title: "Air Quality (Particulate Matter)",
     },
+    DashboardSectionDef {
+        id: "particle_counts",
+        title: "Particle Size Distribution",
+    },
     DashboardSectionDef {
         id: "gas",
         title: "Gas Sensors",
     },
+    DashboardSectionDef {
+        id: "other_gases",
+        title: "Other Gases",
+    },
     DashboardSectionDef {
         id: "environment",
         title: "Environment",
@@ -120,15 +183,29 @@ impl Default for Air1App {
             cfg_paths,
             cfg,
             password: None,
+            key_passphrase: None,
             status: String::new(),
             last_save: None,
             keyring_unavailable: false,
             testing: false,
             test_rx,
             test_tx,
+            diagnostics: None,
             mqtt_rx,
             mqtt_tx,
-            metrics: Metrics::default(),
+            devices: BTreeMap::new(),
+            decode_failures: 0,
+            last_decode_error: None,
+            device_availability: BTreeMap::new(),
+            source_conflicts: BTreeMap::new(),
+            session_details: Vec::new(),
+            pending_pin: None,
+            broker_certificate: None,
+            ca_certificates: Vec::new(),
+            explorer: explorer::TopicExplorer::default(),
+            traffic: inspector::TrafficLog::default(),
+            replay: None,
+            recorder: None,
             mqtt_state: MqttState::Stopped,
             connected: false,
             mqtt_handle: None,
@@ -166,7 +243,7 @@ impl Air1App {
         let mut status = String::new();
 
         let password = if cfg.mqtt.remember_password && !keyring_unavailable {
-            match secrets::load_password() {
+            match secrets::load_password(&cfg.profile) {
                 Ok(secret) => {
                     if secret.is_none() {
                         status = "Password not found in keyring".to_string();
@@ -183,20 +260,39 @@ impl Air1App {
         } else {
             None
         };
+        let key_passphrase = if keyring_unavailable {
+            None
+        } else {
+            Self::load_key_passphrase(&cfg)
+        };
 
         Self {
             cfg_paths,
             cfg,
             password,
+            key_passphrase,
             status,
             last_save: None,
             keyring_unavailable,
             testing: false,
             test_rx: rx,
             test_tx: tx,
+            diagnostics: None,
             mqtt_rx,
             mqtt_tx: mqtt_tx.clone(),
-            metrics: Metrics::default(),
+            devices: BTreeMap::new(),
+            decode_failures: 0,
+            last_decode_error: None,
+            device_availability: BTreeMap::new(),
+            source_conflicts: BTreeMap::new(),
+            session_details: Vec::new(),
+            pending_pin: None,
+            broker_certificate: None,
+            ca_certificates: Vec::new(),
+            explorer: explorer::TopicExplorer::default(),
+            traffic: inspector::TrafficLog::default(),
+            replay: None,
+            recorder: None,
             mqtt_state: MqttState::Stopped,
             connected: false,
             mqtt_handle: None,
@@ -209,10 +305,16 @@ impl Air1App {
             // Try to save password to keyring first if needed
             if self.cfg.mqtt.remember_password {
                 if let Some(secret) = &self.password {
-                    secrets::save_password(secret)?;
+                    secrets::save_password(&self.cfg.profile, secret)?;
                 }
             } else {
-                secrets::delete_password()?;
+                secrets::delete_password(&self.cfg.profile)?;
+            }
+            match &self.key_passphrase {
+                Some(secret) if self.cfg.mqtt.remember_password => {
+                    secrets::save_key_passphrase(&self.cfg.profile, secret)?;
+                }
+                _ => secrets::delete_key_passphrase(&self.cfg.profile)?,
             }
             // Only save config after keyring operations succeed
             config::save(&self.cfg_paths, &self.cfg)?;
@@ -241,12 +343,16 @@ impl Air1App {
     }
 
     pub fn poll_tests(&mut self) {
-        while let Ok(msg) = self.test_rx.try_recv() {
+        while let Ok(report) = self.test_rx.try_recv() {
             self.testing = false;
-            match msg {
-                TestResult::Ok => self.status = "MQTT test succeeded".to_string(),
-                TestResult::Err(err) => self.status = format!("MQTT test failed: {err}"),
-            }
+            self.status = match report.first_failure() {
+                None => "MQTT test succeeded".to_string(),
+                Some(step) => match step.detail.first() {
+                    Some(reason) => format!("MQTT test failed at {}: {reason}", step.name),
+                    None => format!("MQTT test failed at {}", step.name),
+                },
+            };
+            self.diagnostics = Some(report);
         }
     }
 
@@ -264,10 +370,11 @@ impl Air1App {
 
                     // The listener emits Disconnected on transient failures too.
                     // Joining here would freeze the UI while reconnect loop runs.
-                    let listener_finished = self
-                        .mqtt_handle
-                        .as_ref()
-                        .is_some_and(std::thread::JoinHandle::is_finished);
+                    let listener_finished = err == capture::REPLAY_FINISHED
+                        || self
+                            .mqtt_handle
+                            .as_ref()
+                            .is_some_and(std::thread::JoinHandle::is_finished);
                     if listener_finished {
                         if let Some(handle) = self.mqtt_handle.take() {
                             let _ = handle.join();
@@ -288,25 +395,189 @@ impl Air1App {
                     }
                     self.status = msg;
                 }
-                MqttEvent::Metric { topic, value, kind } => {
-                    self.metrics.last_topic = Some(topic);
-                    self.metrics.last_update = Some(Instant::now());
-                    let slot = match kind.as_str() {
-                        "pm1" => &mut self.metrics.pm1,
-                        "pm25" | "pm2_5" => &mut self.metrics.pm25,
-                        "pm10" => &mut self.metrics.pm10,
-                        "tvoc" => &mut self.metrics.tvoc,
-                        "co2" => &mut self.metrics.co2,
-                        "temp" | "temperature" => &mut self.metrics.temp,
-                        "humidity" => &mut self.metrics.humidity,
-                        _ => continue,
-                    };
-                    *slot = Some(value);
+                MqttEvent::SessionDetail(detail) => {
+                    if self.session_details.len() >= MAX_SESSION_DETAILS {
+                        self.session_details.remove(0);
+                    }
+                    self.session_details.push(detail);
+                }
+                MqttEvent::BrokerCertificate(info) => {
+                    if let Some(warning) = info.expiry_warning(SystemTime::now()) {
+                        warn!(subject = %info.subject, "broker certificate {warning}");
+                    }
+                    if tls::offers_pin(&self.cfg.mqtt) {
+                        self.pending_pin = Some(info.clone());
+                    }
+                    self.broker_certificate = Some(info);
+                }
+                MqttEvent::Received(msg) => {
+                    if let Some(recorder) = self.recorder.as_mut()
+                        && let Err(err) = recorder.record(&msg)
+                    {
+                        warn!("capture write failed: {err:#}");
+                        self.status = format!("Recording stopped: {err:#}");
+                        self.recorder = None;
+                    }
+                    self.traffic.push(msg.clone());
+                    self.explorer.record(msg, Instant::now());
+                }
+                MqttEvent::Availability { device, online } => {
+                    self.device_availability.insert(device, online);
+                }
+                MqttEvent::Unavailable {
+                    topic,
+                    kind,
+                    device,
+                } => {
+                    let policy = self.cfg.source_policy(&kind);
+                    let stale_after = self.cfg.staleness.timeout(&device, &kind);
+                    self.devices.entry(device).or_default().mark_unavailable(
+                        &kind,
+                        &topic,
+                        policy,
+                        stale_after,
+                    );
                 }
+                MqttEvent::DecodeFailed { topic, reason } => {
+                    self.decode_failures += 1;
+                    self.last_decode_error = Some(format!("{topic}: {reason}"));
+                }
+                MqttEvent::Metric {
+                    topic,
+                    value,
+                    kind,
+                    unit,
+                    device,
+                } => {
+                    let policy = self.cfg.source_policy(&kind);
+                    let stale_after = self.cfg.staleness.timeout(&device, &kind);
+                    let unexpected = self.devices.entry(device.clone()).or_default().record(
+                        &kind,
+                        value,
+                        topic.clone(),
+                        unit,
+                        policy,
+                        stale_after,
+                    );
+                    if unexpected {
+                        let metric = metrics::canonical_id(&kind).to_string();
+                        warn!(%device, %metric, %topic, "unexpected second source for metric");
+                        self.status = format!(
+                            "{} on {device} has a second source: {topic}",
+                            Self::gauge_label(&metric)
+                        );
+                        self.source_conflicts.insert((device, metric), topic);
+                    }
+                }
+            }
+        }
+
+        // A listener that failed may still have been running when its
+        // Disconnected event arrived; don't leave it shown as reconnecting.
+        if self.mqtt_state == MqttState::Reconnecting
+            && self
+                .mqtt_handle
+                .as_ref()
+                .is_some_and(std::thread::JoinHandle::is_finished)
+        {
+            if let Some(handle) = self.mqtt_handle.take() {
+                let _ = handle.join();
+            }
+            self.mqtt_stop = None;
+            self.mqtt_state = MqttState::Stopped;
+        }
+    }
+
+    /// Device shown on the dashboard: the configured one once it has
+    /// reported, otherwise the first known device.
+    pub fn selected_device(&self) -> Option<&str> {
+        self.cfg
+            .dashboard
+            .device
+            .as_deref()
+            .filter(|device| self.devices.contains_key(*device))
+            .or_else(|| self.devices.keys().next().map(String::as_str))
+    }
+
+    /// Show `device` on the dashboard.
+    pub fn select_device(&mut self, device: &str) {
+        self.cfg.dashboard.device = Some(device.to_string());
+    }
+
+    /// Latest readings of the selected device.
+    pub fn metrics(&self) -> &MetricRegistry {
+        static NO_METRICS: LazyLock<MetricRegistry> = LazyLock::new(MetricRegistry::default);
+        self.selected_device()
+            .and_then(|device| self.devices.get(device))
+            .unwrap_or(&NO_METRICS)
+    }
+
+    /// Whether the selected device's reading for a metric is older than its
+    /// staleness timeout.
+    pub fn is_stale(&self, id: &str) -> bool {
+        let device = self.selected_device().unwrap_or_default();
+        let timeout = self.cfg.staleness.timeout(device, id);
+        self.metrics()
+            .reading(id)
+            .is_some_and(|r| r.timestamp.elapsed() > timeout)
+    }
+
+    /// Latest value of the selected device for a metric, unless it is stale.
+    pub fn fresh_value(&self, id: &str) -> Option<f64> {
+        self.metrics().value(id).filter(|_| !self.is_stale(id))
+    }
+
+    /// Metric ids of the selected device whose readings are stale.
+    pub fn stale_metrics(&self) -> Vec<&str> {
+        self.metrics()
+            .readings()
+            .map(|(id, _)| id)
+            .filter(|id| self.is_stale(id))
+            .collect()
+    }
+
+    /// Where the selected device's value for a metric comes from: the
+    /// topic, or a summary for aggregated metrics.
+    pub fn source_label(&self, id: &str) -> Option<String> {
+        let reading = self.metrics().reading(id)?;
+        match self.cfg.source_policy(id).map(|p| p.mode) {
+            Some(mode) if mode.is_aggregate() && reading.sources > 1 => {
+                Some(format!("{} of {} sources", mode.label(), reading.sources))
             }
+            _ => Some(reading.topic.clone()),
         }
     }
 
+    /// Replace the source policies; conflicts on metrics that now have a
+    /// policy count as acknowledged.
+    pub fn set_source_policies(&mut self, sources: Vec<config::SourcePolicy>) {
+        self.cfg.sources = sources;
+        let cfg = &self.cfg;
+        self.source_conflicts
+            .retain(|(_, metric), _| cfg.source_policy(metric).is_none());
+    }
+
+    /// Metrics of the selected device with an unexpected extra source.
+    pub fn conflicting_metrics(&self) -> Vec<&str> {
+        let Some(device) = self.selected_device() else {
+            return Vec::new();
+        };
+        self.source_conflicts
+            .keys()
+            .filter(|(d, _)| d == device)
+            .map(|(_, metric)| metric.as_str())
+            .collect()
+    }
+
+    /// Devices whose last availability message reported them offline.
+    pub fn offline_devices(&self) -> Vec<&str> {
+        self.device_availability
+            .iter()
+            .filter(|(_, online)| !**online)
+            .map(|(device, _)| device.as_str())
+            .collect()
+    }
+
     pub fn stop_mqtt(&mut self) {
         self.mqtt_state = MqttState::Stopping;
         if let Some(stop) = self.mqtt_stop.take() {
@@ -321,10 +592,65 @@ impl Air1App {
         self.status = "MQTT stopped".to_string();
     }
 
+    /// Pin the broker certificate (or its public key) offered on first use
+    /// and connect with the pin enforced.
+    pub fn pin_certificate(&mut self, kind: config::PinKind) {
+        let Some(info) = self.pending_pin.take() else {
+            return;
+        };
+        self.cfg.mqtt.pin = Some(config::CertPin {
+            kind,
+            sha256: info.fingerprint(kind),
+        });
+        self.save_all();
+        // Until now the listener only fetched the certificate.
+        if self.mqtt_state.is_running() {
+            self.stop_mqtt();
+        }
+        self.start_mqtt();
+        self.status = format!("Pinned broker {}", info.subject);
+    }
+
+    /// Reject the certificate offered on first use and stop listening.
+    pub fn reject_certificate(&mut self) {
+        if self.pending_pin.take().is_some() {
+            self.stop_mqtt();
+            self.status = "Broker certificate not trusted; MQTT stopped".to_string();
+        }
+    }
+
+    /// Expiry warnings for the CA and broker certificates in use.
+    pub fn certificate_warnings(&self) -> Vec<String> {
+        let now = SystemTime::now();
+        let ca = self.ca_certificates.iter().filter_map(|info| {
+            info.expiry_warning(now)
+                .map(|warning| format!("CA {}: {warning}", info.subject))
+        });
+        let broker = self.broker_certificate.iter().filter_map(|info| {
+            info.expiry_warning(now)
+                .map(|warning| format!("Broker certificate {}: {warning}", info.subject))
+        });
+        ca.chain(broker).collect()
+    }
+
+    /// Saved passphrase for the profile's client key, if it has one.
+    fn load_key_passphrase(cfg: &config::AppConfig) -> Option<String> {
+        if cfg.mqtt.client_key_path.is_none() || !cfg.mqtt.remember_password {
+            return None;
+        }
+        secrets::load_key_passphrase(&cfg.profile).unwrap_or_else(|err| {
+            warn!("keyring load error: {err:?}");
+            None
+        })
+    }
+
     pub fn forget_password(&mut self) {
-        match secrets::delete_password() {
+        let deleted = secrets::delete_password(&self.cfg.profile)
+            .and_then(|_| secrets::delete_key_passphrase(&self.cfg.profile));
+        match deleted {
             Ok(_) => {
                 self.password = None;
+                self.key_passphrase = None;
                 self.cfg.mqtt.remember_password = false;
                 self.status = "Removed saved password".to_string();
             }
@@ -343,16 +669,128 @@ impl Air1App {
     }
 
     pub fn gauge_label(id: &str) -> String {
-        match id {
-            "pm25" => "PM2.5".into(),
-            "pm10" => "PM10".into(),
-            "pm1" => "PM1".into(),
-            "co2" => "CO\u{2082}".into(),
-            "tvoc" => "TVOC".into(),
-            "temperature" => "Temperature".into(),
-            "humidity" => "Humidity".into(),
-            other => other.to_string(),
+        metrics::descriptor(id).map_or_else(|| id.to_string(), |m| m.label.to_string())
+    }
+
+    /// Switch to another broker profile: stop the listener, load the
+    /// profile's saved password and start listening on it.
+    pub fn switch_profile(&mut self, name: &str) {
+        if name == self.cfg.profile {
+            return;
+        }
+        if self.load_profile(name) && self.start_mqtt() {
+            self.status = format!("Switched to profile {name}");
+        }
+    }
+
+    /// Stop the listener and make `name` the active profile with its saved
+    /// credentials. Returns `false` for an unknown profile.
+    fn load_profile(&mut self, name: &str) -> bool {
+        if self.mqtt_state.is_running() {
+            self.stop_mqtt();
+        }
+        if !self.cfg.switch_profile(name) {
+            self.status = format!("Unknown profile: {name}");
+            return false;
+        }
+
+        // Readings of the previous broker do not apply to this one.
+        self.devices.clear();
+        self.source_conflicts.clear();
+        self.password = None;
+        if self.cfg.mqtt.remember_password && !self.keyring_unavailable {
+            match secrets::load_password(name) {
+                Ok(secret) => self.password = secret,
+                Err(err) => {
+                    warn!("keyring load error: {err:?}");
+                    self.status = format!("Keyring error: {err:#}");
+                }
+            }
+        }
+        self.key_passphrase = if self.keyring_unavailable {
+            None
+        } else {
+            Self::load_key_passphrase(&self.cfg)
+        };
+        if let Err(err) = config::save(&self.cfg_paths, &self.cfg) {
+            warn!("Settings save failed: {err:#}");
+        }
+        true
+    }
+
+    /// Create a profile from the current settings (without credentials)
+    /// and make it active; the listener is stopped until it is configured.
+    pub fn add_profile(&mut self, name: &str) {
+        if self.mqtt_state.is_running() {
+            self.stop_mqtt();
         }
+        if let Err(err) = self.cfg.add_profile(name) {
+            self.status = format!("{err:#}");
+            return;
+        }
+        self.devices.clear();
+        self.source_conflicts.clear();
+        self.password = None;
+        self.key_passphrase = None;
+        self.save_all();
+        self.status = format!("Created profile {}", self.cfg.profile);
+    }
+
+    /// Delete the active profile and its saved password, switching to the
+    /// first remaining profile. The listener is only restarted when it was
+    /// running.
+    pub fn delete_profile(&mut self) {
+        let current = self.cfg.profile.clone();
+        let Some(next) = self.cfg.profiles.first().map(|p| p.name.clone()) else {
+            self.status = "Cannot delete the only profile".to_string();
+            return;
+        };
+        if self.cfg.mqtt.remember_password
+            && let Err(err) = secrets::delete_password(&current)
+                .and_then(|_| secrets::delete_key_passphrase(&current))
+        {
+            warn!("keyring delete error: {err:?}");
+        }
+        let was_running = self.mqtt_state.is_running();
+        if !self.load_profile(&next) {
+            return;
+        }
+        self.cfg.remove_profile(&current);
+        if let Err(err) = config::save(&self.cfg_paths, &self.cfg) {
+            warn!("Settings save failed: {err:#}");
+        }
+        if was_running {
+            self.start_mqtt();
+        }
+        self.status = format!("Deleted profile {current}");
+    }
+
+    /// Restart the MQTT listener so it picks up changed settings.
+    /// Does nothing when the listener is not running.
+    pub fn restart_mqtt(&mut self) {
+        if self.mqtt_state.is_running() {
+            self.stop_mqtt();
+            // A replay restarts from the top with the new settings.
+            match self.replay.clone() {
+                Some((path, speed)) => {
+                    self.start_replay(&path, speed);
+                }
+                None => {
+                    self.start_mqtt();
+                }
+            }
+        }
+    }
+
+    /// Clear what the previous session reported.
+    fn reset_session(&mut self) {
+        self.connected = false;
+        self.decode_failures = 0;
+        self.last_decode_error = None;
+        self.device_availability.clear();
+        self.session_details.clear();
+        self.pending_pin = None;
+        self.broker_certificate = None;
     }
 
     /// Start the MQTT listener thread. Returns `false` if a password is required but missing.
@@ -363,33 +801,144 @@ impl Air1App {
         }
         let cfg = self.cfg.clone();
         let password = self.password.clone();
+        let key_passphrase = self.key_passphrase.clone();
         let tx = self.mqtt_tx.clone();
         let (stop_tx, stop_rx) = mpsc::channel();
         self.status = "Starting MQTT listener...".to_string();
         self.mqtt_state = MqttState::Starting;
-        self.connected = false;
+        self.replay = None;
+        self.reset_session();
+        self.ca_certificates = match self.cfg.mqtt.ca_path.as_deref() {
+            Some(path) if self.cfg.mqtt.transport().is_secure() => tls::read_certificates(path)
+                .unwrap_or_else(|err| {
+                    warn!("CA file error: {err:#}");
+                    Vec::new()
+                }),
+            _ => Vec::new(),
+        };
+        let handle = std::thread::spawn(move || {
+            let err_tx = tx.clone();
+            if let Err(err) = mqtt::run_listener(
+                cfg.mqtt,
+                cfg.mappings,
+                password.as_deref(),
+                key_passphrase.as_deref(),
+                tx,
+                stop_rx,
+            ) {
+                warn!("MQTT listener failed: {err:#}");
+                let _ = err_tx.send(MqttEvent::Disconnected(format!("{err:#}")));
+            }
+        });
+        self.mqtt_handle = Some(handle);
+        self.mqtt_stop = Some(stop_tx);
+        true
+    }
+
+    /// Replay a capture file through the mapper in place of the broker.
+    /// Returns `false` if the capture cannot be read.
+    pub fn start_replay(&mut self, path: &Path, speed: capture::ReplaySpeed) -> bool {
+        let capture = match capture::read_capture(path) {
+            Ok(capture) => capture,
+            Err(err) => {
+                self.status = format!("Replay failed: {err:#}");
+                return false;
+            }
+        };
+        if self.mqtt_state.is_running() {
+            self.stop_mqtt();
+        }
+        let cfg = self.cfg.clone();
+        let tx = self.mqtt_tx.clone();
+        let (stop_tx, stop_rx) = mpsc::channel();
+        self.status = format!("Replaying {}", path.display());
+        self.mqtt_state = MqttState::Starting;
+        self.replay = Some((path.to_path_buf(), speed));
+        self.reset_session();
+        self.ca_certificates = Vec::new();
         let handle = std::thread::spawn(move || {
-            let _ = mqtt::run_listener(cfg.mqtt, password.as_deref(), tx, stop_rx);
+            capture::run_replay(&cfg.mqtt, &cfg.mappings, capture, speed, tx, stop_rx);
         });
         self.mqtt_handle = Some(handle);
         self.mqtt_stop = Some(stop_tx);
         true
     }
 
-    /// Spawn an ephemeral connection-test thread.
+    /// Record every publish received from now on into `path`.
+    pub fn start_recording(&mut self, path: &Path) {
+        match capture::Recorder::create(path) {
+            Ok(recorder) => {
+                self.recorder = Some(recorder);
+                self.status = format!("Recording to {}", path.display());
+            }
+            Err(err) => self.status = format!("Recording failed: {err:#}"),
+        }
+    }
+
+    pub fn stop_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            self.status = format!("Recorded {} messages", recorder.messages);
+        }
+    }
+
+    /// Spawn an ephemeral connection-test thread that runs the diagnostics.
     pub fn spawn_test_connection(&mut self) {
         self.status = "Testing connection...".to_string();
         self.testing = true;
-        let cfg = self.cfg.clone();
+        let mut cfg = self.cfg.clone();
+        // The test connects with a clean session; under the listener's
+        // client id it would take over and wipe the listener's session.
+        let client_id = cfg.mqtt.client_id.as_deref().unwrap_or("air1-monitor");
+        cfg.mqtt.client_id = Some(format!("{client_id}-test"));
         let password = self.password.clone();
+        let key_passphrase = self.key_passphrase.clone();
         let tx = self.test_tx.clone();
         std::thread::spawn(move || {
-            let result = match mqtt::test_connection(&cfg.mqtt, password.as_deref()) {
-                Ok(_) => TestResult::Ok,
-                Err(err) => TestResult::Err(format!("{err:#}")),
-            };
-            let _ = tx.send(result);
+            let report = diagnostics::run(
+                &cfg.mqtt,
+                &cfg.mappings,
+                password.as_deref(),
+                key_passphrase.as_deref(),
+            );
+            let _ = tx.send(report);
+        });
+    }
+}
+
+impl Air1App {
+    /// Listen on `filter` for `duration` with the current broker settings
+    /// and collect the numeric topics seen. Dropping the returned sender
+    /// stops the scan early.
+    pub fn spawn_topic_scan(
+        &self,
+        filter: String,
+        duration: Duration,
+    ) -> (
+        mpsc::Receiver<anyhow::Result<wizard::TopicScan>>,
+        mpsc::Sender<()>,
+    ) {
+        let mut cfg = self.cfg.mqtt.clone();
+        // A separate client id keeps the broker from dropping the listener.
+        let client_id = cfg.client_id.as_deref().unwrap_or("air1-monitor");
+        cfg.client_id = Some(format!("{client_id}-scan"));
+        let password = self.password.clone();
+        let key_passphrase = self.key_passphrase.clone();
+        let (tx, rx) = mpsc::channel();
+        let (stop_tx, stop_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut scan = wizard::TopicScan::default();
+            let result = mqtt::scan_topics(
+                &cfg,
+                password.as_deref(),
+                key_passphrase.as_deref(),
+                &filter,
+                duration,
+                &stop_rx,
+                |topic, payload| scan.record(topic, payload),
+            );
+            let _ = tx.send(result.map(|_| scan));
         });
+        (rx, stop_tx)
     }
 }
 
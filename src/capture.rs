@@ -0,0 +1,318 @@
+use std::{
+    fmt, fs,
+    io::{BufWriter, Write as _},
+    path::Path,
+    str::FromStr,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::app::MqttEvent;
+use crate::config::{MappingRule, MqttConfig};
+use crate::inspector;
+use crate::mqtt::{Received, TopicMapper};
+
+/// Disconnect reason sent when a replay reaches the end of its capture.
+pub const REPLAY_FINISHED: &str = "replay finished";
+
+/// How fast a capture is replayed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// Original message spacing divided by the factor; 1.0 is real time.
+    Scaled(f64),
+    /// No waiting between messages.
+    Max,
+}
+
+impl ReplaySpeed {
+    pub fn label(self) -> String {
+        match self {
+            ReplaySpeed::Scaled(factor) => format!("{factor}x"),
+            ReplaySpeed::Max => "maximum speed".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ReplaySpeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.label())
+    }
+}
+
+impl FromStr for ReplaySpeed {
+    type Err = anyhow::Error;
+
+    /// Parse `max`, or a factor such as `1`, `2.5` or `10x`.
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("max") {
+            return Ok(ReplaySpeed::Max);
+        }
+        let factor: f64 = s
+            .trim_end_matches(['x', 'X'])
+            .parse()
+            .with_context(|| format!("invalid replay speed {s}; use a factor or max"))?;
+        if !(factor.is_finite() && factor > 0.0) {
+            anyhow::bail!("replay speed must be positive");
+        }
+        Ok(ReplaySpeed::Scaled(factor))
+    }
+}
+
+/// Appends every received publish to a capture file, one JSON object per
+/// line in the traffic inspector's export format.
+pub struct Recorder {
+    out: BufWriter<fs::File>,
+    /// Messages written so far.
+    pub messages: u64,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = fs::File::create(path)
+            .with_context(|| format!("failed to create capture {}", path.display()))?;
+        Ok(Self {
+            out: BufWriter::new(file),
+            messages: 0,
+        })
+    }
+
+    /// Write one publish; flushed right away so a crash keeps the capture.
+    pub fn record(&mut self, msg: &Received) -> Result<()> {
+        serde_json::to_writer(&mut self.out, &inspector::to_json(msg))?;
+        self.out.write_all(b"\n")?;
+        self.out.flush().context("failed to write capture")?;
+        self.messages += 1;
+        Ok(())
+    }
+}
+
+/// A publish read back from a capture.
+#[derive(Debug, Clone)]
+pub struct Captured {
+    /// Time the publish was received, since the Unix epoch.
+    pub at: Duration,
+    pub publish: rumqttc::Publish,
+}
+
+#[derive(Deserialize)]
+struct CaptureLine {
+    unix_ms: u64,
+    topic: String,
+    #[serde(default)]
+    payload: Option<String>,
+    #[serde(default)]
+    payload_hex: Option<String>,
+    #[serde(default)]
+    retain: bool,
+    #[serde(default)]
+    qos: u8,
+}
+
+/// Read a capture written by the recorder or exported from the traffic
+/// inspector.
+pub fn read_capture(path: &Path) -> Result<Vec<Captured>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("failed to read capture {}", path.display()))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(idx, line)| {
+            parse_line(line).with_context(|| format!("{}:{}", path.display(), idx + 1))
+        })
+        .collect()
+}
+
+fn parse_line(line: &str) -> Result<Captured> {
+    let line: CaptureLine = serde_json::from_str(line)?;
+    let payload = match (line.payload, line.payload_hex) {
+        (Some(text), _) => text.into_bytes(),
+        (None, Some(hex)) => parse_hex(&hex)?,
+        (None, None) => Vec::new(),
+    };
+    let qos = match line.qos {
+        0 => rumqttc::QoS::AtMostOnce,
+        1 => rumqttc::QoS::AtLeastOnce,
+        2 => rumqttc::QoS::ExactlyOnce,
+        other => anyhow::bail!("invalid qos {other}"),
+    };
+    let mut publish = rumqttc::Publish::new(line.topic, qos, payload);
+    publish.retain = line.retain;
+    Ok(Captured {
+        at: Duration::from_millis(line.unix_ms),
+        publish,
+    })
+}
+
+fn parse_hex(hex: &str) -> Result<Vec<u8>> {
+    let hex: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if let Some(bad) = hex.iter().find(|b| !b.is_ascii_hexdigit()) {
+        anyhow::bail!("invalid hex in payload_hex: {}", bad.escape_ascii());
+    }
+    if !hex.len().is_multiple_of(2) {
+        anyhow::bail!("odd number of hex digits in payload_hex");
+    }
+    Ok(hex
+        .chunks(2)
+        .map(|pair| (hex_value(pair[0]) << 4) | hex_value(pair[1]))
+        .collect())
+}
+
+/// Value of an ASCII hex digit.
+fn hex_value(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        b'a'..=b'f' => digit - b'a' + 10,
+        _ => digit - b'A' + 10,
+    }
+}
+
+/// Feed a capture through the topic mapper as if a listener received it,
+/// keeping the original spacing scaled by `speed`. Sends the same events as
+/// [`crate::mqtt::run_listener`], then a [`REPLAY_FINISHED`] disconnect;
+/// a stop signal ends it early.
+pub fn run_replay(
+    cfg: &MqttConfig,
+    mappings: &[MappingRule],
+    capture: Vec<Captured>,
+    speed: ReplaySpeed,
+    tx: mpsc::Sender<MqttEvent>,
+    stop_rx: mpsc::Receiver<()>,
+) {
+    let mut mapper = TopicMapper::new(cfg, mappings);
+    let total = capture.len();
+    let _ = tx.send(MqttEvent::Status(format!(
+        "Replaying {total} messages at {speed}"
+    )));
+    let _ = tx.send(MqttEvent::Connected);
+
+    let started = Instant::now();
+    let first = capture.first().map(|c| c.at).unwrap_or_default();
+    for (idx, captured) in capture.into_iter().enumerate() {
+        let due = match speed {
+            ReplaySpeed::Scaled(factor) => {
+                started + captured.at.saturating_sub(first).div_f64(factor)
+            }
+            ReplaySpeed::Max => started,
+        };
+        let wait = due.saturating_duration_since(Instant::now());
+        let stop = match stop_rx.recv_timeout(wait) {
+            Ok(()) | Err(mpsc::RecvTimeoutError::Disconnected) => true,
+            Err(mpsc::RecvTimeoutError::Timeout) => false,
+        };
+        if stop {
+            let _ = tx.send(MqttEvent::Status(format!(
+                "Replay stopped after {idx} of {total} messages"
+            )));
+            let _ = tx.send(MqttEvent::Disconnected("stopped".to_string()));
+            return;
+        }
+
+        let (route, events) = mapper.route_publish(&captured.publish);
+        let received = Received::new(&captured.publish, route, &events);
+        let _ = tx.send(MqttEvent::Received(received));
+        for evt in events {
+            let _ = tx.send(evt);
+        }
+    }
+    let _ = tx.send(MqttEvent::Disconnected(REPLAY_FINISHED.to_string()));
+    let _ = tx.send(MqttEvent::Status(format!(
+        "Replay finished: {total} messages"
+    )));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mqtt::MapRoute;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn recorded_captures_read_back() {
+        let path = std::env::temp_dir().join(format!("air1-capture-{}.jsonl", std::process::id()));
+        let mut recorder = Recorder::create(&path).unwrap();
+        for (payload, retain) in [(b"612".to_vec(), true), (vec![0xff, 0x00], false)] {
+            recorder
+                .record(&Received {
+                    topic: "lab/co2".to_string(),
+                    payload,
+                    retain,
+                    qos: 1,
+                    received: UNIX_EPOCH + Duration::from_millis(1_000),
+                    route: MapRoute::Unmapped,
+                    outcome: Vec::new(),
+                    decode_failed: false,
+                })
+                .unwrap();
+        }
+        drop(recorder);
+        let capture = read_capture(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(capture.len(), 2);
+        assert_eq!(capture[0].at, Duration::from_secs(1));
+        assert_eq!(capture[0].publish.topic, "lab/co2");
+        assert_eq!(&capture[0].publish.payload[..], b"612");
+        assert!(capture[0].publish.retain);
+        assert_eq!(capture[0].publish.qos, rumqttc::QoS::AtLeastOnce);
+        assert_eq!(&capture[1].publish.payload[..], &[0xff, 0x00]);
+    }
+
+    #[test]
+    fn bad_lines_and_speeds_are_rejected() {
+        assert!(parse_line(r#"{"unix_ms": 5, "topic": "a", "payload_hex": "0g"}"#).is_err());
+        assert!(parse_line(r#"{"unix_ms": 5, "topic": "a", "payload_hex": "aé1"}"#).is_err());
+        assert!(parse_line(r#"{"unix_ms": 5, "topic": "a", "payload_hex": "+f"}"#).is_err());
+        let spaced = parse_line(r#"{"unix_ms": 5, "topic": "a", "payload_hex": "0A ff"}"#);
+        assert_eq!(&spaced.unwrap().publish.payload[..], &[0x0a, 0xff]);
+        assert!(parse_line(r#"{"topic": "a"}"#).is_err());
+        assert_eq!("max".parse::<ReplaySpeed>().unwrap(), ReplaySpeed::Max);
+        assert_eq!(
+            "10x".parse::<ReplaySpeed>().unwrap(),
+            ReplaySpeed::Scaled(10.0)
+        );
+        assert!("0".parse::<ReplaySpeed>().is_err());
+    }
+
+    #[test]
+    fn replay_keeps_the_original_spacing_scaled() {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let capture: Vec<Captured> = [0, 400]
+            .into_iter()
+            .map(|offset| Captured {
+                at: now + Duration::from_millis(offset),
+                publish: rumqttc::Publish::new(
+                    "air1/sensor/co2/state",
+                    rumqttc::QoS::AtMostOnce,
+                    "612",
+                ),
+            })
+            .collect();
+        let (tx, rx) = mpsc::channel();
+        let (_stop_tx, stop_rx) = mpsc::channel();
+        let started = Instant::now();
+        run_replay(
+            &MqttConfig::default(),
+            &[],
+            capture,
+            ReplaySpeed::Scaled(4.0),
+            tx,
+            stop_rx,
+        );
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(100), "{elapsed:?}");
+
+        let events: Vec<MqttEvent> = rx.try_iter().collect();
+        let metrics = events
+            .iter()
+            .filter(|evt| matches!(evt, MqttEvent::Metric { kind, .. } if kind == "co2"))
+            .count();
+        assert_eq!(metrics, 2);
+        assert!(events.iter().any(
+            |evt| matches!(evt, MqttEvent::Disconnected(reason) if reason == REPLAY_FINISHED)
+        ));
+    }
+}
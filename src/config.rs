@@ -1,4 +1,10 @@
-use std::{collections::HashSet, fs, io::Write, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    io::Write,
+    path::PathBuf,
+    time::Duration,
+};
 
 use anyhow::{Context, Result};
 use directories::ProjectDirs;
@@ -6,16 +12,38 @@ use serde::{Deserialize, Serialize};
 use tracing::warn;
 
 /// MQTT connection settings persisted to the config file.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct MqttConfig {
     /// MQTT broker hostname or IP address.
     pub host: String,
     /// MQTT broker port.
     pub port: u16,
-    /// Enable TLS for broker connection.
+    /// Enable TLS for broker connection; superseded by `transport` when set.
     pub tls: bool,
+    /// Broker transport; defaults to TCP or TLS according to `tls`.
+    #[serde(default)]
+    pub transport: Option<MqttTransport>,
+    /// URL path for WebSocket transports (defaults to "/mqtt").
+    #[serde(default)]
+    pub ws_path: Option<String>,
+    /// Extra HTTP headers sent with the WebSocket upgrade request.
+    #[serde(default)]
+    pub ws_headers: BTreeMap<String, String>,
     /// Optional CA certificate path for TLS verification.
     pub ca_path: Option<PathBuf>,
+    /// Optional client certificate chain (PEM) for mutual TLS.
+    #[serde(default)]
+    pub client_cert_path: Option<PathBuf>,
+    /// Private key for the client certificate: PEM (PKCS#8, PKCS#1 or SEC1,
+    /// optionally encrypted PKCS#8) or DER PKCS#8.
+    #[serde(default)]
+    pub client_key_path: Option<PathBuf>,
+    /// Pinned broker certificate or public key; replaces CA verification.
+    #[serde(default)]
+    pub pin: Option<CertPin>,
+    /// Accept an unverified broker certificate until one is pinned.
+    #[serde(default)]
+    pub trust_on_first_use: bool,
     /// Optional MQTT client ID.
     pub client_id: Option<String>,
     /// Optional MQTT username.
@@ -28,6 +56,278 @@ pub struct MqttConfig {
     pub keepalive_secs: u16,
     /// Store password in the system keyring if available.
     pub remember_password: bool,
+    /// Consume Home Assistant MQTT discovery configs to map sensors.
+    #[serde(default = "default_true")]
+    pub discovery: bool,
+    /// Home Assistant discovery prefix (defaults to "homeassistant").
+    #[serde(default)]
+    pub discovery_prefix: Option<String>,
+    /// Topic layout adapters, each reading its own base topic.
+    #[serde(default)]
+    pub adapters: Vec<AdapterConfig>,
+    /// Explicit topic filters; replace the `<prefix>/#` subscription.
+    #[serde(default)]
+    pub filters: Vec<TopicFilter>,
+    /// MQTT protocol version spoken to the broker.
+    #[serde(default)]
+    pub protocol: ProtocolVersion,
+    /// MQTT 5 session expiry interval in seconds; unset ends the session on disconnect.
+    #[serde(default)]
+    pub session_expiry_secs: Option<u32>,
+    /// MQTT 5 receive maximum (in-flight QoS 1/2 messages the broker may send).
+    #[serde(default)]
+    pub receive_maximum: Option<u16>,
+    /// MQTT 5 user properties sent with CONNECT.
+    #[serde(default)]
+    pub user_properties: BTreeMap<String, String>,
+}
+
+/// A pinned broker certificate or public key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CertPin {
+    /// What the fingerprint covers.
+    #[serde(default)]
+    pub kind: PinKind,
+    /// SHA-256 fingerprint in hex; colons are optional.
+    pub sha256: String,
+}
+
+impl CertPin {
+    /// Decode the fingerprint into its 32 bytes.
+    pub fn digest(&self) -> Result<[u8; 32]> {
+        let hex: String = self
+            .sha256
+            .chars()
+            .filter(|c| *c != ':' && !c.is_whitespace())
+            .collect();
+        if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            anyhow::bail!(
+                "pinned fingerprint {} must be 64 hex digits (SHA-256)",
+                self.sha256
+            );
+        }
+        let mut digest = [0u8; 32];
+        for (idx, byte) in digest.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[idx * 2..idx * 2 + 2], 16)?;
+        }
+        Ok(digest)
+    }
+}
+
+/// Part of the broker certificate a pin covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PinKind {
+    /// The whole certificate; changes on every renewal.
+    #[default]
+    Certificate,
+    /// The subject public key info; survives renewals that keep the key.
+    Spki,
+}
+
+/// Transport used to reach the broker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MqttTransport {
+    /// Plain TCP.
+    #[default]
+    Tcp,
+    /// TLS over TCP.
+    Tls,
+    /// WebSocket.
+    Ws,
+    /// WebSocket over TLS.
+    Wss,
+}
+
+impl MqttTransport {
+    /// All transports in display order.
+    pub const ALL: [MqttTransport; 4] = [
+        MqttTransport::Tcp,
+        MqttTransport::Tls,
+        MqttTransport::Ws,
+        MqttTransport::Wss,
+    ];
+
+    /// Human-readable label for the transport.
+    pub fn label(self) -> &'static str {
+        match self {
+            MqttTransport::Tcp => "TCP",
+            MqttTransport::Tls => "TLS",
+            MqttTransport::Ws => "WebSocket",
+            MqttTransport::Wss => "WebSocket (TLS)",
+        }
+    }
+
+    /// Whether the transport is encrypted with TLS.
+    pub fn is_secure(self) -> bool {
+        matches!(self, MqttTransport::Tls | MqttTransport::Wss)
+    }
+
+    /// Whether the transport tunnels MQTT through WebSockets.
+    pub fn is_websocket(self) -> bool {
+        matches!(self, MqttTransport::Ws | MqttTransport::Wss)
+    }
+}
+
+impl MqttConfig {
+    /// Effective transport, falling back to the legacy `tls` switch.
+    pub fn transport(&self) -> MqttTransport {
+        self.transport.unwrap_or(if self.tls {
+            MqttTransport::Tls
+        } else {
+            MqttTransport::Tcp
+        })
+    }
+}
+
+/// MQTT protocol version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ProtocolVersion {
+    /// MQTT 3.1.1.
+    #[default]
+    #[serde(rename = "3.1.1")]
+    V311,
+    /// MQTT 5.0.
+    #[serde(rename = "5")]
+    V5,
+}
+
+impl ProtocolVersion {
+    /// All protocol versions in display order.
+    pub const ALL: [ProtocolVersion; 2] = [ProtocolVersion::V311, ProtocolVersion::V5];
+
+    /// Human-readable label for the protocol version.
+    pub fn label(self) -> &'static str {
+        match self {
+            ProtocolVersion::V311 => "MQTT 3.1.1",
+            ProtocolVersion::V5 => "MQTT 5",
+        }
+    }
+}
+
+/// A topic filter to subscribe to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TopicFilter {
+    /// MQTT topic filter, may contain `+`/`#` wildcards.
+    pub filter: String,
+    /// QoS for this filter (0-2); defaults to the connection QoS.
+    #[serde(default)]
+    pub qos: Option<u8>,
+}
+
+impl TopicFilter {
+    /// Check that the filter is a well-formed MQTT topic filter.
+    pub fn validate(&self) -> Result<()> {
+        let filter = self.filter.trim();
+        if filter.is_empty() {
+            anyhow::bail!("topic filter must be non-empty");
+        }
+        if !has_valid_wildcards(filter) {
+            anyhow::bail!("invalid wildcard in topic filter {filter}");
+        }
+        if self.qos.is_some_and(|qos| qos > 2) {
+            anyhow::bail!("QoS for topic filter {filter} must be between 0 and 2");
+        }
+        Ok(())
+    }
+}
+
+/// `#` may only be the last level; wildcards must fill a whole level.
+fn has_valid_wildcards(filter: &str) -> bool {
+    let levels: Vec<&str> = filter.split('/').collect();
+    levels.iter().enumerate().all(|(idx, level)| {
+        let bad_hash = level.contains('#') && (*level != "#" || idx + 1 != levels.len());
+        let bad_plus = level.contains('+') && *level != "+";
+        !(bad_hash || bad_plus)
+    })
+}
+
+/// Built-in topic layout adapters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TopicAdapter {
+    /// Discovery, ESPHome layout and sensor-name guessing.
+    #[default]
+    Auto,
+    /// Home Assistant `mqtt_statestream` layout under `<base>`.
+    Statestream,
+    /// Zigbee2MQTT device JSON on `<base>/<friendly_name>`.
+    Zigbee2mqtt,
+    /// Tasmota telemetry JSON on `<base>/<device>/SENSOR`.
+    Tasmota,
+}
+
+impl TopicAdapter {
+    /// All adapters in display order.
+    pub const ALL: [TopicAdapter; 4] = [
+        TopicAdapter::Auto,
+        TopicAdapter::Statestream,
+        TopicAdapter::Zigbee2mqtt,
+        TopicAdapter::Tasmota,
+    ];
+
+    /// Human-readable label for the adapter.
+    pub fn label(self) -> &'static str {
+        match self {
+            TopicAdapter::Auto => "Automatic",
+            TopicAdapter::Statestream => "HA statestream",
+            TopicAdapter::Zigbee2mqtt => "Zigbee2MQTT",
+            TopicAdapter::Tasmota => "Tasmota",
+        }
+    }
+
+    /// Base topic used when the adapter sets none.
+    pub fn default_base(self) -> &'static str {
+        match self {
+            TopicAdapter::Auto | TopicAdapter::Statestream => "homeassistant",
+            TopicAdapter::Zigbee2mqtt => "zigbee2mqtt",
+            TopicAdapter::Tasmota => "tele",
+        }
+    }
+
+    pub fn is_auto(&self) -> bool {
+        *self == TopicAdapter::Auto
+    }
+}
+
+/// A layout adapter and the base topic it reads.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdapterConfig {
+    /// Topic layout under the base.
+    pub kind: TopicAdapter,
+    /// Base topic; the layout's default base when unset.
+    #[serde(default)]
+    pub base: Option<String>,
+}
+
+impl AdapterConfig {
+    /// Base topic the adapter reads.
+    pub fn base(&self) -> &str {
+        self.base
+            .as_deref()
+            .map(str::trim)
+            .filter(|base| !base.is_empty())
+            .unwrap_or(self.kind.default_base())
+    }
+
+    /// Check that the adapter names a layout and a wildcard-free base.
+    pub fn validate(&self) -> Result<()> {
+        if self.kind.is_auto() {
+            anyhow::bail!("topic layout adapter needs a layout");
+        }
+        if self.base().trim_end_matches("/#").contains(['+', '#']) {
+            anyhow::bail!(
+                "adapter base topic {} must not contain wildcards",
+                self.base()
+            );
+        }
+        Ok(())
+    }
+}
+
+fn default_true() -> bool {
+    true
 }
 
 impl Default for MqttConfig {
@@ -36,13 +336,28 @@ impl Default for MqttConfig {
             host: "localhost".to_string(),
             port: 1883,
             tls: false,
+            transport: None,
+            ws_path: None,
+            ws_headers: BTreeMap::new(),
             ca_path: None,
+            client_cert_path: None,
+            client_key_path: None,
+            pin: None,
+            trust_on_first_use: false,
             client_id: Some("air1-monitor".to_string()),
             username: None,
             topic_prefix: None,
             qos: 0,
             keepalive_secs: 30,
             remember_password: false,
+            discovery: true,
+            discovery_prefix: None,
+            adapters: Vec::new(),
+            filters: Vec::new(),
+            protocol: ProtocolVersion::V311,
+            session_expiry_secs: None,
+            receive_maximum: None,
+            user_properties: BTreeMap::new(),
         }
     }
 }
@@ -89,22 +404,13 @@ impl DashboardSectionConfig {
     }
 
     fn default_gauges_for(section_id: &str) -> Vec<GaugeConfig> {
-        match section_id {
-            "air_quality" => vec![
-                GaugeConfig::new("pm25"),
-                GaugeConfig::new("pm10"),
-                GaugeConfig::new("pm1"),
-            ],
-            "gas" => vec![GaugeConfig::new("co2"), GaugeConfig::new("tvoc")],
-            "environment" => vec![
-                GaugeConfig::new("temperature"),
-                GaugeConfig::new("humidity"),
-            ],
-            _ => vec![],
-        }
+        crate::metrics::section_metrics(section_id)
+            .map(|m| GaugeConfig::new(m.id))
+            .collect()
     }
 
-    /// Normalize gauges by filling defaults and removing duplicates.
+    /// Normalize gauges by canonicalizing metric ids, filling defaults and
+    /// removing duplicates.
     pub fn normalize(&mut self) {
         let defaults = Self::default_gauges_for(&self.id);
         if self.gauges.is_empty() {
@@ -112,6 +418,9 @@ impl DashboardSectionConfig {
             return;
         }
 
+        for gauge in &mut self.gauges {
+            gauge.id = crate::metrics::canonical_id(&gauge.id).to_string();
+        }
         let mut seen: HashSet<String> = HashSet::new();
         self.gauges.retain(|gauge| seen.insert(gauge.id.clone()));
 
@@ -128,6 +437,9 @@ impl DashboardSectionConfig {
 pub struct DashboardConfig {
     /// Ordered list of dashboard sections.
     pub sections: Vec<DashboardSectionConfig>,
+    /// Device shown on the dashboard; the first device seen when unset.
+    #[serde(default)]
+    pub device: Option<String>,
 }
 
 impl DashboardConfig {
@@ -162,7 +474,9 @@ impl DashboardConfig {
         vec![
             DashboardSectionConfig::new("overview"),
             DashboardSectionConfig::new("air_quality"),
+            DashboardSectionConfig::new("particle_counts"),
             DashboardSectionConfig::new("gas"),
+            DashboardSectionConfig::new("other_gases"),
             DashboardSectionConfig::new("environment"),
         ]
     }
@@ -172,18 +486,395 @@ impl Default for DashboardConfig {
     fn default() -> Self {
         Self {
             sections: Self::default_sections(),
+            device: None,
+        }
+    }
+}
+
+/// How a mapping rule's `topic` is matched against incoming topics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TopicMatch {
+    /// Topic must equal the pattern exactly.
+    #[default]
+    Exact,
+    /// Pattern is an MQTT filter with `+`/`#` wildcards.
+    Wildcard,
+    /// Pattern is a regular expression matched against the full topic.
+    Regex,
+}
+
+impl TopicMatch {
+    /// All match types in display order.
+    pub const ALL: [TopicMatch; 3] = [TopicMatch::Exact, TopicMatch::Wildcard, TopicMatch::Regex];
+
+    /// Human-readable label for the match type.
+    pub fn label(self) -> &'static str {
+        match self {
+            TopicMatch::Exact => "Exact",
+            TopicMatch::Wildcard => "Wildcard",
+            TopicMatch::Regex => "Regex",
+        }
+    }
+}
+
+/// User-defined rule binding MQTT topics to a metric.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MappingRule {
+    /// Topic, MQTT filter or regular expression, depending on `match_type`.
+    pub topic: String,
+    /// How `topic` is matched.
+    #[serde(rename = "match", default)]
+    pub match_type: TopicMatch,
+    /// Metric identifier the topic feeds (e.g. "pm25").
+    pub metric: String,
+    /// JSON pointer or JSONPath selecting the value in JSON payloads.
+    /// Several rules on the same topic extract several metrics.
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Factor applied to the parsed value.
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    /// Unit of the scaled values, overriding the payload's; converted to the
+    /// dashboard unit when the metric has one.
+    #[serde(default)]
+    pub unit: Option<String>,
+    /// Device the readings belong to. Regex rules may reference capture
+    /// groups (`$1`, `${name}`); defaults to the device derived from the topic.
+    #[serde(default)]
+    pub device: Option<String>,
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+impl MappingRule {
+    /// Check that the rule can be compiled and applied.
+    pub fn validate(&self) -> Result<()> {
+        if self.topic.trim().is_empty() {
+            anyhow::bail!("mapping rule topic must be non-empty");
+        }
+        if self.metric.trim().is_empty() {
+            anyhow::bail!("mapping rule for {} needs a metric id", self.topic);
+        }
+        if !self.scale.is_finite() {
+            anyhow::bail!("mapping rule for {} has an invalid scale", self.topic);
+        }
+        if let Some(path) = &self.path
+            && !crate::decode::is_valid_path(path)
+        {
+            anyhow::bail!(
+                "invalid JSON path in mapping rule for {}: {path}",
+                self.topic
+            );
+        }
+        match self.match_type {
+            TopicMatch::Regex => {
+                regex::Regex::new(&self.topic)
+                    .with_context(|| format!("invalid regex in mapping rule: {}", self.topic))?;
+            }
+            TopicMatch::Wildcard if !has_valid_wildcards(self.topic.trim()) => {
+                anyhow::bail!("invalid wildcard in mapping rule topic {}", self.topic);
+            }
+            TopicMatch::Exact if self.topic.contains(['+', '#']) => {
+                anyhow::bail!(
+                    "exact mapping rule topic {} contains a wildcard; use wildcard matching",
+                    self.topic
+                );
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// How a metric fed by several topics picks the value it shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceMode {
+    /// The most recent message wins, whichever topic it came from.
+    #[default]
+    Latest,
+    /// The policy's `topic` wins once it has reported; other topics are
+    /// used only until then.
+    Preferred,
+    /// The first topic seen is locked in until the app restarts.
+    First,
+    /// Mean of the latest value from every source.
+    Mean,
+    /// Minimum of the latest value from every source.
+    Min,
+    /// Maximum of the latest value from every source.
+    Max,
+}
+
+impl SourceMode {
+    /// All modes in display order.
+    pub const ALL: [SourceMode; 6] = [
+        SourceMode::Latest,
+        SourceMode::Preferred,
+        SourceMode::First,
+        SourceMode::Mean,
+        SourceMode::Min,
+        SourceMode::Max,
+    ];
+
+    /// Human-readable label for the mode.
+    pub fn label(self) -> &'static str {
+        match self {
+            SourceMode::Latest => "Latest",
+            SourceMode::Preferred => "Preferred topic",
+            SourceMode::First => "First seen",
+            SourceMode::Mean => "Mean",
+            SourceMode::Min => "Minimum",
+            SourceMode::Max => "Maximum",
+        }
+    }
+
+    /// Whether the mode combines all sources, so several are expected.
+    pub fn is_aggregate(self) -> bool {
+        matches!(self, SourceMode::Mean | SourceMode::Min | SourceMode::Max)
+    }
+}
+
+/// Source policy for one metric.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourcePolicy {
+    /// Metric identifier the policy applies to (e.g. "temperature").
+    pub metric: String,
+    /// How readings from different topics are combined.
+    #[serde(default)]
+    pub mode: SourceMode,
+    /// Preferred topic for [`SourceMode::Preferred`].
+    #[serde(default)]
+    pub topic: Option<String>,
+}
+
+impl SourcePolicy {
+    /// Check that the policy can be applied.
+    pub fn validate(&self) -> Result<()> {
+        if self.metric.trim().is_empty() {
+            anyhow::bail!("source policy needs a metric id");
         }
+        if self.mode == SourceMode::Preferred
+            && self.topic.as_deref().is_none_or(|t| t.trim().is_empty())
+        {
+            anyhow::bail!("preferred source policy for {} needs a topic", self.metric);
+        }
+        Ok(())
     }
 }
 
+/// Timeouts after which readings count as stale.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StalenessConfig {
+    /// Timeout in seconds for metrics without a more specific entry.
+    #[serde(default = "default_stale_secs")]
+    pub default_secs: u64,
+    /// Per-metric timeouts in seconds, keyed by metric id.
+    #[serde(default)]
+    pub metrics: BTreeMap<String, u64>,
+    /// Per-device timeouts in seconds, keyed by device name.
+    #[serde(default)]
+    pub devices: BTreeMap<String, u64>,
+}
+
+fn default_stale_secs() -> u64 {
+    60
+}
+
+impl Default for StalenessConfig {
+    fn default() -> Self {
+        Self {
+            default_secs: default_stale_secs(),
+            metrics: BTreeMap::new(),
+            devices: BTreeMap::new(),
+        }
+    }
+}
+
+impl StalenessConfig {
+    /// Timeout for a metric of a device; metric entries win over device
+    /// entries, which win over the default.
+    pub fn timeout(&self, device: &str, metric: &str) -> Duration {
+        let id = crate::metrics::canonical_id(metric);
+        let secs = self
+            .metrics
+            .iter()
+            .find(|(m, _)| crate::metrics::canonical_id(m) == id)
+            .map(|(_, secs)| *secs)
+            .or_else(|| self.devices.get(device).copied())
+            .unwrap_or(self.default_secs);
+        Duration::from_secs(secs)
+    }
+
+    /// Check that all timeouts are positive.
+    pub fn validate(&self) -> Result<()> {
+        if self.default_secs == 0 {
+            anyhow::bail!("staleness timeout must be greater than 0 seconds");
+        }
+        if let Some((name, _)) = self
+            .metrics
+            .iter()
+            .chain(&self.devices)
+            .find(|(_, secs)| **secs == 0)
+        {
+            anyhow::bail!("staleness timeout for {name} must be greater than 0 seconds");
+        }
+        Ok(())
+    }
+}
+
+/// Name of the profile used by configs written before profiles existed.
+pub const DEFAULT_PROFILE: &str = "default";
+
+fn default_profile() -> String {
+    DEFAULT_PROFILE.to_string()
+}
+
+/// A stored broker profile: connection, topic settings, mapping rules and
+/// dashboard layout.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    /// Unique profile name.
+    pub name: String,
+    /// MQTT settings of the profile.
+    pub mqtt: MqttConfig,
+    /// Dashboard layout of the profile.
+    #[serde(default)]
+    pub dashboard: DashboardConfig,
+    /// Topic-to-metric mapping rules of the profile.
+    #[serde(default, rename = "mapping")]
+    pub mappings: Vec<MappingRule>,
+    /// Source policies of the profile.
+    #[serde(default, rename = "source")]
+    pub sources: Vec<SourcePolicy>,
+    /// Staleness timeouts of the profile.
+    #[serde(default)]
+    pub staleness: StalenessConfig,
+}
+
 /// Root application configuration persisted to disk.
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+///
+/// `mqtt`, `dashboard`, `mappings`, `sources` and `staleness` belong to the
+/// active profile; the other profiles are kept in `profiles` until switched
+/// to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppConfig {
+    /// Name of the active profile.
+    #[serde(default = "default_profile")]
+    pub profile: String,
     /// MQTT configuration block.
     pub mqtt: MqttConfig,
     /// Dashboard layout configuration.
     #[serde(default)]
     pub dashboard: DashboardConfig,
+    /// Topic-to-metric mapping rules, checked before built-in guessing.
+    #[serde(default, rename = "mapping")]
+    pub mappings: Vec<MappingRule>,
+    /// Per-metric policies for metrics fed by more than one topic.
+    #[serde(default, rename = "source")]
+    pub sources: Vec<SourcePolicy>,
+    /// Staleness timeouts for readings.
+    #[serde(default)]
+    pub staleness: StalenessConfig,
+    /// Inactive broker profiles.
+    #[serde(default)]
+    pub profiles: Vec<Profile>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            profile: default_profile(),
+            mqtt: MqttConfig::default(),
+            dashboard: DashboardConfig::default(),
+            mappings: Vec::new(),
+            sources: Vec::new(),
+            staleness: StalenessConfig::default(),
+            profiles: Vec::new(),
+        }
+    }
+}
+
+impl AppConfig {
+    /// Names of all profiles, sorted, including the active one.
+    pub fn profile_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = std::iter::once(self.profile.as_str())
+            .chain(self.profiles.iter().map(|p| p.name.as_str()))
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Make `name` the active profile, storing the current one.
+    /// Returns false when no such profile exists.
+    pub fn switch_profile(&mut self, name: &str) -> bool {
+        if name == self.profile {
+            return true;
+        }
+        let Some(idx) = self.profiles.iter().position(|p| p.name == name) else {
+            return false;
+        };
+        let next = self.profiles.remove(idx);
+        let previous = Profile {
+            name: std::mem::replace(&mut self.profile, next.name),
+            mqtt: std::mem::replace(&mut self.mqtt, next.mqtt),
+            dashboard: std::mem::replace(&mut self.dashboard, next.dashboard),
+            mappings: std::mem::replace(&mut self.mappings, next.mappings),
+            sources: std::mem::replace(&mut self.sources, next.sources),
+            staleness: std::mem::replace(&mut self.staleness, next.staleness),
+        };
+        self.profiles.push(previous);
+        self.profiles.sort_by(|a, b| a.name.cmp(&b.name));
+        true
+    }
+
+    /// Add a profile named `name` starting from the active profile's
+    /// settings without its credentials, and switch to it.
+    pub fn add_profile(&mut self, name: &str) -> Result<()> {
+        let name = name.trim();
+        if name.is_empty() {
+            anyhow::bail!("profile name must be non-empty");
+        }
+        if self.profile_names().contains(&name) {
+            anyhow::bail!("a profile named {name} already exists");
+        }
+        let mut mqtt = self.mqtt.clone();
+        mqtt.username = None;
+        mqtt.remember_password = false;
+        mqtt.client_cert_path = None;
+        mqtt.client_key_path = None;
+        mqtt.pin = None;
+        mqtt.trust_on_first_use = false;
+        self.profiles.push(Profile {
+            name: name.to_string(),
+            mqtt,
+            dashboard: self.dashboard.clone(),
+            mappings: self.mappings.clone(),
+            sources: self.sources.clone(),
+            staleness: self.staleness.clone(),
+        });
+        self.switch_profile(name);
+        Ok(())
+    }
+
+    /// Remove an inactive profile. Returns false when it does not exist or
+    /// is the active one.
+    pub fn remove_profile(&mut self, name: &str) -> bool {
+        let before = self.profiles.len();
+        self.profiles.retain(|p| p.name != name);
+        self.profiles.len() != before
+    }
+
+    /// Source policy configured for a metric id or alias, if any.
+    pub fn source_policy(&self, metric: &str) -> Option<&SourcePolicy> {
+        let id = crate::metrics::canonical_id(metric);
+        self.sources
+            .iter()
+            .find(|p| crate::metrics::canonical_id(&p.metric) == id)
+    }
 }
 
 /// Resolved paths for configuration files.
@@ -225,17 +916,22 @@ pub fn load_or_default(paths: &ConfigPaths) -> Result<AppConfig> {
                 format!("failed to parse config at {}", paths.config_file.display())
             })?;
             cfg.dashboard.normalize();
-            if cfg.mqtt.host.trim().is_empty() {
-                anyhow::bail!("MQTT host must be non-empty");
-            }
-            if !(1..=65535).contains(&cfg.mqtt.port) {
-                anyhow::bail!("MQTT port must be between 1 and 65535");
+            for profile in &mut cfg.profiles {
+                profile.dashboard.normalize();
             }
-            if cfg.mqtt.qos > 2 {
-                anyhow::bail!("MQTT QoS must be between 0 and 2");
-            }
-            if cfg.mqtt.keepalive_secs == 0 {
-                anyhow::bail!("MQTT keepalive must be greater than 0 seconds");
+            validate_profile(&cfg.mqtt, &cfg.mappings, &cfg.sources, &cfg.staleness)?;
+            let mut names = HashSet::from([cfg.profile.as_str()]);
+            for profile in &cfg.profiles {
+                if !names.insert(profile.name.as_str()) {
+                    anyhow::bail!("duplicate profile name {}", profile.name);
+                }
+                validate_profile(
+                    &profile.mqtt,
+                    &profile.mappings,
+                    &profile.sources,
+                    &profile.staleness,
+                )
+                .with_context(|| format!("invalid profile {}", profile.name))?;
             }
             Ok(cfg)
         }
@@ -245,6 +941,49 @@ pub fn load_or_default(paths: &ConfigPaths) -> Result<AppConfig> {
     }
 }
 
+/// Check the settings one profile is made of.
+fn validate_profile(
+    mqtt: &MqttConfig,
+    mappings: &[MappingRule],
+    sources: &[SourcePolicy],
+    staleness: &StalenessConfig,
+) -> Result<()> {
+    if mqtt.host.trim().is_empty() {
+        anyhow::bail!("MQTT host must be non-empty");
+    }
+    if !(1..=65535).contains(&mqtt.port) {
+        anyhow::bail!("MQTT port must be between 1 and 65535");
+    }
+    if mqtt.qos > 2 {
+        anyhow::bail!("MQTT QoS must be between 0 and 2");
+    }
+    if mqtt.keepalive_secs == 0 {
+        anyhow::bail!("MQTT keepalive must be greater than 0 seconds");
+    }
+    if mqtt.client_cert_path.is_some() != mqtt.client_key_path.is_some() {
+        anyhow::bail!("MQTT client certificate and key must be set together");
+    }
+    if let Some(pin) = &mqtt.pin {
+        pin.digest()?;
+    }
+    if mqtt.receive_maximum == Some(0) {
+        anyhow::bail!("MQTT receive maximum must be greater than 0");
+    }
+    for filter in &mqtt.filters {
+        filter.validate()?;
+    }
+    for adapter in &mqtt.adapters {
+        adapter.validate()?;
+    }
+    for rule in mappings {
+        rule.validate()?;
+    }
+    for policy in sources {
+        policy.validate()?;
+    }
+    staleness.validate()
+}
+
 /// Save configuration to disk with secure permissions.
 pub fn save(paths: &ConfigPaths, cfg: &AppConfig) -> Result<()> {
     if let Some(dir) = paths.config_file.parent() {
@@ -340,6 +1079,20 @@ mod tests {
         assert!(defaults.is_empty());
     }
 
+    #[test]
+    fn section_normalize_canonicalizes_metric_aliases() {
+        let mut section = DashboardSectionConfig {
+            id: "environment".to_string(),
+            enabled: true,
+            gauges: vec![GaugeConfig::new("temp"), GaugeConfig::new("temperature")],
+        };
+        section.normalize();
+        assert_eq!(
+            gauge_ids(&section),
+            vec!["temperature", "humidity", "pressure"]
+        );
+    }
+
     #[test]
     fn dashboard_normalize_dedupes_sections_and_recurses() {
         let mut dashboard = DashboardConfig {
@@ -365,6 +1118,7 @@ mod tests {
                     gauges: vec![GaugeConfig::new("x"), GaugeConfig::new("x")],
                 },
             ],
+            device: None,
         };
 
         dashboard.normalize();
@@ -476,4 +1230,357 @@ enabled = true
         assert_eq!(cfg.mqtt.host, cfg2.mqtt.host);
         assert_eq!(cfg.mqtt.port, cfg2.mqtt.port);
     }
+
+    #[test]
+    fn mapping_rules_parse_and_round_trip() {
+        let raw = r#"
+[mqtt]
+host = "localhost"
+port = 1883
+tls = false
+qos = 0
+keepalive_secs = 30
+remember_password = false
+
+[[mapping]]
+topic = "office/air/pm25"
+metric = "pm25"
+
+[[mapping]]
+topic = "^sensors/(kitchen|hall)/co2$"
+match = "regex"
+metric = "co2"
+scale = 0.5
+unit = "ppm"
+device = "$1"
+
+[[mapping]]
+topic = "tele/+/SENSOR"
+match = "wildcard"
+metric = "pm25"
+path = "$.PMS5003['PM2.5']"
+"#;
+
+        let cfg: AppConfig = toml::from_str(raw).expect("failed to parse mapping rules");
+        assert_eq!(cfg.mappings.len(), 3);
+        assert_eq!(cfg.mappings[2].path.as_deref(), Some("$.PMS5003['PM2.5']"));
+        assert_eq!(cfg.mappings[0].match_type, TopicMatch::Exact);
+        assert_eq!(cfg.mappings[0].scale, 1.0);
+        assert_eq!(cfg.mappings[1].match_type, TopicMatch::Regex);
+        assert_eq!(cfg.mappings[1].unit.as_deref(), Some("ppm"));
+        assert_eq!(cfg.mappings[1].device.as_deref(), Some("$1"));
+        for rule in &cfg.mappings {
+            rule.validate().expect("rule should be valid");
+        }
+
+        let serialized = toml::to_string_pretty(&cfg).expect("failed to serialize");
+        let cfg2: AppConfig = toml::from_str(&serialized).expect("failed to re-parse");
+        assert_eq!(cfg.mappings, cfg2.mappings);
+    }
+
+    #[test]
+    fn mapping_rule_rejects_invalid_regex() {
+        let rule = MappingRule {
+            topic: "sensors/(".to_string(),
+            match_type: TopicMatch::Regex,
+            metric: "co2".to_string(),
+            path: None,
+            scale: 1.0,
+            unit: None,
+            device: None,
+        };
+        assert!(rule.validate().is_err());
+
+        for (topic, match_type) in [
+            ("a/#/b", TopicMatch::Wildcard),
+            ("a/b+", TopicMatch::Wildcard),
+            ("a/+/b", TopicMatch::Exact),
+        ] {
+            let rule = MappingRule {
+                topic: topic.to_string(),
+                match_type,
+                ..rule.clone()
+            };
+            assert!(rule.validate().is_err(), "{topic} should be rejected");
+        }
+        let rule = MappingRule {
+            topic: "a/+/b/#".to_string(),
+            match_type: TopicMatch::Wildcard,
+            ..rule
+        };
+        rule.validate().expect("wildcard rule should be valid");
+    }
+
+    #[test]
+    fn source_policies_parse_and_validate() {
+        let raw = r#"
+[mqtt]
+host = "localhost"
+port = 1883
+tls = false
+qos = 0
+keepalive_secs = 30
+remember_password = false
+
+[[source]]
+metric = "temp"
+mode = "preferred"
+topic = "air1/sensor/temperature/state"
+
+[[source]]
+metric = "co2"
+mode = "mean"
+"#;
+
+        let cfg: AppConfig = toml::from_str(raw).expect("failed to parse source policies");
+        assert_eq!(cfg.sources.len(), 2);
+        assert_eq!(
+            cfg.source_policy("temperature").map(|p| p.mode),
+            Some(SourceMode::Preferred)
+        );
+        assert!(cfg.source_policy("pm25").is_none());
+        for policy in &cfg.sources {
+            policy.validate().expect("policy should be valid");
+        }
+
+        let missing_topic = SourcePolicy {
+            metric: "co2".to_string(),
+            mode: SourceMode::Preferred,
+            topic: None,
+        };
+        assert!(missing_topic.validate().is_err());
+    }
+
+    #[test]
+    fn staleness_timeouts_prefer_metric_then_device() {
+        let raw = r#"
+[mqtt]
+host = "localhost"
+port = 1883
+tls = false
+qos = 0
+keepalive_secs = 30
+remember_password = false
+
+[staleness]
+default_secs = 120
+
+[staleness.metrics]
+co2 = 30
+
+[staleness.devices]
+air1-bedroom = 600
+"#;
+
+        let cfg: AppConfig = toml::from_str(raw).expect("failed to parse staleness");
+        let staleness = &cfg.staleness;
+        assert_eq!(
+            staleness.timeout("air1-bedroom", "co2"),
+            Duration::from_secs(30)
+        );
+        assert_eq!(
+            staleness.timeout("air1-bedroom", "pm25"),
+            Duration::from_secs(600)
+        );
+        assert_eq!(
+            staleness.timeout("air1-living", "temp"),
+            Duration::from_secs(120)
+        );
+        staleness.validate().expect("timeouts are valid");
+
+        let mut zero = StalenessConfig::default();
+        zero.metrics.insert("pm25".to_string(), 0);
+        assert!(zero.validate().is_err());
+        assert_eq!(AppConfig::default().staleness.default_secs, 60);
+    }
+
+    #[test]
+    fn topic_filters_parse_and_validate() {
+        let raw = r#"
+[mqtt]
+host = "localhost"
+port = 1883
+tls = false
+qos = 1
+keepalive_secs = 30
+remember_password = false
+
+[[mqtt.filters]]
+filter = "apollo-air-1/sensor/+/state"
+
+[[mqtt.filters]]
+filter = "apollo-air-1/status"
+qos = 0
+"#;
+
+        let cfg: AppConfig = toml::from_str(raw).expect("failed to parse topic filters");
+        assert_eq!(cfg.mqtt.filters.len(), 2);
+        assert_eq!(cfg.mqtt.filters[0].qos, None);
+        assert_eq!(cfg.mqtt.filters[1].qos, Some(0));
+        for filter in &cfg.mqtt.filters {
+            filter.validate().expect("filter should be valid");
+        }
+
+        let serialized = toml::to_string_pretty(&cfg).expect("failed to serialize");
+        let cfg2: AppConfig = toml::from_str(&serialized).expect("failed to re-parse");
+        assert_eq!(cfg.mqtt.filters, cfg2.mqtt.filters);
+
+        for bad in ["a/#/b", "a/b+", ""] {
+            let filter = TopicFilter {
+                filter: bad.to_string(),
+                qos: None,
+            };
+            assert!(filter.validate().is_err(), "{bad} should be rejected");
+        }
+    }
+
+    #[test]
+    fn every_profile_is_validated_on_load() {
+        let path = std::env::temp_dir().join(format!("air1-profiles-{}.toml", std::process::id()));
+        let paths = ConfigPaths {
+            config_file: path.clone(),
+        };
+        let mut cfg = AppConfig::default();
+        cfg.add_profile("office").expect("add profile");
+        cfg.switch_profile(DEFAULT_PROFILE);
+        cfg.profiles[0].mqtt.port = 0;
+        fs::write(&path, toml::to_string_pretty(&cfg).unwrap()).unwrap();
+        let err = load_or_default(&paths).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("invalid profile office"),
+            "{err:#}"
+        );
+
+        cfg.profiles[0].mqtt.port = 1883;
+        cfg.profiles[0].name = DEFAULT_PROFILE.to_string();
+        fs::write(&path, toml::to_string_pretty(&cfg).unwrap()).unwrap();
+        let err = load_or_default(&paths).unwrap_err();
+        let _ = fs::remove_file(&path);
+        assert!(
+            format!("{err:#}").contains("duplicate profile name"),
+            "{err:#}"
+        );
+    }
+
+    #[test]
+    fn profiles_switch_and_round_trip() {
+        let mut cfg = AppConfig::default();
+        cfg.mqtt.host = "home.lan".to_string();
+        cfg.mqtt.username = Some("me".to_string());
+        cfg.mqtt.client_cert_path = Some(PathBuf::from("/etc/air1/client.pem"));
+        cfg.mqtt.client_key_path = Some(PathBuf::from("/etc/air1/client.key"));
+        cfg.mqtt.trust_on_first_use = true;
+        cfg.add_profile("office").expect("add profile");
+
+        assert_eq!(cfg.profile, "office");
+        assert_eq!(cfg.mqtt.host, "home.lan");
+        assert_eq!(cfg.mqtt.username, None);
+        assert_eq!(cfg.mqtt.client_cert_path, None);
+        assert_eq!(cfg.mqtt.client_key_path, None);
+        assert!(!cfg.mqtt.trust_on_first_use);
+        assert!(cfg.add_profile("default").is_err());
+        cfg.mqtt.host = "office.example.com".to_string();
+        cfg.staleness.default_secs = 300;
+
+        let serialized = toml::to_string_pretty(&cfg).expect("failed to serialize");
+        let mut cfg: AppConfig = toml::from_str(&serialized).expect("failed to re-parse");
+        assert_eq!(cfg.profile_names(), vec!["default", "office"]);
+
+        assert_eq!(cfg.staleness.default_secs, 300);
+        assert!(cfg.switch_profile("default"));
+        assert_eq!(cfg.mqtt.host, "home.lan");
+        assert_eq!(cfg.mqtt.username.as_deref(), Some("me"));
+        assert_eq!(cfg.staleness.default_secs, 60);
+        assert!(!cfg.switch_profile("missing"));
+        assert!(!cfg.remove_profile("default"));
+        assert!(cfg.remove_profile("office"));
+        assert_eq!(cfg.profile_names(), vec!["default"]);
+    }
+
+    #[test]
+    fn mqtt5_options_parse_and_default_to_3_1_1() {
+        assert_eq!(MqttConfig::default().protocol, ProtocolVersion::V311);
+
+        let raw = r#"
+[mqtt]
+host = "localhost"
+port = 1883
+tls = false
+qos = 1
+keepalive_secs = 30
+remember_password = false
+protocol = "5"
+session_expiry_secs = 3600
+receive_maximum = 10
+
+[mqtt.user_properties]
+site = "home"
+"#;
+
+        let cfg: AppConfig = toml::from_str(raw).expect("failed to parse MQTT 5 options");
+        assert_eq!(cfg.mqtt.protocol, ProtocolVersion::V5);
+        assert_eq!(cfg.mqtt.session_expiry_secs, Some(3600));
+        assert_eq!(cfg.mqtt.receive_maximum, Some(10));
+        assert_eq!(
+            cfg.mqtt.user_properties.get("site").map(String::as_str),
+            Some("home")
+        );
+
+        let serialized = toml::to_string_pretty(&cfg).expect("failed to serialize");
+        assert!(serialized.contains("protocol = \"5\""));
+        let cfg2: AppConfig = toml::from_str(&serialized).expect("failed to re-parse");
+        assert_eq!(cfg2.mqtt.protocol, ProtocolVersion::V5);
+        assert_eq!(cfg2.mqtt.user_properties, cfg.mqtt.user_properties);
+    }
+
+    #[test]
+    fn transport_falls_back_to_tls_switch() {
+        let mut mqtt = MqttConfig::default();
+        assert_eq!(mqtt.transport(), MqttTransport::Tcp);
+        mqtt.tls = true;
+        assert_eq!(mqtt.transport(), MqttTransport::Tls);
+
+        let raw = r#"
+[mqtt]
+host = "broker.example.com"
+port = 443
+tls = true
+transport = "wss"
+ws_path = "/mqtt"
+qos = 0
+keepalive_secs = 30
+remember_password = false
+
+[mqtt.ws_headers]
+Authorization = "Bearer token"
+"#;
+        let cfg: AppConfig = toml::from_str(raw).expect("failed to parse transport");
+        assert_eq!(cfg.mqtt.transport(), MqttTransport::Wss);
+        assert!(cfg.mqtt.transport().is_secure() && cfg.mqtt.transport().is_websocket());
+        assert_eq!(cfg.mqtt.ws_headers.len(), 1);
+    }
+
+    #[test]
+    fn certificate_pins_decode_with_or_without_colons() {
+        let hex = "AB".repeat(32);
+        let plain = CertPin {
+            kind: PinKind::Spki,
+            sha256: hex.clone(),
+        };
+        assert_eq!(plain.digest().expect("plain hex"), [0xAB; 32]);
+
+        let colons = CertPin {
+            kind: PinKind::Certificate,
+            sha256: vec!["ab"; 32].join(":"),
+        };
+        assert_eq!(colons.digest().expect("colon hex"), [0xAB; 32]);
+
+        for bad in ["AB", &"ZZ".repeat(32)] {
+            let pin = CertPin {
+                kind: PinKind::Certificate,
+                sha256: bad.to_string(),
+            };
+            assert!(pin.digest().is_err(), "{bad} should be rejected");
+        }
+    }
 }
@@ -0,0 +1,345 @@
+use std::fmt;
+
+use serde_json::Value;
+
+/// A numeric reading extracted from a payload.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decoded {
+    /// Parsed numeric value.
+    pub value: f64,
+    /// Unit carried in the payload, if any (e.g. a JSON `unit` field).
+    pub unit: Option<String>,
+}
+
+/// Reasons a payload could not be turned into a number.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// Payload is neither a number nor JSON.
+    NotNumeric(String),
+    /// Payload looked like JSON but failed to parse.
+    InvalidJson(String),
+    /// The configured path is malformed.
+    InvalidPath(String),
+    /// The configured path does not exist in the payload.
+    MissingField(String),
+    /// The field exists but does not hold a number.
+    NotANumber(String),
+    /// The sensor reported itself as `unavailable` or `unknown`.
+    Unavailable(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::NotNumeric(payload) => write!(f, "payload {payload:?} is not numeric"),
+            DecodeError::InvalidJson(err) => write!(f, "invalid JSON: {err}"),
+            DecodeError::InvalidPath(path) => write!(f, "invalid JSON path {path:?}"),
+            DecodeError::MissingField(path) => write!(f, "field {path} not found"),
+            DecodeError::NotANumber(path) => write!(f, "field {path} is not a number"),
+            DecodeError::Unavailable(state) => write!(f, "sensor is {state}"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Decode a payload as a plain number, or as JSON when a path is given.
+///
+/// `path` accepts a JSON pointer (`/a/b/0`), a JSONPath subset
+/// (`$.a.b[0]`, `$['PM2.5']`) or a dotted key path (`a.b`). Without a path,
+/// JSON objects are read from their `value` field. Plain numbers may carry a
+/// unit suffix (`12.3 µg/m³`), and Home Assistant's `unavailable`/`unknown`
+/// states decode to [`DecodeError::Unavailable`].
+pub fn decode(payload: &[u8], path: Option<&str>) -> Result<Decoded, DecodeError> {
+    let text = String::from_utf8_lossy(payload);
+    let text = text.trim();
+
+    if let Some(state) = unavailable_state(text) {
+        return Err(DecodeError::Unavailable(state));
+    }
+    if path.is_none()
+        && let Some(decoded) = parse_number(text)
+    {
+        return Ok(decoded);
+    }
+
+    if !(text.starts_with('{') || text.starts_with('[')) {
+        return Err(DecodeError::NotNumeric(text.to_string()));
+    }
+    let json: Value =
+        serde_json::from_str(text).map_err(|err| DecodeError::InvalidJson(err.to_string()))?;
+    decode_json(&json, path.unwrap_or("value"))
+}
+
+/// Extract a number from an already parsed JSON document.
+pub fn decode_json(json: &Value, path: &str) -> Result<Decoded, DecodeError> {
+    let tokens = parse_path(path).ok_or_else(|| DecodeError::InvalidPath(path.to_string()))?;
+
+    let mut parent = json;
+    let mut current = json;
+    for token in &tokens {
+        let next = match (current, token) {
+            (Value::Object(map), PathToken::Key(key)) => map.get(key),
+            (Value::Array(items), PathToken::Index(idx)) => items.get(*idx),
+            (Value::Array(items), PathToken::Key(key)) => {
+                key.parse::<usize>().ok().and_then(|idx| items.get(idx))
+            }
+            _ => None,
+        };
+        parent = current;
+        current = next.ok_or_else(|| DecodeError::MissingField(path.to_string()))?;
+    }
+
+    let (value, suffix) = match current {
+        Value::Number(n) => (n.as_f64(), None),
+        Value::String(s) => {
+            if let Some(state) = unavailable_state(s) {
+                return Err(DecodeError::Unavailable(state));
+            }
+            parse_number(s).map_or((None, None), |d| (Some(d.value), d.unit))
+        }
+        _ => (None, None),
+    };
+    let value = value.ok_or_else(|| DecodeError::NotANumber(path.to_string()))?;
+
+    let unit = ["unit", "unit_of_measurement"]
+        .iter()
+        .find_map(|key| parent.get(key).and_then(Value::as_str))
+        .map(str::to_string)
+        .or(suffix);
+
+    Ok(Decoded { value, unit })
+}
+
+/// Parse a finite number with an optional unit suffix, e.g. `21.5`,
+/// `12.3 µg/m³`, `1e3 ppm` or `21.5°C`. A unit starts with a letter, `%`,
+/// a degree sign or a micro sign.
+pub fn parse_number(text: &str) -> Option<Decoded> {
+    let text = text.trim();
+    let (number, unit) = text.split_at(number_len(text));
+    let value = number.parse::<f64>().ok().filter(|v| v.is_finite())?;
+    let unit = unit.trim();
+    // Anything else after the number, like the `,5` of a decimal comma, is
+    // not a unit.
+    if !unit.is_empty()
+        && !unit
+            .starts_with(|c: char| c.is_alphabetic() || matches!(c, '%' | '°' | 'º' | 'µ' | 'μ'))
+    {
+        return None;
+    }
+    Some(Decoded {
+        value,
+        unit: (!unit.is_empty()).then(|| unit.to_string()),
+    })
+}
+
+/// Length of the decimal number at the start of `text`: sign, digits,
+/// fraction and exponent. Zero when it does not start with one.
+fn number_len(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    let digits = |from: usize| {
+        bytes.get(from..).map_or(0, |rest| {
+            rest.iter().take_while(|b| b.is_ascii_digit()).count()
+        })
+    };
+    let mut len = usize::from(matches!(bytes.first(), Some(b'+' | b'-')));
+    let int = digits(len);
+    len += int;
+    let mut frac = 0;
+    if bytes.get(len) == Some(&b'.') {
+        frac = digits(len + 1);
+        len += 1 + frac;
+    }
+    if int + frac == 0 {
+        return 0;
+    }
+    // An `e` only starts an exponent when digits follow, so `5 eggs` stays a unit.
+    if matches!(bytes.get(len), Some(b'e' | b'E')) {
+        let sign = usize::from(matches!(bytes.get(len + 1), Some(b'+' | b'-')));
+        let exp = digits(len + 1 + sign);
+        if exp > 0 {
+            len += 1 + sign + exp;
+        }
+    }
+    len
+}
+
+/// Home Assistant state strings meaning the sensor has no current value,
+/// returned lowercased. Non-finite numbers such as `nan` count too.
+fn unavailable_state(text: &str) -> Option<String> {
+    let state = text.trim().trim_matches('"').to_ascii_lowercase();
+    let unavailable = matches!(state.as_str(), "unavailable" | "unknown")
+        || state.parse::<f64>().is_ok_and(|v| !v.is_finite());
+    unavailable.then_some(state)
+}
+
+/// Return true if `path` is a usable JSON pointer or JSONPath expression.
+pub fn is_valid_path(path: &str) -> bool {
+    parse_path(path).is_some()
+}
+
+#[derive(Debug, PartialEq)]
+enum PathToken {
+    Key(String),
+    Index(usize),
+}
+
+fn parse_path(path: &str) -> Option<Vec<PathToken>> {
+    let path = path.trim();
+    if let Some(pointer) = path.strip_prefix('/') {
+        return Some(
+            pointer
+                .split('/')
+                .map(|t| PathToken::Key(t.replace("~1", "/").replace("~0", "~")))
+                .collect(),
+        );
+    }
+    if path.is_empty() {
+        return None;
+    }
+
+    let rest = path.strip_prefix('$').unwrap_or(path);
+    let mut tokens = Vec::new();
+    let mut chars = rest.chars();
+    let mut key = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '.' => {
+                if !key.is_empty() {
+                    tokens.push(PathToken::Key(std::mem::take(&mut key)));
+                }
+            }
+            '[' => {
+                if !key.is_empty() {
+                    tokens.push(PathToken::Key(std::mem::take(&mut key)));
+                }
+                let mut inner = String::new();
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    inner.push(c);
+                }
+                let inner = inner.trim();
+                let quoted = inner
+                    .strip_prefix('\'')
+                    .and_then(|s| s.strip_suffix('\''))
+                    .or_else(|| inner.strip_prefix('"').and_then(|s| s.strip_suffix('"')));
+                match quoted {
+                    Some(name) => tokens.push(PathToken::Key(name.to_string())),
+                    None => tokens.push(PathToken::Index(inner.parse().ok()?)),
+                }
+            }
+            _ => key.push(c),
+        }
+    }
+    if !key.is_empty() {
+        tokens.push(PathToken::Key(key));
+    }
+    Some(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_numbers_decode_without_path() {
+        assert_eq!(
+            decode(b" 12.5\n", None),
+            Ok(Decoded {
+                value: 12.5,
+                unit: None
+            })
+        );
+        assert!(matches!(
+            decode(b"on", None),
+            Err(DecodeError::NotNumeric(_))
+        ));
+    }
+
+    #[test]
+    fn json_value_field_and_unit_are_used_by_default() {
+        let decoded =
+            decode(r#"{"value": 12.3, "unit": "µg/m³"}"#.as_bytes(), None).expect("decodes");
+        assert_eq!(decoded.value, 12.3);
+        assert_eq!(decoded.unit.as_deref(), Some("µg/m³"));
+    }
+
+    #[test]
+    fn paths_support_pointer_jsonpath_and_dotted_forms() {
+        let payload = br#"{"PMS5003": {"PM2.5": 7, "PM10": "11"}, "list": [1, {"co2": 640}]}"#;
+        let value = |path| decode(payload, Some(path)).map(|d| d.value);
+
+        assert_eq!(value("/PMS5003/PM2.5"), Ok(7.0));
+        assert_eq!(value("$['PMS5003']['PM2.5']"), Ok(7.0));
+        assert_eq!(value("$.PMS5003.PM10"), Ok(11.0));
+        assert_eq!(value("list[1].co2"), Ok(640.0));
+        assert_eq!(
+            value("$.PMS5003.PM1"),
+            Err(DecodeError::MissingField("$.PMS5003.PM1".to_string()))
+        );
+        assert!(matches!(
+            decode(b"{not json", Some("$.a")),
+            Err(DecodeError::InvalidJson(_))
+        ));
+    }
+
+    #[test]
+    fn unit_suffixes_and_unavailable_states() {
+        let decoded = decode("12.3 µg/m³".as_bytes(), None).expect("decodes");
+        assert_eq!(decoded.value, 12.3);
+        assert_eq!(decoded.unit.as_deref(), Some("µg/m³"));
+        assert_eq!(
+            parse_number("21.5°C").and_then(|d| d.unit),
+            Some("°C".to_string())
+        );
+        assert!(parse_number("PM2.5").is_none());
+        assert_eq!(
+            parse_number("1e3 ppm"),
+            Some(Decoded {
+                value: 1000.0,
+                unit: Some("ppm".to_string())
+            })
+        );
+        assert_eq!(
+            parse_number("-2.5E-1"),
+            Some(Decoded {
+                value: -0.25,
+                unit: None
+            })
+        );
+        assert_eq!(
+            parse_number("5 eggs").and_then(|d| d.unit).as_deref(),
+            Some("eggs")
+        );
+        assert!(parse_number("1.2.3").is_none());
+        assert!(parse_number("12,5").is_none());
+        assert!(decode(b"12,5", None).is_err());
+        assert_eq!(
+            parse_number("45 %").and_then(|d| d.unit).as_deref(),
+            Some("%")
+        );
+        assert_eq!(
+            decode(br#"{"co2": "640 ppm"}"#, Some("co2")).map(|d| d.unit),
+            Ok(Some("ppm".to_string()))
+        );
+
+        assert_eq!(
+            decode(b"unavailable", None),
+            Err(DecodeError::Unavailable("unavailable".to_string()))
+        );
+        assert_eq!(
+            decode(br#"{"state": "Unknown"}"#, Some("state")),
+            Err(DecodeError::Unavailable("unknown".to_string()))
+        );
+        assert_eq!(
+            decode(b"NaN", None),
+            Err(DecodeError::Unavailable("nan".to_string()))
+        );
+        assert!(matches!(
+            decode(br#"{"co2": "inf"}"#, Some("co2")),
+            Err(DecodeError::Unavailable(_))
+        ));
+    }
+}
@@ -0,0 +1,557 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{self, Write as _},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+use crate::config::{MappingRule, MqttConfig, PinKind};
+use crate::{mqtt, tls};
+
+const TCP_TIMEOUT: Duration = Duration::from_secs(3);
+/// How long to wait for the CONNACK and for the SUBACKs.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait for a publish the mapper turns into a reading.
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How a diagnostic step ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    /// Did not stop the connection but needs attention.
+    Warning,
+    Failed,
+    Skipped,
+}
+
+impl Outcome {
+    fn label(self) -> &'static str {
+        match self {
+            Outcome::Passed => "PASS",
+            Outcome::Warning => "WARN",
+            Outcome::Failed => "FAIL",
+            Outcome::Skipped => "SKIP",
+        }
+    }
+}
+
+/// One timed phase of a connection test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub name: String,
+    pub outcome: Outcome,
+    /// Time the phase took, when it ran.
+    pub elapsed: Option<Duration>,
+    /// Result lines (addresses, certificates, reason codes, errors).
+    pub detail: Vec<String>,
+}
+
+/// Step-by-step result of a connection test.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Broker address, transport and protocol under test.
+    pub broker: String,
+    pub steps: Vec<Step>,
+}
+
+impl Report {
+    pub(crate) fn record(
+        &mut self,
+        name: impl Into<String>,
+        outcome: Outcome,
+        elapsed: Option<Duration>,
+        detail: Vec<String>,
+    ) {
+        self.steps.push(Step {
+            name: name.into(),
+            outcome,
+            elapsed,
+            detail,
+        });
+    }
+
+    /// First step that failed, if any.
+    pub fn first_failure(&self) -> Option<&Step> {
+        self.steps.iter().find(|s| s.outcome == Outcome::Failed)
+    }
+
+    /// Whether every step passed or only warned.
+    pub fn passed(&self) -> bool {
+        self.first_failure().is_none()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Air1 Monitor {} connection diagnostics",
+            env!("CARGO_PKG_VERSION")
+        )?;
+        writeln!(f, "Broker: {}", self.broker)?;
+        for step in &self.steps {
+            let mut line = format!("\n[{}] {}", step.outcome.label(), step.name);
+            if let Some(elapsed) = step.elapsed {
+                let _ = write!(line, " ({} ms)", elapsed.as_millis());
+            }
+            writeln!(f, "{line}")?;
+            for detail in &step.detail {
+                writeln!(f, "       {detail}")?;
+            }
+        }
+        match self.first_failure() {
+            Some(step) => write!(f, "\nResult: failed at {}", step.name),
+            None => write!(f, "\nResult: passed"),
+        }
+    }
+}
+
+/// Run every phase of a connection test in turn, stopping at the first one
+/// that makes the later phases pointless.
+pub fn run(
+    cfg: &MqttConfig,
+    mappings: &[MappingRule],
+    password: Option<&str>,
+    key_passphrase: Option<&str>,
+) -> Report {
+    let mut report = Report {
+        broker: format!(
+            "{}:{} over {}, {}",
+            cfg.host,
+            cfg.port,
+            cfg.transport().label(),
+            cfg.protocol.label()
+        ),
+        steps: Vec::new(),
+    };
+
+    let started = Instant::now();
+    let addrs: Vec<SocketAddr> = match (cfg.host.as_str(), cfg.port).to_socket_addrs() {
+        Ok(addrs) => addrs.collect(),
+        Err(err) => {
+            report.record(
+                "DNS resolution",
+                Outcome::Failed,
+                Some(started.elapsed()),
+                vec![err.to_string()],
+            );
+            return report;
+        }
+    };
+    if addrs.is_empty() {
+        report.record(
+            "DNS resolution",
+            Outcome::Failed,
+            Some(started.elapsed()),
+            vec!["no addresses".to_string()],
+        );
+        return report;
+    }
+    report.record(
+        "DNS resolution",
+        Outcome::Passed,
+        Some(started.elapsed()),
+        addrs.iter().map(|addr| addr.ip().to_string()).collect(),
+    );
+
+    if !check_tcp(&mut report, &addrs) {
+        return report;
+    }
+
+    if cfg.transport().is_secure() {
+        let started = Instant::now();
+        match tls::probe(cfg, key_passphrase) {
+            Ok(probe) => {
+                let (outcome, detail) = describe_probe(cfg, &probe);
+                report.record("TLS handshake", outcome, Some(started.elapsed()), detail);
+            }
+            Err(err) => {
+                report.record(
+                    "TLS handshake",
+                    Outcome::Failed,
+                    Some(started.elapsed()),
+                    vec![format!("{err:#}")],
+                );
+                return report;
+            }
+        }
+    } else {
+        report.record(
+            "TLS handshake",
+            Outcome::Skipped,
+            None,
+            vec!["transport is not encrypted".to_string()],
+        );
+    }
+
+    mqtt::diagnose_session(cfg, mappings, password, key_passphrase, &mut report);
+    report
+}
+
+/// Try every resolved address; an unreachable address only warns when
+/// another one answers, since the client falls back to it.
+fn check_tcp(report: &mut Report, addrs: &[SocketAddr]) -> bool {
+    let results: Vec<(SocketAddr, Duration, Result<(), String>)> = addrs
+        .iter()
+        .map(|addr| {
+            let started = Instant::now();
+            let result = TcpStream::connect_timeout(addr, TCP_TIMEOUT)
+                .map(drop)
+                .map_err(|err| err.to_string());
+            (*addr, started.elapsed(), result)
+        })
+        .collect();
+    let reachable = results.iter().any(|(_, _, result)| result.is_ok());
+    for (addr, elapsed, result) in results {
+        let (outcome, detail) = match result {
+            Ok(()) => (Outcome::Passed, Vec::new()),
+            Err(err) if reachable => (Outcome::Warning, vec![err]),
+            Err(err) => (Outcome::Failed, vec![err]),
+        };
+        report.record(
+            format!("TCP connect {addr}"),
+            outcome,
+            Some(elapsed),
+            detail,
+        );
+    }
+    reachable
+}
+
+fn describe_probe(cfg: &MqttConfig, probe: &tls::TlsProbe) -> (Outcome, Vec<String>) {
+    let mut outcome = Outcome::Passed;
+    let mut detail = vec![format!("Protocol: {}", probe.version)];
+    let now = std::time::SystemTime::now();
+    for (idx, info) in probe.chain.iter().enumerate() {
+        detail.push(format!("Certificate #{idx}:"));
+        detail.extend(info.describe().lines().map(|line| format!("  {line}")));
+        if let Some(warning) = info.expiry_warning(now) {
+            outcome = Outcome::Warning;
+            detail.push(format!("  Warning: {warning}"));
+        }
+    }
+    if let (Some(pin), Some(leaf)) = (&cfg.pin, probe.chain.first()) {
+        let presented = match pin.kind {
+            PinKind::Certificate => leaf.sha256,
+            PinKind::Spki => leaf.spki_sha256,
+        };
+        if pin.digest().is_ok_and(|digest| digest == presented) {
+            detail.push("Pinned fingerprint matches".to_string());
+        } else {
+            outcome = Outcome::Failed;
+            detail.push(format!("Pinned fingerprint does not match {}", pin.sha256));
+        }
+    }
+    (outcome, detail)
+}
+
+/// MQTT session event, independent of the protocol version.
+pub(crate) enum SessionEvent {
+    ConnAck {
+        accepted: bool,
+        detail: String,
+    },
+    /// Filters subscribed to after the session started, e.g. discovered
+    /// state topics; their SUBSCRIBE packets follow.
+    Subscribing(Vec<String>),
+    SubscribeSent(u16),
+    /// Grant or rejection text per filter in the SUBSCRIBE packet.
+    SubAck {
+        pkid: u16,
+        codes: Vec<Result<String, String>>,
+    },
+    Publish {
+        topic: String,
+        mapped: bool,
+    },
+    Other,
+}
+
+/// Record the CONNACK, the SUBACK of each filter, including those added
+/// during the session, and the first publish the mapper turns into a
+/// reading. `next` waits up to the given time for the next event: `None` on
+/// timeout, `Err` with a reason when the connection fails.
+pub(crate) fn record_session(
+    report: &mut Report,
+    filters: Vec<String>,
+    mut next: impl FnMut(Duration) -> Option<Result<SessionEvent, String>>,
+) {
+    let started = Instant::now();
+    let mut queued: VecDeque<String> = filters.into();
+    let mut in_flight: HashMap<u16, String> = HashMap::new();
+    let mut pending = queued.len();
+    let mut connected_at: Option<Instant> = None;
+    let mut subscribed_at: Option<Instant> = None;
+    let mut first_publish: Option<String> = None;
+
+    loop {
+        let (phase, phase_start, timeout) = match (connected_at, subscribed_at) {
+            (None, _) => ("CONNACK", started, SESSION_TIMEOUT),
+            (Some(at), None) => ("SUBACK", at, SESSION_TIMEOUT),
+            (Some(_), Some(at)) => ("First matching publish", at, PUBLISH_TIMEOUT),
+        };
+        let remaining = timeout.saturating_sub(phase_start.elapsed());
+        let event = if remaining.is_zero() {
+            None
+        } else {
+            next(remaining)
+        };
+        let event = match event {
+            Some(Ok(event)) => event,
+            Some(Err(reason)) => {
+                report.record(
+                    phase,
+                    Outcome::Failed,
+                    Some(phase_start.elapsed()),
+                    vec![reason],
+                );
+                return;
+            }
+            None if phase_start.elapsed() < timeout => continue,
+            None => {
+                let waited = format!("nothing within {}s", timeout.as_secs());
+                match phase {
+                    "SUBACK" => {
+                        for filter in queued.iter().chain(in_flight.values()) {
+                            report.record(
+                                format!("SUBACK {filter}"),
+                                Outcome::Failed,
+                                Some(phase_start.elapsed()),
+                                vec![waited.clone()],
+                            );
+                        }
+                    }
+                    "First matching publish" => {
+                        let mut detail = vec![waited];
+                        if let Some(topic) = first_publish {
+                            detail.push(format!("unmapped publishes arrived, first on {topic}"));
+                        }
+                        report.record(phase, Outcome::Warning, Some(timeout), detail);
+                    }
+                    _ => report.record(phase, Outcome::Failed, Some(timeout), vec![waited]),
+                }
+                return;
+            }
+        };
+
+        match event {
+            SessionEvent::ConnAck { accepted, detail } => {
+                let outcome = if accepted {
+                    Outcome::Passed
+                } else {
+                    Outcome::Failed
+                };
+                report.record("CONNACK", outcome, Some(started.elapsed()), vec![detail]);
+                if !accepted {
+                    return;
+                }
+                connected_at = Some(Instant::now());
+                if pending == 0 {
+                    report.record(
+                        "SUBACK",
+                        Outcome::Skipped,
+                        None,
+                        vec!["no topic filters to subscribe to".to_string()],
+                    );
+                    return;
+                }
+            }
+            SessionEvent::Subscribing(filters) => {
+                pending += filters.len();
+                queued.extend(filters);
+            }
+            SessionEvent::SubscribeSent(pkid) => {
+                if let Some(filter) = queued.pop_front() {
+                    in_flight.insert(pkid, filter);
+                }
+            }
+            SessionEvent::SubAck { pkid, codes } => {
+                let filter = in_flight
+                    .remove(&pkid)
+                    .unwrap_or_else(|| format!("packet {pkid}"));
+                let elapsed = connected_at.map(|at| at.elapsed());
+                for code in codes {
+                    let (outcome, detail) = match code {
+                        Ok(granted) => (Outcome::Passed, granted),
+                        Err(rejected) => (Outcome::Failed, rejected),
+                    };
+                    report.record(format!("SUBACK {filter}"), outcome, elapsed, vec![detail]);
+                }
+                pending = pending.saturating_sub(1);
+                if pending == 0 {
+                    subscribed_at = Some(Instant::now());
+                }
+            }
+            SessionEvent::Publish { topic, mapped } => {
+                if mapped {
+                    let since = subscribed_at.or(connected_at).unwrap_or(started);
+                    report.record(
+                        "First matching publish",
+                        Outcome::Passed,
+                        Some(since.elapsed()),
+                        vec![topic],
+                    );
+                    return;
+                }
+                first_publish.get_or_insert(topic);
+            }
+            SessionEvent::Other => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replay(
+        filters: &[&str],
+        events: Vec<Option<Result<SessionEvent, String>>>,
+    ) -> Vec<(String, Outcome)> {
+        let mut report = Report::default();
+        let mut events = events.into_iter();
+        record_session(
+            &mut report,
+            filters.iter().map(|f| f.to_string()).collect(),
+            |_| {
+                events
+                    .next()
+                    .unwrap_or(Some(Err("connection closed".to_string())))
+            },
+        );
+        report
+            .steps
+            .into_iter()
+            .map(|step| (step.name, step.outcome))
+            .collect()
+    }
+
+    fn connack() -> Option<Result<SessionEvent, String>> {
+        Some(Ok(SessionEvent::ConnAck {
+            accepted: true,
+            detail: "CONNACK Success".to_string(),
+        }))
+    }
+
+    #[test]
+    fn session_steps_follow_connack_subacks_and_first_publish() {
+        let steps = replay(
+            &["air1/#", "homeassistant/#"],
+            vec![
+                connack(),
+                Some(Ok(SessionEvent::SubscribeSent(1))),
+                Some(Ok(SessionEvent::SubscribeSent(2))),
+                Some(Ok(SessionEvent::SubAck {
+                    pkid: 2,
+                    codes: vec![Err("rejected with NotAuthorized".to_string())],
+                })),
+                Some(Ok(SessionEvent::SubAck {
+                    pkid: 1,
+                    codes: vec![Ok("granted AtMostOnce".to_string())],
+                })),
+                Some(Ok(SessionEvent::Publish {
+                    topic: "air1/status".to_string(),
+                    mapped: false,
+                })),
+                Some(Ok(SessionEvent::Publish {
+                    topic: "air1/sensor/pm25/state".to_string(),
+                    mapped: true,
+                })),
+            ],
+        );
+        assert_eq!(
+            steps,
+            vec![
+                ("CONNACK".to_string(), Outcome::Passed),
+                ("SUBACK homeassistant/#".to_string(), Outcome::Failed),
+                ("SUBACK air1/#".to_string(), Outcome::Passed),
+                ("First matching publish".to_string(), Outcome::Passed),
+            ]
+        );
+    }
+
+    #[test]
+    fn discovered_state_topics_are_subscribed_during_the_session() {
+        let steps = replay(
+            &["homeassistant/sensor/+/config"],
+            vec![
+                connack(),
+                Some(Ok(SessionEvent::SubscribeSent(1))),
+                Some(Ok(SessionEvent::SubAck {
+                    pkid: 1,
+                    codes: vec![Ok("granted AtMostOnce".to_string())],
+                })),
+                Some(Ok(SessionEvent::Publish {
+                    topic: "homeassistant/sensor/co2/config".to_string(),
+                    mapped: false,
+                })),
+                Some(Ok(SessionEvent::Subscribing(vec![
+                    "air1/sensor/co2/state".to_string(),
+                ]))),
+                Some(Ok(SessionEvent::SubscribeSent(2))),
+                Some(Ok(SessionEvent::SubAck {
+                    pkid: 2,
+                    codes: vec![Ok("granted AtMostOnce".to_string())],
+                })),
+                Some(Ok(SessionEvent::Publish {
+                    topic: "air1/sensor/co2/state".to_string(),
+                    mapped: true,
+                })),
+            ],
+        );
+        assert_eq!(
+            steps,
+            vec![
+                ("CONNACK".to_string(), Outcome::Passed),
+                (
+                    "SUBACK homeassistant/sensor/+/config".to_string(),
+                    Outcome::Passed
+                ),
+                ("SUBACK air1/sensor/co2/state".to_string(), Outcome::Passed),
+                ("First matching publish".to_string(), Outcome::Passed),
+            ]
+        );
+    }
+
+    #[test]
+    fn connection_errors_fail_the_current_phase() {
+        let steps = replay(&["air1/#"], vec![None, Some(Err("refused".to_string()))]);
+        assert_eq!(steps, vec![("CONNACK".to_string(), Outcome::Failed)]);
+
+        let steps = replay(&[], vec![connack()]);
+        assert_eq!(
+            steps,
+            vec![
+                ("CONNACK".to_string(), Outcome::Passed),
+                ("SUBACK".to_string(), Outcome::Skipped),
+            ]
+        );
+    }
+
+    #[test]
+    fn report_text_lists_each_step_and_the_result() {
+        let mut report = Report {
+            broker: "broker.lan:1883 over TCP, MQTT 3.1.1".to_string(),
+            steps: Vec::new(),
+        };
+        report.record(
+            "DNS resolution",
+            Outcome::Passed,
+            Some(Duration::from_millis(4)),
+            vec!["192.0.2.10".to_string()],
+        );
+        report.record(
+            "TCP connect 192.0.2.10:1883",
+            Outcome::Failed,
+            Some(Duration::from_millis(3000)),
+            vec!["connection refused".to_string()],
+        );
+        assert!(!report.passed());
+
+        let text = report.to_string();
+        assert!(text.contains("Broker: broker.lan:1883 over TCP, MQTT 3.1.1"));
+        assert!(text.contains("[PASS] DNS resolution (4 ms)\n       192.0.2.10"));
+        assert!(text.contains("[FAIL] TCP connect 192.0.2.10:1883 (3000 ms)"));
+        assert!(text.ends_with("Result: failed at TCP connect 192.0.2.10:1883"));
+    }
+}
@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+/// Default Home Assistant discovery prefix.
+pub const DEFAULT_DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// Device block of a Home Assistant discovery payload.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DiscoveryDevice {
+    /// Device identifiers (a single string or a list in the wire format).
+    #[serde(default, alias = "ids", deserialize_with = "string_or_list")]
+    pub identifiers: Vec<String>,
+    /// Human-readable device name.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Device model.
+    #[serde(default, alias = "mdl")]
+    pub model: Option<String>,
+    /// Device manufacturer.
+    #[serde(default, alias = "mf")]
+    pub manufacturer: Option<String>,
+}
+
+/// Subset of a Home Assistant `sensor` discovery payload used for mapping.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DiscoveryPayload {
+    /// Base topic substituted for a leading or trailing `~` in topic fields.
+    #[serde(default, rename = "~")]
+    pub base_topic: Option<String>,
+    /// Topic the sensor state is published on.
+    #[serde(default, alias = "stat_t")]
+    pub state_topic: Option<String>,
+    /// Home Assistant device class (e.g. "pm25", "carbon_dioxide").
+    #[serde(default, alias = "dev_cla")]
+    pub device_class: Option<String>,
+    /// Unit of measurement reported by the sensor.
+    #[serde(default, alias = "unit_of_meas")]
+    pub unit_of_measurement: Option<String>,
+    /// Entity name.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Entity object id.
+    #[serde(default, alias = "obj_id")]
+    pub object_id: Option<String>,
+    /// Device the entity belongs to.
+    #[serde(default, alias = "dev")]
+    pub device: Option<DiscoveryDevice>,
+}
+
+/// A sensor bound to a metric kind through a discovery record.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveredSensor {
+    /// Normalized metric kind (e.g. "pm25").
+    pub kind: &'static str,
+    /// Object id from the discovery topic.
+    pub object_id: String,
+    /// Unit of measurement announced in the discovery payload.
+    pub unit: Option<String>,
+    /// Device name or identifier, if announced.
+    pub device: Option<String>,
+}
+
+/// Result of processing a discovery config message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiscoveryUpdate {
+    /// A state topic was bound to a metric kind.
+    Added {
+        /// State topic of the sensor.
+        state_topic: String,
+        /// Metric kind the topic maps to.
+        kind: &'static str,
+    },
+    /// A previously announced sensor was removed (empty config payload).
+    Removed {
+        /// State topic that is no longer mapped.
+        state_topic: String,
+    },
+}
+
+/// In-memory table of discovered sensors keyed by state topic.
+#[derive(Debug, Default)]
+pub struct DiscoveryRegistry {
+    prefix: String,
+    sensors: HashMap<String, DiscoveredSensor>,
+    config_topics: HashMap<String, String>,
+}
+
+impl DiscoveryRegistry {
+    /// Create an empty registry for the given discovery prefix.
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            prefix: prefix.trim().trim_end_matches('/').to_string(),
+            sensors: HashMap::new(),
+            config_topics: HashMap::new(),
+        }
+    }
+
+    /// Discovery prefix this registry listens on.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Topic filters needed to receive sensor discovery configs.
+    pub fn subscriptions(&self) -> Vec<String> {
+        vec![
+            format!("{}/sensor/+/config", self.prefix),
+            format!("{}/sensor/+/+/config", self.prefix),
+        ]
+    }
+
+    /// Return true if the topic is a sensor discovery config topic.
+    pub fn is_config_topic(&self, topic: &str) -> bool {
+        parse_config_topic(&self.prefix, topic).is_some()
+    }
+
+    /// Look up the discovered sensor for a state topic.
+    pub fn lookup(&self, state_topic: &str) -> Option<&DiscoveredSensor> {
+        self.sensors.get(state_topic)
+    }
+
+    /// Device announced for the sensors publishing under `node/`, e.g. the
+    /// discovery name of an ESPHome node.
+    pub fn device_under(&self, node: &str) -> Option<&str> {
+        self.sensors
+            .iter()
+            .filter(|(topic, _)| {
+                topic
+                    .strip_prefix(node)
+                    .is_some_and(|rest| rest.starts_with('/'))
+            })
+            .find_map(|(_, sensor)| sensor.device.as_deref())
+    }
+
+    /// Iterate over all state topics currently bound by discovery.
+    pub fn state_topics(&self) -> impl Iterator<Item = &str> {
+        self.sensors.keys().map(String::as_str)
+    }
+
+    /// Process a discovery config message.
+    ///
+    /// Returns `None` when the topic is not a sensor config topic, the payload
+    /// is malformed, or the sensor cannot be bound to a known metric kind. An
+    /// update that can no longer be bound drops the sensor's earlier binding.
+    pub fn handle_config(&mut self, topic: &str, payload: &[u8]) -> Option<DiscoveryUpdate> {
+        let (_node, object_id) = parse_config_topic(&self.prefix, topic)?;
+
+        if payload.iter().all(u8::is_ascii_whitespace) {
+            return self.unbind(topic);
+        }
+
+        let parsed: DiscoveryPayload = match serde_json::from_slice(payload) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                warn!(%topic, "ignoring malformed discovery payload: {err}");
+                return None;
+            }
+        };
+
+        let Some(state_topic) = parsed.state_topic.as_deref() else {
+            debug!(%topic, "discovery record has no state topic");
+            return self.unbind(topic);
+        };
+        let state_topic = expand_base(state_topic, parsed.base_topic.as_deref());
+        let object_id = parsed.object_id.clone().unwrap_or(object_id);
+        let Some(kind) = kind_for(
+            parsed.device_class.as_deref(),
+            parsed.unit_of_measurement.as_deref(),
+            &object_id,
+        ) else {
+            debug!(%topic, %object_id, "discovery record has no matching metric kind");
+            return self.unbind(topic);
+        };
+
+        let device = parsed
+            .device
+            .as_ref()
+            .and_then(|d| d.name.clone().or_else(|| d.identifiers.first().cloned()));
+
+        if let Some(previous) = self
+            .config_topics
+            .insert(topic.to_string(), state_topic.clone())
+            && previous != state_topic
+        {
+            self.sensors.remove(&previous);
+        }
+        self.sensors.insert(
+            state_topic.clone(),
+            DiscoveredSensor {
+                kind,
+                object_id,
+                unit: parsed.unit_of_measurement,
+                device,
+            },
+        );
+        debug!(%topic, %state_topic, kind, "discovery record added");
+        Some(DiscoveryUpdate::Added { state_topic, kind })
+    }
+
+    /// Forget the sensor announced on a config topic, if any.
+    fn unbind(&mut self, topic: &str) -> Option<DiscoveryUpdate> {
+        let state_topic = self.config_topics.remove(topic)?;
+        self.sensors.remove(&state_topic);
+        debug!(%topic, %state_topic, "discovery record removed");
+        Some(DiscoveryUpdate::Removed { state_topic })
+    }
+}
+
+/// Split `<prefix>/sensor/[<node>/]<object>/config` into `(node, object)`.
+fn parse_config_topic(prefix: &str, topic: &str) -> Option<(Option<String>, String)> {
+    let rest = topic.strip_prefix(prefix)?.strip_prefix('/')?;
+    let rest = rest.strip_prefix("sensor/")?.strip_suffix("/config")?;
+    let segments = rest.split('/').collect::<Vec<_>>();
+    match segments.as_slice() {
+        [object] if !object.is_empty() => Some((None, object.to_string())),
+        [node, object] if !node.is_empty() && !object.is_empty() => {
+            Some((Some(node.to_string()), object.to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// Expand the `~` abbreviation used by compact discovery payloads.
+fn expand_base(topic: &str, base: Option<&str>) -> String {
+    match base {
+        Some(base) if topic.starts_with('~') => format!("{base}{}", &topic[1..]),
+        Some(base) if topic.ends_with('~') => format!("{}{base}", &topic[..topic.len() - 1]),
+        _ => topic.to_string(),
+    }
+}
+
+/// Bind a discovery record to a metric kind, preferring the device class.
+pub(crate) fn kind_for(
+    device_class: Option<&str>,
+    unit: Option<&str>,
+    object_id: &str,
+) -> Option<&'static str> {
+    let by_class = match device_class.map(str::to_ascii_lowercase).as_deref() {
+        Some("pm1") => Some("pm1"),
+        Some("pm25") => Some("pm25"),
+        Some("pm10") => Some("pm10"),
+        Some("carbon_dioxide") => Some("co2"),
+        Some("volatile_organic_compounds") | Some("volatile_organic_compounds_parts") => {
+            Some("tvoc")
+        }
+        Some("temperature") => Some("temperature"),
+        Some("pressure") | Some("atmospheric_pressure") => Some("pressure"),
+        Some("carbon_monoxide") => Some("co"),
+        Some("nitrogen_dioxide") => Some("no2"),
+        Some("humidity") => Some("humidity"),
+        _ => None,
+    };
+    if by_class.is_some() {
+        return by_class;
+    }
+
+    let guessed = crate::mqtt::map_sensor_kind(object_id)?;
+    // Reject guesses that contradict the announced unit, e.g. a "temp_offset"
+    // number entity reported in seconds.
+    let unit_ok = match (guessed, unit) {
+        (_, None) => true,
+        ("temperature" | "humidity" | "co2" | "pm1" | "pm25" | "pm10", Some(u)) => {
+            crate::units::to_canonical(guessed, 0.0, u).is_some()
+        }
+        _ => true,
+    };
+    unit_ok.then_some(guessed)
+}
+
+fn string_or_list<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(s) => vec![s],
+        OneOrMany::Many(v) => v,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_binds_state_topic_by_device_class() {
+        let mut registry = DiscoveryRegistry::new("homeassistant");
+        let payload = r#"{
+            "name": "PM2.5",
+            "state_topic": "apollo-air-1/sensor/pm_2_5/state",
+            "device_class": "pm25",
+            "unit_of_measurement": "µg/m³",
+            "device": {"identifiers": ["a1b2"], "name": "Living Room AIR-1"}
+        }"#;
+
+        let update = registry.handle_config(
+            "homeassistant/sensor/air1/pm_2_5/config",
+            payload.as_bytes(),
+        );
+
+        assert_eq!(
+            update,
+            Some(DiscoveryUpdate::Added {
+                state_topic: "apollo-air-1/sensor/pm_2_5/state".to_string(),
+                kind: "pm25",
+            })
+        );
+        let sensor = registry
+            .lookup("apollo-air-1/sensor/pm_2_5/state")
+            .expect("sensor registered");
+        assert_eq!(sensor.device.as_deref(), Some("Living Room AIR-1"));
+        assert_eq!(sensor.unit.as_deref(), Some("µg/m³"));
+    }
+
+    #[test]
+    fn abbreviated_payload_and_removal() {
+        let mut registry = DiscoveryRegistry::new("homeassistant");
+        let topic = "homeassistant/sensor/air1_co2/config";
+        let payload = br#"{"~": "air1/sensor/co2", "stat_t": "~/state", "dev_cla": "carbon_dioxide", "dev": {"ids": "air1"}}"#;
+
+        registry.handle_config(topic, payload);
+        let sensor = registry
+            .lookup("air1/sensor/co2/state")
+            .expect("expanded topic");
+        assert_eq!(sensor.kind, "co2");
+        assert_eq!(sensor.device.as_deref(), Some("air1"));
+
+        let removed = registry.handle_config(topic, b"");
+        assert_eq!(
+            removed,
+            Some(DiscoveryUpdate::Removed {
+                state_topic: "air1/sensor/co2/state".to_string()
+            })
+        );
+        assert!(registry.lookup("air1/sensor/co2/state").is_none());
+    }
+
+    #[test]
+    fn unit_mismatch_rejects_name_guess() {
+        let mut registry = DiscoveryRegistry::new("homeassistant");
+        let payload =
+            br#"{"state_topic": "air1/temperature_offset/state", "unit_of_measurement": "s"}"#;
+
+        let update = registry.handle_config(
+            "homeassistant/sensor/air1/temperature_offset/config",
+            payload,
+        );
+
+        assert!(update.is_none());
+        assert!(!registry.is_config_topic("homeassistant/binary_sensor/air1/x/config"));
+    }
+
+    #[test]
+    fn unmappable_update_drops_the_old_binding() {
+        let mut registry = DiscoveryRegistry::new("homeassistant");
+        let topic = "homeassistant/sensor/air1/sensor_a/config";
+        registry.handle_config(
+            topic,
+            br#"{"state_topic": "air1/sensor/co2/state", "device_class": "carbon_dioxide"}"#,
+        );
+        assert!(registry.lookup("air1/sensor/co2/state").is_some());
+
+        let update = registry.handle_config(
+            topic,
+            br#"{"state_topic": "air1/sensor/co2/state", "device_class": "enum"}"#,
+        );
+        assert_eq!(
+            update,
+            Some(DiscoveryUpdate::Removed {
+                state_topic: "air1/sensor/co2/state".to_string()
+            })
+        );
+        assert!(registry.lookup("air1/sensor/co2/state").is_none());
+        assert_eq!(registry.state_topics().count(), 0);
+    }
+}
@@ -0,0 +1,283 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write as _,
+    time::{Duration, Instant},
+};
+
+use crate::config::MappingRule;
+use crate::mqtt::{MapRoute, Received};
+use crate::wizard::{ScannedTopic, TopicScan};
+
+/// Window the message rate is averaged over.
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Topics tracked before new ones are left out.
+pub const EXPLORER_CAPACITY: usize = 5000;
+
+/// Bytes of each topic's last payload that are kept.
+pub const MAX_PAYLOAD: usize = 4096;
+
+/// Traffic seen on one topic.
+#[derive(Debug, Clone)]
+pub struct TopicStats {
+    /// Most recent publish on the topic.
+    pub last: Received,
+    /// Messages received since the explorer was cleared.
+    pub messages: u64,
+    arrivals: VecDeque<Instant>,
+}
+
+impl TopicStats {
+    /// Messages received over the last minute.
+    pub fn rate_per_minute(&self, now: Instant) -> usize {
+        self.arrivals
+            .iter()
+            .filter(|at| now.duration_since(**at) < RATE_WINDOW)
+            .count()
+    }
+
+    /// How the last publish was mapped, e.g. `mapping rule: pm25 = 4.5`.
+    pub fn mapping(&self) -> String {
+        self.last.describe()
+    }
+
+    /// Whether the mapper turned the topic into anything.
+    pub fn is_mapped(&self) -> bool {
+        self.last.route != MapRoute::Unmapped
+    }
+
+    /// Rule the last payload suggests: the numeric value, or the JSON field,
+    /// whose metric is easiest to guess. `None` for non-numeric payloads.
+    pub fn suggested_rule(&self, mappings: &[MappingRule]) -> Option<ScannedTopic> {
+        let mut scan = TopicScan::default();
+        scan.record(&self.last.topic, &self.last.payload);
+        let topics = scan.into_topics(mappings);
+        let best = topics
+            .iter()
+            .position(|t| t.proposed.is_some() && !t.mapped)
+            .unwrap_or(0);
+        topics.into_iter().nth(best)
+    }
+}
+
+/// Topics received on the listener's subscriptions, in topic order, up to
+/// [`EXPLORER_CAPACITY`].
+#[derive(Debug, Default)]
+pub struct TopicExplorer {
+    topics: BTreeMap<String, TopicStats>,
+    /// Publishes on topics left out because the explorer was full.
+    skipped: u64,
+}
+
+impl TopicExplorer {
+    /// Track a publish, keeping at most [`MAX_PAYLOAD`] bytes of its payload.
+    pub fn record(&mut self, mut msg: Received, now: Instant) {
+        msg.payload.truncate(MAX_PAYLOAD);
+        let full = self.topics.len() >= EXPLORER_CAPACITY;
+        match self.topics.get_mut(&msg.topic) {
+            Some(stats) => {
+                while stats
+                    .arrivals
+                    .front()
+                    .is_some_and(|at| now.duration_since(*at) >= RATE_WINDOW)
+                {
+                    stats.arrivals.pop_front();
+                }
+                stats.arrivals.push_back(now);
+                stats.messages += 1;
+                stats.last = msg;
+            }
+            None if full => self.skipped += 1,
+            None => {
+                self.topics.insert(
+                    msg.topic.clone(),
+                    TopicStats {
+                        last: msg,
+                        messages: 1,
+                        arrivals: VecDeque::from([now]),
+                    },
+                );
+            }
+        }
+    }
+
+    pub fn get(&self, topic: &str) -> Option<&TopicStats> {
+        self.topics.get(topic)
+    }
+
+    pub fn topics(&self) -> impl Iterator<Item = &TopicStats> {
+        self.topics.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.topics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.topics.is_empty()
+    }
+
+    /// Number of topics the mapper dropped.
+    pub fn unmapped(&self) -> usize {
+        self.topics.values().filter(|s| !s.is_mapped()).count()
+    }
+
+    /// Publishes left out because the explorer was full.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    pub fn clear(&mut self) {
+        self.topics.clear();
+        self.skipped = 0;
+    }
+}
+
+/// Payload as one line of text, cut to `max` characters; `None` when it is
+/// not UTF-8.
+pub fn payload_text(payload: &[u8], max: usize) -> Option<String> {
+    let text = std::str::from_utf8(payload).ok()?;
+    let mut line: String = text
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .take(max)
+        .collect();
+    if text.chars().count() > max {
+        line.push('…');
+    }
+    Some(line)
+}
+
+/// Payload as space-separated hex bytes, cut to `max` bytes.
+pub fn payload_hex(payload: &[u8], max: usize) -> String {
+    let mut hex = String::with_capacity(payload.len().min(max) * 3);
+    for (idx, byte) in payload.iter().take(max).enumerate() {
+        if idx > 0 {
+            hex.push(' ');
+        }
+        let _ = write!(hex, "{byte:02x}");
+    }
+    if payload.len() > max {
+        hex.push_str(" …");
+    }
+    hex
+}
+
+/// Text preview of a payload, falling back to hex for binary payloads.
+pub fn payload_preview(payload: &[u8], max: usize) -> String {
+    payload_text(payload, max).unwrap_or_else(|| payload_hex(payload, max / 3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn received(topic: &str, payload: &str, route: MapRoute, outcome: &[&str]) -> Received {
+        Received {
+            topic: topic.to_string(),
+            payload: payload.as_bytes().to_vec(),
+            retain: false,
+            qos: 0,
+            received: std::time::SystemTime::UNIX_EPOCH,
+            route,
+            outcome: outcome.iter().map(|o| o.to_string()).collect(),
+            decode_failed: false,
+        }
+    }
+
+    #[test]
+    fn topics_keep_last_publish_rate_and_mapping() {
+        let start = Instant::now();
+        let mut explorer = TopicExplorer::default();
+        explorer.record(
+            received(
+                "air1/sensor/co2/state",
+                "610",
+                MapRoute::SensorName,
+                &["co2 = 610"],
+            ),
+            start,
+        );
+        let mut retained = received("air1/sensor/co2/state", "612", MapRoute::SensorName, &[]);
+        retained.retain = true;
+        explorer.record(retained, start + Duration::from_secs(30));
+        explorer.record(
+            received("lab/fan", "on", MapRoute::Unmapped, &[]),
+            start + Duration::from_secs(40),
+        );
+
+        assert_eq!(explorer.len(), 2);
+        assert_eq!(explorer.unmapped(), 1);
+        let co2 = explorer.get("air1/sensor/co2/state").unwrap();
+        assert_eq!(co2.messages, 2);
+        assert!(co2.last.retain);
+        assert_eq!(co2.last.payload, b"612");
+        assert_eq!(co2.rate_per_minute(start + Duration::from_secs(45)), 2);
+        assert_eq!(co2.rate_per_minute(start + Duration::from_secs(75)), 1);
+        assert_eq!(co2.mapping(), "sensor name");
+        assert_eq!(explorer.get("lab/fan").unwrap().mapping(), "unmapped");
+
+        let topics: Vec<&str> = explorer.topics().map(|s| s.last.topic.as_str()).collect();
+        assert_eq!(topics, vec!["air1/sensor/co2/state", "lab/fan"]);
+    }
+
+    #[test]
+    fn unmapped_json_topics_suggest_a_field_rule() {
+        let mut explorer = TopicExplorer::default();
+        explorer.record(
+            received(
+                "zigbee2mqtt/office",
+                r#"{"linkquality": 120, "humidity": 48.5}"#,
+                MapRoute::Unmapped,
+                &[],
+            ),
+            Instant::now(),
+        );
+        let stats = explorer.get("zigbee2mqtt/office").unwrap();
+        let suggestion = stats.suggested_rule(&[]).unwrap();
+        assert_eq!(suggestion.path.as_deref(), Some("/humidity"));
+        assert_eq!(suggestion.proposed.as_deref(), Some("humidity"));
+
+        explorer.record(
+            received("lab/fan", "on", MapRoute::Unmapped, &[]),
+            Instant::now(),
+        );
+        assert!(
+            explorer
+                .get("lab/fan")
+                .unwrap()
+                .suggested_rule(&[])
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn explorer_is_bounded() {
+        let mut explorer = TopicExplorer::default();
+        let now = Instant::now();
+        for idx in 0..=EXPLORER_CAPACITY {
+            explorer.record(
+                received(&format!("lab/{idx}"), "1", MapRoute::Unmapped, &[]),
+                now,
+            );
+        }
+        assert_eq!(explorer.len(), EXPLORER_CAPACITY);
+        assert_eq!(explorer.skipped(), 1);
+
+        let large = "x".repeat(MAX_PAYLOAD * 2);
+        explorer.record(received("lab/0", &large, MapRoute::Unmapped, &[]), now);
+        assert_eq!(
+            explorer.get("lab/0").unwrap().last.payload.len(),
+            MAX_PAYLOAD
+        );
+        assert_eq!(explorer.get("lab/0").unwrap().messages, 2);
+    }
+
+    #[test]
+    fn payloads_preview_as_text_or_hex() {
+        assert_eq!(payload_preview(b"12.5\n", 10), "12.5 ");
+        assert_eq!(payload_preview(b"abcdefgh", 4), "abcd…");
+        assert_eq!(payload_hex(&[0x00, 0xff, 0x10], 8), "00 ff 10");
+        assert_eq!(payload_preview(&[0xff, 0xfe, 0x01, 0x02], 6), "ff fe …");
+    }
+}
@@ -0,0 +1,321 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{BufWriter, Write as _},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use serde_json::json;
+
+use crate::mqtt::{self, Received};
+
+/// Messages kept in the traffic log before the oldest are dropped.
+pub const TRAFFIC_CAPACITY: usize = 2000;
+
+/// Bounded log of raw publishes, oldest first.
+#[derive(Debug)]
+pub struct TrafficLog {
+    entries: VecDeque<Received>,
+    capacity: usize,
+    paused: bool,
+    /// Messages not logged while paused.
+    skipped: u64,
+    /// Bumped on every change, so views only redraw when needed.
+    version: u64,
+    /// Publishes logged so far; the n-th one is number n.
+    pushed: u64,
+}
+
+impl Default for TrafficLog {
+    fn default() -> Self {
+        Self::with_capacity(TRAFFIC_CAPACITY)
+    }
+}
+
+impl TrafficLog {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity.min(TRAFFIC_CAPACITY)),
+            capacity: capacity.max(1),
+            paused: false,
+            skipped: 0,
+            version: 0,
+            pushed: 0,
+        }
+    }
+
+    /// Log a publish, dropping the oldest entry when full. Paused logs only
+    /// count what they miss.
+    pub fn push(&mut self, msg: Received) {
+        if self.paused {
+            self.skipped += 1;
+            return;
+        }
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(msg);
+        self.version += 1;
+        self.pushed += 1;
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        if paused && !self.paused {
+            self.skipped = 0;
+        }
+        self.paused = paused;
+        self.version += 1;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Messages missed during the current or last pause.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Number of the newest publish ever logged, 0 before the first.
+    pub fn pushed(&self) -> u64 {
+        self.pushed
+    }
+
+    /// Number of the oldest publish still logged; everything below it was
+    /// dropped or cleared.
+    pub fn oldest(&self) -> u64 {
+        self.pushed + 1 - self.entries.len() as u64
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.version += 1;
+    }
+
+    /// Logged publishes matching `filter`, oldest first.
+    pub fn matching<'a>(
+        &'a self,
+        filter: &'a TrafficFilter,
+    ) -> impl DoubleEndedIterator<Item = &'a Received> {
+        self.entries.iter().filter(|msg| filter.matches(msg))
+    }
+
+    /// Logged publishes numbered above `after` and matching `filter`, with
+    /// their numbers, oldest first.
+    pub fn matching_after<'a>(
+        &'a self,
+        filter: &'a TrafficFilter,
+        after: u64,
+    ) -> impl DoubleEndedIterator<Item = (u64, &'a Received)> {
+        let oldest = self.oldest();
+        let skip = after
+            .saturating_sub(oldest - 1)
+            .min(self.entries.len() as u64) as usize;
+        self.entries
+            .range(skip..)
+            .enumerate()
+            .filter(|(_, msg)| filter.matches(msg))
+            .map(move |(idx, msg)| (oldest + (skip + idx) as u64, msg))
+    }
+}
+
+/// Topic and payload filter for the traffic log. Empty fields match
+/// everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrafficFilter {
+    /// MQTT filter when it has wildcards, otherwise a topic substring.
+    pub topic: String,
+    /// Case-insensitive payload substring.
+    pub payload: String,
+}
+
+impl TrafficFilter {
+    pub fn matches(&self, msg: &Received) -> bool {
+        let topic = self.topic.trim();
+        let topic_ok = topic.is_empty()
+            || if topic.contains(['+', '#']) {
+                mqtt::topic_matches(topic, &msg.topic)
+            } else {
+                msg.topic.contains(topic)
+            };
+        let payload = self.payload.trim().to_lowercase();
+        topic_ok
+            && (payload.is_empty()
+                || String::from_utf8_lossy(&msg.payload)
+                    .to_lowercase()
+                    .contains(&payload))
+    }
+}
+
+/// UTC time of day with milliseconds, e.g. `14:03:27.512`.
+pub fn clock(time: SystemTime) -> String {
+    timestamp(time).get(11..23).unwrap_or_default().to_string()
+}
+
+/// RFC 3339 UTC timestamp with milliseconds.
+pub fn timestamp(time: SystemTime) -> String {
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_millis())
+        .unwrap_or_default();
+    match x509_cert::der::DateTime::from_system_time(time) {
+        Ok(t) => format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{millis:03}Z",
+            t.year(),
+            t.month(),
+            t.day(),
+            t.hour(),
+            t.minutes(),
+            t.seconds()
+        ),
+        Err(_) => "out of range".to_string(),
+    }
+}
+
+/// One JSON object per publish. UTF-8 payloads are kept as text, anything
+/// else as `payload_hex`.
+pub fn to_json(msg: &Received) -> serde_json::Value {
+    let mut line = json!({
+        "time": timestamp(msg.received),
+        "unix_ms": msg
+            .received
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default(),
+        "topic": msg.topic,
+        "retain": msg.retain,
+        "qos": msg.qos,
+        "route": msg.route.label(),
+        "outcome": msg.outcome,
+        "decode_failed": msg.decode_failed,
+    });
+    match std::str::from_utf8(&msg.payload) {
+        Ok(text) => line["payload"] = json!(text),
+        Err(_) => {
+            let hex: String = msg.payload.iter().map(|b| format!("{b:02x}")).collect();
+            line["payload_hex"] = json!(hex);
+        }
+    }
+    line
+}
+
+/// Write publishes to `path` as JSON Lines; returns how many were written.
+pub fn export_jsonl<'a>(path: &Path, entries: impl Iterator<Item = &'a Received>) -> Result<usize> {
+    let file =
+        fs::File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+    let mut out = BufWriter::new(file);
+    let mut written = 0;
+    for msg in entries {
+        serde_json::to_writer(&mut out, &to_json(msg))?;
+        out.write_all(b"\n")?;
+        written += 1;
+    }
+    out.flush()
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mqtt::MapRoute;
+    use std::time::Duration;
+
+    fn received(topic: &str, payload: &[u8]) -> Received {
+        Received {
+            topic: topic.to_string(),
+            payload: payload.to_vec(),
+            retain: false,
+            qos: 1,
+            received: UNIX_EPOCH + Duration::from_millis(1_760_000_000_250),
+            route: MapRoute::Unmapped,
+            outcome: Vec::new(),
+            decode_failed: false,
+        }
+    }
+
+    #[test]
+    fn log_is_bounded_and_pausable() {
+        let mut log = TrafficLog::with_capacity(2);
+        for payload in ["1", "2", "3"] {
+            log.push(received("lab/co2", payload.as_bytes()));
+        }
+        let all = TrafficFilter::default();
+        let payloads: Vec<&[u8]> = log.matching(&all).map(|m| m.payload.as_slice()).collect();
+        assert_eq!(payloads, vec![b"2", b"3"]);
+
+        log.set_paused(true);
+        log.push(received("lab/co2", b"4"));
+        assert_eq!(log.len(), 2);
+        assert_eq!(log.skipped(), 1);
+    }
+
+    #[test]
+    fn new_entries_are_numbered_across_drops_and_clears() {
+        let mut log = TrafficLog::with_capacity(2);
+        for payload in ["1", "2", "3"] {
+            log.push(received("lab/co2", payload.as_bytes()));
+        }
+        assert_eq!((log.oldest(), log.pushed()), (2, 3));
+        let all = TrafficFilter::default();
+        let newer: Vec<(u64, &[u8])> = log
+            .matching_after(&all, 2)
+            .map(|(n, m)| (n, m.payload.as_slice()))
+            .collect();
+        assert_eq!(newer, vec![(3, b"3".as_slice())]);
+        assert_eq!(log.matching_after(&all, 0).count(), 2);
+
+        log.clear();
+        log.push(received("lab/co2", b"4"));
+        assert_eq!((log.oldest(), log.pushed()), (4, 4));
+        assert_eq!(log.matching_after(&all, 3).count(), 1);
+    }
+
+    #[test]
+    fn filters_match_topic_and_payload() {
+        let msg = received("homeassistant/light/kitchen/set", br#"{"state": "ON"}"#);
+        let filter = |topic: &str, payload: &str| TrafficFilter {
+            topic: topic.to_string(),
+            payload: payload.to_string(),
+        };
+        assert!(filter("kitchen", "").matches(&msg));
+        assert!(filter("homeassistant/+/+/set", "on").matches(&msg));
+        assert!(!filter("homeassistant/sensor/#", "").matches(&msg));
+        assert!(!filter("", "off").matches(&msg));
+    }
+
+    #[test]
+    fn export_writes_one_json_object_per_line() {
+        let mut binary = received("lab/raw", &[0xff, 0x01]);
+        binary.retain = true;
+        let entries = [received("lab/co2", b"612"), binary];
+        let path = std::env::temp_dir().join(format!("air1-traffic-{}.jsonl", std::process::id()));
+        assert_eq!(export_jsonl(&path, entries.iter()).unwrap(), 2);
+        let text = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines[0]["time"], "2025-10-09T08:53:20.250Z");
+        assert_eq!(lines[0]["payload"], "612");
+        assert_eq!(lines[1]["payload_hex"], "ff01");
+        assert_eq!(lines[1]["retain"], true);
+        assert_eq!(clock(entries[0].received), "08:53:20.250");
+    }
+}
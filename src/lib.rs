@@ -2,8 +2,21 @@
 //!
 //! This module exposes the main application components for testing and external use.
 
+pub mod adapters;
 pub mod app;
+pub mod capture;
 pub mod config;
+pub mod decode;
+pub mod diagnostics;
+pub mod discovery;
+pub mod explorer;
+pub mod inspector;
+pub mod mapping;
+pub mod metrics;
 pub mod mqtt;
+pub mod mqtt5;
 pub mod secrets;
+pub mod tls;
 pub mod ui;
+pub mod units;
+pub mod wizard;
@@ -1,12 +1,55 @@
+mod adapters;
 mod app;
+mod capture;
 mod config;
+mod decode;
+mod diagnostics;
+mod discovery;
+mod explorer;
+mod inspector;
+mod mapping;
+mod metrics;
 mod mqtt;
+mod mqtt5;
 mod secrets;
+mod tls;
 mod ui;
+mod units;
+mod wizard;
+
+use std::path::PathBuf;
 
 use gtk4::prelude::*;
 use tracing_subscriber::EnvFilter;
 
+/// Split off `--replay <capture>` and `--speed <factor|max>`; the remaining
+/// arguments are passed on to GTK.
+fn parse_args(
+    mut args: impl Iterator<Item = String>,
+) -> anyhow::Result<(Option<(PathBuf, capture::ReplaySpeed)>, Vec<String>)> {
+    let mut replay = None;
+    let mut speed = capture::ReplaySpeed::Scaled(1.0);
+    let mut rest = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--replay" => {
+                let path = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("--replay needs a capture file"))?;
+                replay = Some(PathBuf::from(path));
+            }
+            "--speed" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("--speed needs a factor or max"))?;
+                speed = value.parse()?;
+            }
+            _ => rest.push(arg),
+        }
+    }
+    Ok((replay.map(|path| (path, speed)), rest))
+}
+
 fn main() -> gtk4::glib::ExitCode {
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env())
@@ -19,14 +62,25 @@ fn main() -> gtk4::glib::ExitCode {
     let full_version = format!("{}.r{}", version, git_count);
     let window_title = format!("Air 1 MQTT Monitor v{full_version}");
 
+    let (replay, gtk_args) = match parse_args(std::env::args()) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            tracing::error!("{err:#}");
+            return gtk4::glib::ExitCode::FAILURE;
+        }
+    };
+
     let gtk_app = gtk4::Application::builder()
         .application_id("com.air1.monitor")
         .build();
 
     gtk_app.connect_activate(move |gtk_app| {
         let state = std::rc::Rc::new(std::cell::RefCell::new(app::Air1App::init()));
+        if let Some((path, speed)) = &replay {
+            state.borrow_mut().start_replay(path, *speed);
+        }
         ui::build_ui(gtk_app, state, &window_title);
     });
 
-    gtk_app.run()
+    gtk_app.run_with_args(&gtk_args)
 }
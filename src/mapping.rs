@@ -0,0 +1,179 @@
+use regex::Regex;
+use tracing::warn;
+
+use crate::config::{MappingRule, TopicMatch};
+use crate::metrics;
+use crate::mqtt::topic_matches;
+
+enum Matcher {
+    Exact(String),
+    Wildcard(String),
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn is_match(&self, topic: &str) -> bool {
+        match self {
+            Matcher::Exact(pattern) => pattern == topic,
+            Matcher::Wildcard(filter) => topic_matches(filter, topic),
+            Matcher::Regex(re) => re.is_match(topic),
+        }
+    }
+}
+
+/// A mapping rule compiled for matching.
+pub struct CompiledRule {
+    matcher: Matcher,
+    /// Metric identifier the rule feeds.
+    pub metric: String,
+    /// JSON path of the value, if the payload is JSON.
+    pub path: Option<String>,
+    /// Factor applied to the parsed value.
+    pub scale: f64,
+    /// Display unit override.
+    pub unit: Option<String>,
+    device: Option<String>,
+}
+
+impl CompiledRule {
+    /// Device named by the rule for `topic`, expanding regex capture groups.
+    pub fn device(&self, topic: &str) -> Option<String> {
+        let template = self.device.as_deref()?;
+        match &self.matcher {
+            Matcher::Regex(re) => {
+                let captures = re.captures(topic)?;
+                let mut device = String::new();
+                captures.expand(template, &mut device);
+                Some(device).filter(|d| !d.is_empty())
+            }
+            _ => Some(template.to_string()),
+        }
+    }
+}
+
+/// Ordered set of user mapping rules.
+///
+/// Every matching rule applies, so one JSON message can feed several metrics;
+/// when several rules feed the same metric the earliest one wins.
+#[derive(Default)]
+pub struct RuleSet {
+    rules: Vec<CompiledRule>,
+}
+
+impl RuleSet {
+    /// Compile rules from config, skipping (and logging) invalid ones.
+    pub fn compile(rules: &[MappingRule]) -> Self {
+        let rules = rules
+            .iter()
+            .filter_map(|rule| {
+                let matcher = match rule.match_type {
+                    TopicMatch::Exact => Matcher::Exact(rule.topic.trim().to_string()),
+                    TopicMatch::Wildcard => Matcher::Wildcard(rule.topic.trim().to_string()),
+                    TopicMatch::Regex => match Regex::new(&rule.topic) {
+                        Ok(re) => Matcher::Regex(re),
+                        Err(err) => {
+                            warn!("skipping mapping rule {}: {err}", rule.topic);
+                            return None;
+                        }
+                    },
+                };
+                Some(CompiledRule {
+                    matcher,
+                    metric: rule.metric.trim().to_string(),
+                    path: rule.path.clone().filter(|p| !p.trim().is_empty()),
+                    scale: rule.scale,
+                    unit: rule.unit.clone().filter(|u| !u.trim().is_empty()),
+                    device: rule.device.clone().filter(|d| !d.trim().is_empty()),
+                })
+            })
+            .collect();
+        Self { rules }
+    }
+
+    /// Return true if there are no rules.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Return the rules that apply to the topic, at most one per metric;
+    /// aliases of a metric count as the same metric.
+    pub fn matching(&self, topic: &str) -> Vec<&CompiledRule> {
+        let mut matched: Vec<&CompiledRule> = Vec::new();
+        for rule in self
+            .rules
+            .iter()
+            .filter(|rule| rule.matcher.is_match(topic))
+        {
+            let metric = metrics::canonical_id(&rule.metric);
+            if !matched
+                .iter()
+                .any(|m| metrics::canonical_id(&m.metric) == metric)
+            {
+                matched.push(rule);
+            }
+        }
+        matched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(topic: &str, match_type: TopicMatch, metric: &str) -> MappingRule {
+        MappingRule {
+            topic: topic.to_string(),
+            match_type,
+            metric: metric.to_string(),
+            path: None,
+            scale: 1.0,
+            unit: None,
+            device: None,
+        }
+    }
+
+    #[test]
+    fn rules_match_exact_wildcard_and_regex() {
+        let rules = RuleSet::compile(&[
+            rule("office/air/pm25", TopicMatch::Exact, "pm25"),
+            rule("office/+/co2", TopicMatch::Wildcard, "co2"),
+            rule(r"^lab/.*/temp_f$", TopicMatch::Regex, "temperature"),
+        ]);
+
+        let metric = |topic| rules.matching(topic).first().map(|r| r.metric.clone());
+        assert_eq!(metric("office/air/pm25").as_deref(), Some("pm25"));
+        assert_eq!(metric("office/air/pm25/raw"), None);
+        assert_eq!(metric("office/desk/co2").as_deref(), Some("co2"));
+        assert_eq!(metric("lab/bench/2/temp_f").as_deref(), Some("temperature"));
+        assert_eq!(metric("lab/bench/2/temp_c"), None);
+    }
+
+    #[test]
+    fn first_rule_per_metric_wins_and_invalid_rules_are_skipped() {
+        let rules = RuleSet::compile(&[
+            rule("[", TopicMatch::Regex, "broken"),
+            rule("air/#", TopicMatch::Wildcard, "pm10"),
+            rule("air/pm25", TopicMatch::Exact, "pm25"),
+            rule("air/+", TopicMatch::Wildcard, "pm10"),
+            rule("air/pm25", TopicMatch::Exact, "pm2_5"),
+        ]);
+
+        let matched = rules.matching("air/pm25");
+        let metrics: Vec<&str> = matched.iter().map(|r| r.metric.as_str()).collect();
+        assert_eq!(metrics, vec!["pm10", "pm25"]);
+        assert!(matches!(matched[0].matcher, Matcher::Wildcard(ref f) if f == "air/#"));
+    }
+
+    #[test]
+    fn rule_devices_expand_regex_captures() {
+        let mut by_room = rule(r"^(?P<room>\w+)/air/pm25$", TopicMatch::Regex, "pm25");
+        by_room.device = Some("${room}-air".to_string());
+        let mut fixed = rule("lab/pm10", TopicMatch::Exact, "pm10");
+        fixed.device = Some("lab".to_string());
+        let rules = RuleSet::compile(&[by_room, fixed]);
+
+        let device = |topic| rules.matching(topic).first().and_then(|r| r.device(topic));
+        assert_eq!(device("bedroom/air/pm25").as_deref(), Some("bedroom-air"));
+        assert_eq!(device("lab/pm10").as_deref(), Some("lab"));
+    }
+}
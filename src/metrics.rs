@@ -0,0 +1,702 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use crate::config::{SourceMode, SourcePolicy};
+
+/// Quality tiers as `(min, max, label)`, in ascending order.
+pub type QualityRanges = &'static [(f64, f64, &'static str)];
+
+/// Static description of a metric the dashboard knows how to show.
+#[derive(Debug)]
+pub struct MetricDescriptor {
+    /// Canonical metric id (e.g. "pm25").
+    pub id: &'static str,
+    /// Other ids mappers and mapping rules may use for the metric.
+    pub aliases: &'static [&'static str],
+    /// Display label.
+    pub label: &'static str,
+    /// Dashboard unit; readings are converted into it where possible.
+    pub unit: &'static str,
+    /// Quality tiers used for colouring and labels (empty for count bins).
+    pub ranges: QualityRanges,
+    /// Value at the end of the gauge arc (unused for count bins).
+    pub gauge_max: f64,
+    /// Decimal places shown for values.
+    pub precision: usize,
+    /// Dashboard section the gauge belongs to by default.
+    pub section: &'static str,
+}
+
+/// All known metrics, in dashboard order.
+pub const METRICS: &[MetricDescriptor] = &[
+    MetricDescriptor {
+        id: "pm25",
+        aliases: &["pm2_5", "pm2.5"],
+        label: "PM2.5",
+        unit: "μg/m³",
+        ranges: &[
+            (0.0, 12.0, "Good"),
+            (12.0, 35.0, "Moderate"),
+            (35.0, 55.0, "Unhealthy (Sensitive)"),
+            (55.0, 150.0, "Unhealthy"),
+            (150.0, 250.0, "Very Unhealthy"),
+        ],
+        gauge_max: 250.0,
+        precision: 1,
+        section: "air_quality",
+    },
+    MetricDescriptor {
+        id: "pm10",
+        aliases: &[],
+        label: "PM10",
+        unit: "μg/m³",
+        ranges: &[
+            (0.0, 54.0, "Good"),
+            (54.0, 154.0, "Moderate"),
+            (154.0, 254.0, "Unhealthy (Sensitive)"),
+            (254.0, 354.0, "Unhealthy"),
+            (354.0, 424.0, "Very Unhealthy"),
+        ],
+        gauge_max: 500.0,
+        precision: 1,
+        section: "air_quality",
+    },
+    MetricDescriptor {
+        id: "pm1",
+        aliases: &["pm1_0"],
+        label: "PM1",
+        unit: "μg/m³",
+        ranges: &[
+            (0.0, 10.0, "Good"),
+            (10.0, 25.0, "Moderate"),
+            (25.0, 50.0, "Unhealthy"),
+        ],
+        gauge_max: 100.0,
+        precision: 1,
+        section: "air_quality",
+    },
+    MetricDescriptor {
+        id: "pm4",
+        aliases: &["pm4_0"],
+        label: "PM4",
+        unit: "μg/m³",
+        ranges: &[
+            (0.0, 25.0, "Good"),
+            (25.0, 50.0, "Moderate"),
+            (50.0, 100.0, "Unhealthy (Sensitive)"),
+            (100.0, 200.0, "Unhealthy"),
+            (200.0, 300.0, "Very Unhealthy"),
+        ],
+        gauge_max: 300.0,
+        precision: 1,
+        section: "air_quality",
+    },
+    MetricDescriptor {
+        id: "pc_0_3_1",
+        aliases: &["pm_0_3_to_1"],
+        label: "0.3–1 µm",
+        unit: "#/cm³",
+        ranges: &[],
+        gauge_max: 0.0,
+        precision: 0,
+        section: "particle_counts",
+    },
+    MetricDescriptor {
+        id: "pc_1_2_5",
+        aliases: &["pm_1_to_2_5"],
+        label: "1–2.5 µm",
+        unit: "#/cm³",
+        ranges: &[],
+        gauge_max: 0.0,
+        precision: 0,
+        section: "particle_counts",
+    },
+    MetricDescriptor {
+        id: "pc_2_5_4",
+        aliases: &["pm_2_5_to_4"],
+        label: "2.5–4 µm",
+        unit: "#/cm³",
+        ranges: &[],
+        gauge_max: 0.0,
+        precision: 0,
+        section: "particle_counts",
+    },
+    MetricDescriptor {
+        id: "pc_4_10",
+        aliases: &["pm_4_to_10"],
+        label: "4–10 µm",
+        unit: "#/cm³",
+        ranges: &[],
+        gauge_max: 0.0,
+        precision: 0,
+        section: "particle_counts",
+    },
+    MetricDescriptor {
+        id: "co2",
+        aliases: &["carbon_dioxide"],
+        label: "CO₂",
+        unit: "ppm",
+        ranges: &[
+            (0.0, 800.0, "Excellent"),
+            (800.0, 1000.0, "Good"),
+            (1000.0, 1500.0, "Acceptable"),
+            (1500.0, 2000.0, "Poor"),
+            (2000.0, 5000.0, "Bad"),
+        ],
+        gauge_max: 5000.0,
+        precision: 0,
+        section: "gas",
+    },
+    MetricDescriptor {
+        id: "tvoc",
+        aliases: &["voc"],
+        label: "TVOC",
+        unit: "ppb",
+        ranges: &[
+            (0.0, 220.0, "Excellent"),
+            (220.0, 660.0, "Good"),
+            (660.0, 1430.0, "Moderate"),
+            (1430.0, 2200.0, "Poor"),
+            (2200.0, 5500.0, "Unhealthy"),
+        ],
+        gauge_max: 5500.0,
+        precision: 0,
+        section: "gas",
+    },
+    MetricDescriptor {
+        id: "voc_index",
+        aliases: &[],
+        label: "VOC Index",
+        unit: "",
+        ranges: &[
+            (0.0, 150.0, "Good"),
+            (150.0, 250.0, "Moderate"),
+            (250.0, 400.0, "Poor"),
+            (400.0, 500.0, "Unhealthy"),
+        ],
+        gauge_max: 500.0,
+        precision: 0,
+        section: "gas",
+    },
+    MetricDescriptor {
+        id: "nox_index",
+        aliases: &[],
+        label: "NOx Index",
+        unit: "",
+        ranges: &[
+            (0.0, 20.0, "Good"),
+            (20.0, 150.0, "Moderate"),
+            (150.0, 300.0, "Poor"),
+            (300.0, 500.0, "Unhealthy"),
+        ],
+        gauge_max: 500.0,
+        precision: 0,
+        section: "gas",
+    },
+    MetricDescriptor {
+        id: "temperature",
+        aliases: &["temp"],
+        label: "Temperature",
+        unit: "°F",
+        ranges: &[
+            (32.0, 64.0, "Cool"),
+            (64.0, 75.0, "Comfortable"),
+            (75.0, 82.0, "Warm"),
+            (82.0, 104.0, "Hot"),
+        ],
+        gauge_max: 104.0,
+        precision: 1,
+        section: "environment",
+    },
+    MetricDescriptor {
+        id: "humidity",
+        aliases: &[],
+        label: "Humidity",
+        unit: "%",
+        ranges: &[
+            (0.0, 30.0, "Dry"),
+            (30.0, 60.0, "Comfortable"),
+            (60.0, 80.0, "Humid"),
+            (80.0, 100.0, "Very Humid"),
+        ],
+        gauge_max: 100.0,
+        precision: 1,
+        section: "environment",
+    },
+    MetricDescriptor {
+        id: "pressure",
+        aliases: &["atmospheric_pressure"],
+        label: "Pressure",
+        unit: "hPa",
+        ranges: &[
+            (0.0, 980.0, "Low"),
+            (980.0, 1040.0, "Normal"),
+            (1040.0, 1100.0, "High"),
+        ],
+        gauge_max: 1100.0,
+        precision: 1,
+        section: "environment",
+    },
+    MetricDescriptor {
+        id: "co",
+        aliases: &["carbon_monoxide"],
+        label: "CO",
+        unit: "ppm",
+        ranges: &[
+            (0.0, 9.0, "Good"),
+            (9.0, 35.0, "Moderate"),
+            (35.0, 200.0, "Unhealthy"),
+            (200.0, 1000.0, "Dangerous"),
+        ],
+        gauge_max: 1000.0,
+        precision: 1,
+        section: "other_gases",
+    },
+    MetricDescriptor {
+        id: "no2",
+        aliases: &["nitrogen_dioxide"],
+        label: "NO₂",
+        unit: "ppm",
+        ranges: &[
+            (0.0, 0.1, "Good"),
+            (0.1, 0.36, "Moderate"),
+            (0.36, 0.65, "Unhealthy (Sensitive)"),
+            (0.65, 1.25, "Unhealthy"),
+            (1.25, 10.0, "Very Unhealthy"),
+        ],
+        gauge_max: 10.0,
+        precision: 2,
+        section: "other_gases",
+    },
+    MetricDescriptor {
+        id: "ethanol",
+        aliases: &["c2h5oh"],
+        label: "Ethanol",
+        unit: "ppm",
+        ranges: &[
+            (0.0, 50.0, "Low"),
+            (50.0, 200.0, "Elevated"),
+            (200.0, 500.0, "High"),
+        ],
+        gauge_max: 500.0,
+        precision: 1,
+        section: "other_gases",
+    },
+    MetricDescriptor {
+        id: "h2",
+        aliases: &["hydrogen"],
+        label: "H₂",
+        unit: "ppm",
+        ranges: &[
+            (0.0, 100.0, "Low"),
+            (100.0, 500.0, "Elevated"),
+            (500.0, 1000.0, "High"),
+        ],
+        gauge_max: 1000.0,
+        precision: 1,
+        section: "other_gases",
+    },
+    MetricDescriptor {
+        id: "nh3",
+        aliases: &["ammonia"],
+        label: "NH₃",
+        unit: "ppm",
+        ranges: &[
+            (0.0, 25.0, "Good"),
+            (25.0, 50.0, "Moderate"),
+            (50.0, 300.0, "Unhealthy"),
+            (300.0, 500.0, "Dangerous"),
+        ],
+        gauge_max: 500.0,
+        precision: 1,
+        section: "other_gases",
+    },
+    MetricDescriptor {
+        id: "ch4",
+        aliases: &["methane"],
+        label: "CH₄",
+        unit: "ppm",
+        ranges: &[
+            (0.0, 1000.0, "Normal"),
+            (1000.0, 5000.0, "Elevated"),
+            (5000.0, 10000.0, "High"),
+        ],
+        gauge_max: 10000.0,
+        precision: 0,
+        section: "other_gases",
+    },
+];
+
+/// Look up a metric by id or alias (case-insensitive).
+pub fn descriptor(id: &str) -> Option<&'static MetricDescriptor> {
+    let id = id.trim();
+    METRICS.iter().find(|m| {
+        m.id.eq_ignore_ascii_case(id) || m.aliases.iter().any(|a| a.eq_ignore_ascii_case(id))
+    })
+}
+
+/// Canonical id for a metric id or alias; unknown ids are returned as given.
+pub fn canonical_id(id: &str) -> &str {
+    descriptor(id).map_or(id, |m| m.id)
+}
+
+/// Dashboard section holding particle number concentration bins; shown as a
+/// size-distribution histogram rather than gauges.
+pub const PARTICLE_COUNT_SECTION: &str = "particle_counts";
+
+/// Metrics shown by default in a dashboard section.
+pub fn section_metrics(section: &str) -> impl Iterator<Item = &'static MetricDescriptor> + '_ {
+    METRICS.iter().filter(move |m| m.section == section)
+}
+
+/// Latest reading of a metric.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    /// Value in the dashboard unit (or in `unit` when it could not be converted).
+    pub value: f64,
+    /// When the reading arrived.
+    pub timestamp: Instant,
+    /// Topic the reading came from (the latest contributor for aggregates).
+    pub topic: String,
+    /// Number of topics combined into the value (1 unless aggregated).
+    pub sources: usize,
+}
+
+/// Latest readings of one device, keyed by canonical metric id.
+///
+/// Ids without a descriptor (custom mapping rule metrics) are kept too, so
+/// they can be shown once a descriptor exists.
+#[derive(Debug, Clone, Default)]
+pub struct MetricRegistry {
+    /// Value shown for each metric after applying its source policy.
+    readings: BTreeMap<String, Reading>,
+    /// Latest raw reading per metric and source topic.
+    sources: BTreeMap<String, BTreeMap<String, Reading>>,
+    /// First topic seen per metric, for [`SourceMode::First`].
+    first_sources: HashMap<String, String>,
+    /// Display units reported alongside readings, keyed by canonical id.
+    units: HashMap<String, String>,
+    /// Metrics whose active source reported `unavailable`/`unknown`, with
+    /// that source's topic.
+    unavailable: HashMap<String, String>,
+    /// Id of the most recently recorded metric.
+    last: Option<String>,
+}
+
+impl MetricRegistry {
+    /// Store a reading under the canonical id of `id`, resolving several
+    /// source topics with `policy` (latest wins when `None`). Aggregates
+    /// leave out sources silent for longer than `stale_after`.
+    ///
+    /// Returns true when `topic` is a new source the policy does not expect,
+    /// i.e. a second topic for a non-aggregated metric.
+    pub fn record(
+        &mut self,
+        id: &str,
+        value: f64,
+        topic: String,
+        unit: Option<String>,
+        policy: Option<&SourcePolicy>,
+        stale_after: Duration,
+    ) -> bool {
+        let id = canonical_id(id).to_string();
+        let mode = policy.map(|p| p.mode).unwrap_or_default();
+        let preferred = policy
+            .filter(|p| p.mode == SourceMode::Preferred)
+            .and_then(|p| p.topic.as_deref());
+
+        let sources = self.sources.entry(id.clone()).or_default();
+        let unexpected = !sources.is_empty()
+            && !sources.contains_key(&topic)
+            && !mode.is_aggregate()
+            && preferred != Some(topic.as_str());
+        let reading = Reading {
+            value,
+            timestamp: Instant::now(),
+            topic: topic.clone(),
+            sources: 1,
+        };
+        sources.insert(topic.clone(), reading.clone());
+        let first = self
+            .first_sources
+            .entry(id.clone())
+            .or_insert_with(|| topic.clone());
+
+        let active = match mode {
+            SourceMode::Latest => Some(reading),
+            SourceMode::First => (*first == topic).then_some(reading),
+            SourceMode::Preferred => {
+                let use_it = preferred.is_none_or(|p| p == topic || !sources.contains_key(p));
+                use_it.then_some(reading)
+            }
+            SourceMode::Mean | SourceMode::Min | SourceMode::Max => {
+                aggregate(mode, sources, reading, stale_after)
+            }
+        };
+
+        if let Some(reading) = active {
+            // A reading without a unit falls back to the descriptor's, not
+            // to whatever an earlier source reported.
+            match unit {
+                Some(unit) => self.units.insert(id.clone(), unit),
+                None => self.units.remove(&id),
+            };
+            self.unavailable.remove(&id);
+            self.last = Some(id.clone());
+            self.readings.insert(id, reading);
+        }
+        unexpected
+    }
+
+    /// Record that `topic` reported its sensor unavailable. The metric
+    /// becomes unavailable when that topic was its active source; aggregates
+    /// fall back to the remaining sources.
+    pub fn mark_unavailable(
+        &mut self,
+        id: &str,
+        topic: &str,
+        policy: Option<&SourcePolicy>,
+        stale_after: Duration,
+    ) {
+        let id = canonical_id(id).to_string();
+        let mode = policy.map(|p| p.mode).unwrap_or_default();
+        let sources = self.sources.entry(id.clone()).or_default();
+        sources.remove(topic);
+
+        if mode.is_aggregate()
+            && let Some(latest) = sources.values().max_by_key(|r| r.timestamp).cloned()
+            && let Some(reading) = aggregate(mode, sources, latest, stale_after)
+        {
+            self.readings.insert(id, reading);
+            return;
+        }
+        let active = self
+            .readings
+            .get(&id)
+            .is_none_or(|r| r.topic == topic || r.sources > 1);
+        if active {
+            self.readings.remove(&id);
+            self.unavailable.insert(id, topic.to_string());
+        }
+    }
+
+    /// Whether a metric id or alias is currently reported unavailable.
+    pub fn is_unavailable(&self, id: &str) -> bool {
+        self.unavailable.contains_key(canonical_id(id))
+    }
+
+    /// Topics that have reported a metric id or alias, in sorted order.
+    pub fn sources(&self, id: &str) -> impl Iterator<Item = &str> {
+        self.sources
+            .get(canonical_id(id))
+            .into_iter()
+            .flat_map(|s| s.keys().map(String::as_str))
+    }
+
+    /// Latest reading for a metric id or alias.
+    pub fn reading(&self, id: &str) -> Option<&Reading> {
+        self.readings.get(canonical_id(id))
+    }
+
+    /// Latest value for a metric id or alias.
+    pub fn value(&self, id: &str) -> Option<f64> {
+        self.reading(id).map(|r| r.value)
+    }
+
+    /// Display unit: the unit reported with the readings, else the descriptor's.
+    pub fn unit(&self, id: &str) -> &str {
+        self.units
+            .get(canonical_id(id))
+            .map(String::as_str)
+            .or_else(|| descriptor(id).map(|m| m.unit))
+            .unwrap_or_default()
+    }
+
+    /// Most recent reading across all metrics.
+    pub fn last_reading(&self) -> Option<&Reading> {
+        self.readings.get(self.last.as_deref()?)
+    }
+
+    /// All readings, keyed by canonical id.
+    pub fn readings(&self) -> impl Iterator<Item = (&str, &Reading)> {
+        self.readings.iter().map(|(id, r)| (id.as_str(), r))
+    }
+}
+
+/// Combine the latest value of every source heard from within `stale_after`;
+/// `latest` supplies the topic and timestamp of the result. `None` when every
+/// source is stale.
+fn aggregate(
+    mode: SourceMode,
+    sources: &BTreeMap<String, Reading>,
+    latest: Reading,
+    stale_after: Duration,
+) -> Option<Reading> {
+    let now = Instant::now();
+    let values: Vec<f64> = sources
+        .values()
+        .filter(|r| now.duration_since(r.timestamp) < stale_after)
+        .map(|r| r.value)
+        .collect();
+    if values.is_empty() {
+        return None;
+    }
+    let value = match mode {
+        SourceMode::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+        SourceMode::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        _ => values.iter().sum::<f64>() / values.len() as f64,
+    };
+    Some(Reading {
+        value,
+        sources: values.len(),
+        ..latest
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STALE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn aliases_resolve_to_canonical_ids() {
+        assert_eq!(canonical_id("temp"), "temperature");
+        assert_eq!(canonical_id("PM2_5"), "pm25");
+        assert_eq!(canonical_id("radon"), "radon");
+        assert_eq!(descriptor("temp").map(|m| m.label), Some("Temperature"));
+        let gas: Vec<&str> = section_metrics("gas").map(|m| m.id).collect();
+        assert_eq!(gas, vec!["co2", "tvoc", "voc_index", "nox_index"]);
+        assert_eq!(canonical_id("carbon_monoxide"), "co");
+        assert_eq!(section_metrics("other_gases").count(), 6);
+        assert_eq!(canonical_id("pm_1_to_2_5"), "pc_1_2_5");
+        assert_eq!(section_metrics(PARTICLE_COUNT_SECTION).count(), 4);
+    }
+
+    #[test]
+    fn registry_keeps_latest_reading_per_metric() {
+        let mut registry = MetricRegistry::default();
+        assert!(!registry.record("temp", 68.0, "a/temp".to_string(), None, None, STALE));
+        assert!(registry.record("temperature", 70.0, "b/temp".to_string(), None, None, STALE));
+        registry.record(
+            "pm25",
+            3.0,
+            "a/pm".to_string(),
+            Some("mg/m³".to_string()),
+            None,
+            STALE,
+        );
+
+        assert_eq!(registry.value("temperature"), Some(70.0));
+        assert_eq!(
+            registry.reading("temp").map(|r| r.topic.as_str()),
+            Some("b/temp")
+        );
+        assert_eq!(registry.unit("temperature"), "°F");
+        assert_eq!(registry.unit("pm25"), "mg/m³");
+        assert_eq!(
+            registry.last_reading().map(|r| r.topic.as_str()),
+            Some("a/pm")
+        );
+        assert_eq!(registry.readings().count(), 2);
+
+        registry.record("pm25", 4.0, "a/pm".to_string(), None, None, STALE);
+        assert_eq!(registry.unit("pm25"), descriptor("pm25").unwrap().unit);
+    }
+
+    #[test]
+    fn source_policies_resolve_competing_topics() {
+        let policy = |mode, topic: Option<&str>| SourcePolicy {
+            metric: "co2".to_string(),
+            mode,
+            topic: topic.map(str::to_string),
+        };
+        let feed = |policy: &SourcePolicy| {
+            let mut registry = MetricRegistry::default();
+            let mut unexpected = Vec::new();
+            for (topic, value) in [("a/co2", 400.0), ("b/co2", 800.0), ("a/co2", 600.0)] {
+                if registry.record("co2", value, topic.to_string(), None, Some(policy), STALE) {
+                    unexpected.push(topic);
+                }
+            }
+            (registry, unexpected)
+        };
+
+        let (registry, unexpected) = feed(&policy(SourceMode::First, None));
+        assert_eq!(registry.value("co2"), Some(600.0));
+        assert_eq!(unexpected, vec!["b/co2"]);
+
+        let (registry, unexpected) = feed(&policy(SourceMode::Preferred, Some("b/co2")));
+        assert_eq!(registry.value("co2"), Some(800.0));
+        assert!(unexpected.is_empty());
+
+        let (registry, unexpected) = feed(&policy(SourceMode::Mean, None));
+        let reading = registry.reading("co2").expect("aggregated reading");
+        assert_eq!((reading.value, reading.sources), (700.0, 2));
+        assert!(unexpected.is_empty());
+        assert_eq!(
+            registry.sources("co2").collect::<Vec<_>>(),
+            vec!["a/co2", "b/co2"]
+        );
+
+        let (registry, _) = feed(&policy(SourceMode::Max, None));
+        assert_eq!(registry.value("co2"), Some(800.0));
+    }
+
+    #[test]
+    fn unavailable_replaces_the_active_reading() {
+        let mut registry = MetricRegistry::default();
+        registry.record("pm25", 8.0, "a/pm25".to_string(), None, None, STALE);
+        registry.mark_unavailable("pm25", "b/pm25", None, STALE);
+        assert_eq!(registry.value("pm25"), Some(8.0));
+
+        registry.mark_unavailable("pm25", "a/pm25", None, STALE);
+        assert_eq!(registry.value("pm25"), None);
+        assert!(registry.is_unavailable("pm25"));
+
+        registry.record("pm25", 9.0, "a/pm25".to_string(), None, None, STALE);
+        assert!(!registry.is_unavailable("pm25"));
+
+        let mean = SourcePolicy {
+            metric: "co2".to_string(),
+            mode: SourceMode::Mean,
+            topic: None,
+        };
+        registry.record("co2", 400.0, "a/co2".to_string(), None, Some(&mean), STALE);
+        registry.record("co2", 800.0, "b/co2".to_string(), None, Some(&mean), STALE);
+        registry.mark_unavailable("co2", "b/co2", Some(&mean), STALE);
+        assert_eq!(registry.value("co2"), Some(400.0));
+        assert!(!registry.is_unavailable("co2"));
+    }
+
+    #[test]
+    fn aggregates_leave_out_stale_sources() {
+        let mean = SourcePolicy {
+            metric: "co2".to_string(),
+            mode: SourceMode::Mean,
+            topic: None,
+        };
+        let stale_after = Duration::from_millis(50);
+        let mut registry = MetricRegistry::default();
+        registry.record(
+            "co2",
+            400.0,
+            "a/co2".to_string(),
+            None,
+            Some(&mean),
+            stale_after,
+        );
+        std::thread::sleep(Duration::from_millis(80));
+        registry.record(
+            "co2",
+            800.0,
+            "b/co2".to_string(),
+            None,
+            Some(&mean),
+            stale_after,
+        );
+
+        let reading = registry.reading("co2").expect("aggregated reading");
+        assert_eq!((reading.value, reading.sources), (800.0, 1));
+    }
+}
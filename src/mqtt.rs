@@ -1,57 +1,341 @@
 use std::{
-    fs,
-    net::ToSocketAddrs,
-    path::Path,
-    sync::{Arc, mpsc},
-    time::{Duration, Instant},
+    sync::mpsc,
+    time::{Duration, Instant, SystemTime},
 };
 
 use anyhow::{Context, Result};
-use rumqttc::tokio_rustls::rustls::{
-    ClientConfig, RootCertStore,
-    pki_types::{CertificateDer, pem::PemObject},
+use rumqttc::{
+    Client, ConnectReturnCode, Event, MqttOptions, Outgoing, Packet, QoS, SubscribeReasonCode,
+    Transport,
 };
-use rumqttc::{Client, Event, MqttOptions, Packet, QoS, TlsConfiguration, Transport};
-use tracing::error;
+use tracing::{debug, error, info};
 
-use crate::config::MqttConfig;
+use crate::adapters::LayoutAdapter;
+use crate::config::{
+    MappingRule, MqttConfig, MqttTransport, ProtocolVersion, TopicAdapter, TopicMatch,
+};
+use crate::decode::{DecodeError, decode};
+use crate::diagnostics::{self, Outcome, Report, SessionEvent};
+use crate::discovery::{DEFAULT_DISCOVERY_PREFIX, DiscoveryRegistry, DiscoveryUpdate};
+use crate::mapping::RuleSet;
+use crate::mqtt5;
+use crate::tls::{offer_certificate, offers_pin, report_certificate, tls_config, tls_failure};
+use crate::units;
 
-/// Test a one-shot MQTT connection and subscribe to a status topic.
-pub fn test_connection(cfg: &MqttConfig, password: Option<&str>) -> Result<()> {
-    socket_check(cfg)?;
+/// Connect, subscribe to the listener's filters and wait for a mapped
+/// publish, recording each phase in the diagnostics report.
+pub(crate) fn diagnose_session(
+    cfg: &MqttConfig,
+    mappings: &[MappingRule],
+    password: Option<&str>,
+    key_passphrase: Option<&str>,
+    report: &mut Report,
+) {
+    if cfg.protocol == ProtocolVersion::V5 {
+        return mqtt5::diagnose_session(cfg, mappings, password, key_passphrase, report);
+    }
+    let mut opts = match build_options(cfg, password, key_passphrase) {
+        Ok(opts) => opts,
+        Err(err) => {
+            report.record("CONNACK", Outcome::Failed, None, vec![format!("{err:#}")]);
+            return;
+        }
+    };
+    opts.set_clean_session(true);
 
-    let mut opts = build_options(cfg, password)?;
-    opts.set_keep_alive(Duration::from_secs(cfg.keepalive_secs.into()));
+    // The subscribes are queued before the event loop runs, so the request
+    // channel has to hold all of them.
+    let subs = subscriptions(cfg, mappings);
+    let (client, mut connection) = Client::new(opts, subs.len() + 10);
+    for sub in &subs {
+        if let Err(err) = client.subscribe(sub.filter.clone(), sub.qos) {
+            report.record("SUBACK", Outcome::Failed, None, vec![err.to_string()]);
+            return;
+        }
+    }
+    let mut mapper = TopicMapper::new(cfg, mappings);
+    let filters = subs.iter().map(|sub| sub.filter.clone()).collect();
+    let mut subs = subs;
+    let mut unsubscribed = Vec::new();
+    diagnostics::record_session(report, filters, |timeout| {
+        // Follow discovery like the listener, so discovered sensors can
+        // produce the first matching publish.
+        let added = subscribe_discovered(&mut subs, &mut unsubscribed, cfg.qos, |sub| {
+            client
+                .try_subscribe(sub.filter.clone(), sub.qos)
+                .map_err(|err| err.to_string())
+        });
+        if !added.is_empty() {
+            return Some(Ok(SessionEvent::Subscribing(added)));
+        }
+        let event = match connection.recv_timeout(timeout) {
+            Err(_) => return None,
+            Ok(Err(err)) => {
+                return Some(Err(match tls_failure(&err) {
+                    Some(reason) => format!("TLS handshake failed: {reason}"),
+                    None => format!("{err:#}"),
+                }));
+            }
+            Ok(Ok(event)) => event,
+        };
+        Some(Ok(match event {
+            Event::Incoming(Packet::ConnAck(ack)) => SessionEvent::ConnAck {
+                accepted: ack.code == ConnectReturnCode::Success,
+                detail: format!(
+                    "{:?}, session {}",
+                    ack.code,
+                    if ack.session_present {
+                        "resumed"
+                    } else {
+                        "new"
+                    }
+                ),
+            },
+            Event::Outgoing(Outgoing::Subscribe(pkid)) => SessionEvent::SubscribeSent(pkid),
+            Event::Incoming(Packet::SubAck(ack)) => SessionEvent::SubAck {
+                pkid: ack.pkid,
+                codes: ack
+                    .return_codes
+                    .iter()
+                    .map(|code| match code {
+                        SubscribeReasonCode::Success(qos) => Ok(format!("granted {qos:?}")),
+                        SubscribeReasonCode::Failure => Err("rejected".to_string()),
+                    })
+                    .collect(),
+            },
+            Event::Incoming(Packet::Publish(p)) => {
+                let mapped = produces_reading(&mapper.map_publish(&p));
+                unsubscribed.extend(discovered_topics(&mut mapper));
+                SessionEvent::Publish {
+                    topic: p.topic.clone(),
+                    mapped,
+                }
+            }
+            _ => SessionEvent::Other,
+        }))
+    });
+    let _ = client.disconnect();
+}
+
+/// Subscribe to `filter` for `duration` and hand every publish to
+/// `on_publish`; stops early once `stop_rx` fires or its sender is dropped.
+pub(crate) fn scan_topics(
+    cfg: &MqttConfig,
+    password: Option<&str>,
+    key_passphrase: Option<&str>,
+    filter: &str,
+    duration: Duration,
+    stop_rx: &mpsc::Receiver<()>,
+    mut on_publish: impl FnMut(&str, &[u8]),
+) -> Result<()> {
+    if cfg.protocol == ProtocolVersion::V5 {
+        return mqtt5::scan_topics(
+            cfg,
+            password,
+            key_passphrase,
+            filter,
+            duration,
+            stop_rx,
+            on_publish,
+        );
+    }
+    let mut opts = build_options(cfg, password, key_passphrase)?;
     opts.set_clean_session(true);
+    let (client, mut connection) = Client::new(opts, 100);
+    client.subscribe(filter, qos_level(cfg.qos))?;
+
+    let deadline = Instant::now() + duration;
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        if !matches!(stop_rx.try_recv(), Err(mpsc::TryRecvError::Empty)) {
+            break;
+        }
+        match connection.recv_timeout(remaining.min(Duration::from_millis(250))) {
+            Ok(Ok(Event::Incoming(Packet::Publish(p)))) => on_publish(&p.topic, &p.payload),
+            Ok(Ok(_)) | Err(_) => {}
+            Ok(Err(err)) => match tls_failure(&err) {
+                Some(reason) => anyhow::bail!("TLS handshake failed: {reason}"),
+                None => return Err(err).context("MQTT error during scan"),
+            },
+        }
+    }
+    let _ = client.disconnect();
+    Ok(())
+}
+
+/// Drain the state topics discovery announced, dropping any that are not
+/// valid topic names and so could never be subscribed to.
+pub(crate) fn discovered_topics(mapper: &mut TopicMapper) -> Vec<String> {
+    let mut topics = mapper.take_new_state_topics();
+    topics.retain(|topic| {
+        let valid = !topic.is_empty() && !topic.contains(['+', '#']);
+        if !valid {
+            error!("ignoring invalid discovered state topic {topic:?}");
+        }
+        valid
+    });
+    topics
+}
+
+/// Subscribe to the discovered topics in `unsubscribed` not covered by
+/// `subs` yet, returning the new filters. Uses a non-blocking subscribe: a
+/// blocking send could deadlock while the calling thread is the one driving
+/// the event loop. Topics the request channel cannot take yet stay in
+/// `unsubscribed` for the next call.
+pub(crate) fn subscribe_discovered(
+    subs: &mut Vec<Subscription>,
+    unsubscribed: &mut Vec<String>,
+    qos: u8,
+    mut try_subscribe: impl FnMut(&Subscription) -> Result<(), String>,
+) -> Vec<String> {
+    let mut added = Vec::new();
+    unsubscribed.retain(|topic| {
+        if is_covered(subs, topic) {
+            return false;
+        }
+        let sub = Subscription::new(topic.clone(), qos);
+        match try_subscribe(&sub) {
+            Ok(()) => {
+                added.push(sub.filter.clone());
+                subs.push(sub);
+                false
+            }
+            Err(err) => {
+                debug!("subscribe to discovered topic {topic} deferred: {err}");
+                true
+            }
+        }
+    });
+    added
+}
 
-    let (client, mut connection) = Client::new(opts, 10);
-    client.subscribe(test_topic(cfg), QoS::AtMostOnce)?;
+/// Whether mapped events include a reading (or a sensor reporting it has none).
+pub(crate) fn produces_reading(events: &[crate::app::MqttEvent]) -> bool {
+    events.iter().any(|evt| {
+        matches!(
+            evt,
+            crate::app::MqttEvent::Metric { .. } | crate::app::MqttEvent::Unavailable { .. }
+        )
+    })
+}
 
-    let start = Instant::now();
-    let timeout = Duration::from_secs(5);
+/// How the mapper classified a topic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapRoute {
+    /// Nothing recognised the topic; the publish was dropped.
+    Unmapped,
+    /// Home Assistant discovery config, consumed by the registry.
+    DiscoveryConfig,
+    /// ESPHome `<node>/status` availability topic.
+    Availability,
+    /// A user mapping rule.
+    Rule,
+    /// One of the configured layout adapters.
+    Adapter,
+    /// A sensor announced through discovery.
+    Discovery,
+    /// Metric guessed from the sensor name.
+    SensorName,
+}
 
-    for notification in connection.iter() {
-        if start.elapsed() > timeout {
-            anyhow::bail!("MQTT test timed out after {:?}", timeout);
+impl MapRoute {
+    pub fn label(self) -> &'static str {
+        match self {
+            MapRoute::Unmapped => "unmapped",
+            MapRoute::DiscoveryConfig => "discovery config",
+            MapRoute::Availability => "availability",
+            MapRoute::Rule => "mapping rule",
+            MapRoute::Adapter => "layout adapter",
+            MapRoute::Discovery => "discovered sensor",
+            MapRoute::SensorName => "sensor name",
         }
-        match notification {
-            Ok(Event::Incoming(Packet::ConnAck(_))) => return Ok(()),
-            Ok(_) => continue,
-            Err(err) => return Err(err).context("MQTT error during test"),
+    }
+}
+
+/// A publish as the listener received it, with what the mapper made of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Received {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub retain: bool,
+    pub qos: u8,
+    pub received: SystemTime,
+    pub route: MapRoute,
+    /// Readings or failures the publish produced, e.g. `pm25 = 4.5 µg/m³`.
+    pub outcome: Vec<String>,
+    /// Whether a mapped payload could not be decoded.
+    pub decode_failed: bool,
+}
+
+impl Received {
+    pub(crate) fn new(
+        p: &rumqttc::Publish,
+        route: MapRoute,
+        events: &[crate::app::MqttEvent],
+    ) -> Self {
+        use crate::app::MqttEvent;
+        let outcome = events
+            .iter()
+            .filter_map(|evt| match evt {
+                MqttEvent::Metric {
+                    kind, value, unit, ..
+                } => Some(match unit {
+                    Some(unit) => format!("{kind} = {value} {unit}"),
+                    None => format!("{kind} = {value}"),
+                }),
+                MqttEvent::Unavailable { kind, .. } => Some(format!("{kind} unavailable")),
+                MqttEvent::Availability { device, online } => Some(format!(
+                    "{device} {}",
+                    if *online { "online" } else { "offline" }
+                )),
+                MqttEvent::DecodeFailed { reason, .. } => Some(format!("decode failed: {reason}")),
+                _ => None,
+            })
+            .collect();
+        Self {
+            topic: p.topic.clone(),
+            payload: p.payload.to_vec(),
+            retain: p.retain,
+            qos: p.qos as u8,
+            received: SystemTime::now(),
+            route,
+            outcome,
+            decode_failed: events
+                .iter()
+                .any(|evt| matches!(evt, MqttEvent::DecodeFailed { .. })),
         }
     }
 
-    anyhow::bail!("MQTT test ended without ConnAck")
+    /// How the publish was mapped, e.g. `mapping rule: pm25 = 4.5`.
+    pub fn describe(&self) -> String {
+        let route = self.route.label();
+        if self.outcome.is_empty() {
+            route.to_string()
+        } else {
+            format!("{route}: {}", self.outcome.join(", "))
+        }
+    }
 }
 
 /// Run the MQTT listener loop and forward events to the UI thread.
 pub fn run_listener(
     cfg: MqttConfig,
+    mappings: Vec<MappingRule>,
     password: Option<&str>,
+    key_passphrase: Option<&str>,
     tx: std::sync::mpsc::Sender<crate::app::MqttEvent>,
     stop_rx: mpsc::Receiver<()>,
 ) -> Result<()> {
+    if offers_pin(&cfg) {
+        return offer_certificate(&cfg, key_passphrase, &tx);
+    }
+    if cfg.protocol == ProtocolVersion::V5 {
+        return mqtt5::run_listener(cfg, mappings, password, key_passphrase, tx, stop_rx);
+    }
     let mut backoff = Duration::from_secs(1);
+    // Discovery records are kept across reconnects; retained configs are
+    // re-delivered anyway, but this keeps mapping stable in between.
+    let mut mapper = TopicMapper::new(&cfg, &mappings);
+    let mut cert_reported = false;
 
     loop {
         if stop_rx.try_recv().is_ok() {
@@ -62,23 +346,33 @@ pub fn run_listener(
             break;
         }
 
-        let mut opts = build_options(&cfg, password)?;
+        let mut opts = build_options(&cfg, password, key_passphrase)?;
         opts.set_clean_session(false);
-        let (client, mut connection) = Client::new(opts, 20);
-        let connect_at = Instant::now();
 
-        let subs = subscriptions(&cfg);
+        let mut subs = subscriptions(&cfg, &mappings);
+        for topic in mapper.discovered_state_topics() {
+            if !is_covered(&subs, &topic) {
+                subs.push(Subscription::new(topic, cfg.qos));
+            }
+        }
+        // Nothing drains the request channel until the loop below polls the
+        // connection, so it must fit every subscribe queued up front.
+        let (client, mut connection) = Client::new(opts, subs.len() + 20);
+        let connect_at = Instant::now();
         for sub in &subs {
-            client.subscribe(sub.clone(), QoS::AtMostOnce)?;
+            client.subscribe(sub.filter.clone(), sub.qos)?;
         }
+        let filters: Vec<&str> = subs.iter().map(|sub| sub.filter.as_str()).collect();
         let _ = tx.send(crate::app::MqttEvent::Status(format!(
             "MQTT connected; subs: {}",
-            subs.join(", ")
+            filters.join(", ")
         )));
         let _ = tx.send(crate::app::MqttEvent::Connected);
 
         let mut stopped = false;
         let mut disconnect_reason: Option<String> = None;
+        // Discovered state topics still to subscribe to.
+        let mut unsubscribed: Vec<String> = Vec::new();
         for notification in connection.iter() {
             if stop_rx.try_recv().is_ok() {
                 stopped = true;
@@ -89,18 +383,36 @@ pub fn run_listener(
                 break;
             }
             match notification {
+                Ok(Event::Incoming(Packet::ConnAck(_)))
+                    if !cert_reported && cfg.transport().is_secure() =>
+                {
+                    cert_reported = true;
+                    report_certificate(&cfg, key_passphrase, &tx);
+                }
                 Ok(Event::Incoming(Packet::Publish(p))) => {
-                    if let Some(evt) = map_publish(&p) {
+                    let (route, events) = mapper.route_publish(&p);
+                    let received = Received::new(&p, route, &events);
+                    let _ = tx.send(crate::app::MqttEvent::Received(received));
+                    for evt in events {
                         let _ = tx.send(evt);
                     }
+                    unsubscribed.extend(discovered_topics(&mut mapper));
                 }
                 Ok(_) => {}
                 Err(err) => {
                     error!("MQTT connection error: {:#}", err);
-                    disconnect_reason = Some(format!("{err:#}"));
+                    disconnect_reason = Some(match tls_failure(&err) {
+                        Some(reason) => format!("TLS handshake failed: {reason}"),
+                        None => format!("{err:#}"),
+                    });
                     break;
                 }
             }
+            subscribe_discovered(&mut subs, &mut unsubscribed, cfg.qos, |sub| {
+                client
+                    .try_subscribe(sub.filter.clone(), sub.qos)
+                    .map_err(|err| err.to_string())
+            });
         }
 
         if stopped {
@@ -133,115 +445,496 @@ pub fn run_listener(
     Ok(())
 }
 
-fn build_options(cfg: &MqttConfig, password: Option<&str>) -> Result<MqttOptions> {
+fn build_options(
+    cfg: &MqttConfig,
+    password: Option<&str>,
+    key_passphrase: Option<&str>,
+) -> Result<MqttOptions> {
     let client_id = cfg
         .client_id
         .clone()
         .unwrap_or_else(|| "air1-monitor".to_string());
-    let mut opts = MqttOptions::new(client_id, cfg.host.clone(), cfg.port);
+    let mut opts = MqttOptions::new(client_id, broker_address(cfg), cfg.port);
     if let Some(user) = cfg.username.as_deref() {
         opts.set_credentials(user, password.unwrap_or(""));
     }
     opts.set_keep_alive(Duration::from_secs(cfg.keepalive_secs.into()));
-    if cfg.tls {
-        let tls = tls_config(cfg.ca_path.as_deref())?;
-        opts.set_transport(Transport::tls_with_config(tls));
+    opts.set_transport(transport(cfg, key_passphrase)?);
+    if cfg.transport().is_websocket() && !cfg.ws_headers.is_empty() {
+        let headers = websocket_headers(cfg)?;
+        opts.set_request_modifier(move |mut request: http::Request<()>| {
+            request.headers_mut().extend(headers.clone());
+            std::future::ready(request)
+        });
     }
     Ok(opts)
 }
 
-fn subscriptions(cfg: &MqttConfig) -> Vec<String> {
-    let raw = cfg
-        .topic_prefix
+/// Broker address for `MqttOptions`: the host, or the full URL for
+/// WebSocket transports.
+pub(crate) fn broker_address(cfg: &MqttConfig) -> String {
+    match cfg.transport() {
+        MqttTransport::Tcp | MqttTransport::Tls => cfg.host.clone(),
+        MqttTransport::Ws | MqttTransport::Wss => websocket_url(cfg),
+    }
+}
+
+/// WebSocket URL built from host, port and the configured path.
+pub(crate) fn websocket_url(cfg: &MqttConfig) -> String {
+    let scheme = if cfg.transport().is_secure() {
+        "wss"
+    } else {
+        "ws"
+    };
+    let path = cfg
+        .ws_path
         .as_deref()
-        .unwrap_or("homeassistant")
-        .trim();
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .unwrap_or("/mqtt");
+    let slash = if path.starts_with('/') { "" } else { "/" };
+    format!("{scheme}://{}:{}{slash}{path}", cfg.host, cfg.port)
+}
+
+/// rumqttc transport for the configured transport; TLS variants share the
+/// CA and client certificate handling.
+pub(crate) fn transport(cfg: &MqttConfig, key_passphrase: Option<&str>) -> Result<Transport> {
+    Ok(match cfg.transport() {
+        MqttTransport::Tcp => Transport::Tcp,
+        MqttTransport::Tls => Transport::tls_with_config(tls_config(cfg, key_passphrase)?),
+        MqttTransport::Ws => Transport::Ws,
+        MqttTransport::Wss => Transport::wss_with_config(tls_config(cfg, key_passphrase)?),
+    })
+}
+
+/// Validated HTTP headers for the WebSocket upgrade request.
+pub(crate) fn websocket_headers(cfg: &MqttConfig) -> Result<http::HeaderMap> {
+    let mut headers = http::HeaderMap::new();
+    for (name, value) in &cfg.ws_headers {
+        let name = http::HeaderName::from_bytes(name.trim().as_bytes())
+            .with_context(|| format!("invalid HTTP header name {name:?}"))?;
+        let value = http::HeaderValue::from_str(value.trim())
+            .with_context(|| format!("invalid value for HTTP header {name}"))?;
+        headers.insert(name, value);
+    }
+    Ok(headers)
+}
+
+/// Base topic under the configured prefix, falling back to Home Assistant's.
+fn topic_base(cfg: &MqttConfig) -> &str {
+    trim_base(
+        cfg.topic_prefix
+            .as_deref()
+            .unwrap_or(TopicAdapter::Auto.default_base()),
+    )
+}
 
-    // Normalize: strip any trailing wildcard the user may have entered (e.g., "apollo_air1/#")
-    // and collapse trailing slashes.
-    let base = raw
+/// Strip any trailing wildcard the user may have entered (e.g.,
+/// "apollo_air1/#") and collapse trailing slashes.
+fn trim_base(raw: &str) -> &str {
+    raw.trim()
         .trim_end_matches("/#")
         .trim_end_matches('#')
-        .trim_end_matches('/');
-
-    vec![format!("{base}/#")]
-}
-
-fn tls_config(ca_path: Option<&Path>) -> Result<TlsConfiguration> {
-    let mut roots = RootCertStore::empty();
-    if let Some(path) = ca_path {
-        let data = fs::read(path)
-            .with_context(|| format!("failed to read CA file at {}", path.display()))?;
-        let certs: Vec<CertificateDer<'static>> = CertificateDer::pem_slice_iter(&data)
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|_| anyhow::anyhow!("failed to parse CA certs"))?;
-        let (added, _skipped) = roots.add_parsable_certificates(certs);
-        if added == 0 {
-            anyhow::bail!("no CA certs added from {}", path.display());
+        .trim_end_matches('/')
+}
+
+/// A topic filter and the QoS it is subscribed with.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Subscription {
+    pub(crate) filter: String,
+    pub(crate) qos: QoS,
+}
+
+impl Subscription {
+    pub(crate) fn new(filter: String, qos: u8) -> Self {
+        Self {
+            filter,
+            qos: qos_level(qos),
         }
-    } else {
-        let native_result = rustls_native_certs::load_native_certs();
-        if !native_result.errors.is_empty() && native_result.certs.is_empty() {
-            anyhow::bail!("failed to load native certs: {:?}", native_result.errors);
+    }
+}
+
+/// Map a config QoS level to rumqttc's enum; config validation keeps it in 0-2.
+pub(crate) fn qos_level(qos: u8) -> QoS {
+    match qos {
+        0 => QoS::AtMostOnce,
+        1 => QoS::AtLeastOnce,
+        _ => QoS::ExactlyOnce,
+    }
+}
+
+pub(crate) fn is_covered(subs: &[Subscription], topic: &str) -> bool {
+    subs.iter().any(|sub| topic_matches(&sub.filter, topic))
+}
+
+/// Whether the whole base tree has to be subscribed: an explicit prefix
+/// asks for it, regex rules can only match topics arriving through it, and
+/// without rules, discovery or adapters nothing narrower is known.
+fn needs_base_tree(cfg: &MqttConfig, mappings: &[MappingRule]) -> bool {
+    cfg.topic_prefix
+        .as_deref()
+        .is_some_and(|prefix| !prefix.trim().is_empty())
+        || mappings
+            .iter()
+            .any(|rule| rule.match_type == TopicMatch::Regex)
+        || (!cfg.discovery && mappings.is_empty() && cfg.adapters.is_empty())
+}
+
+/// Filters subscribed in place of explicit ones: the base tree when it is
+/// needed and each adapter's tree.
+pub(crate) fn implicit_filters(cfg: &MqttConfig, mappings: &[MappingRule]) -> Vec<String> {
+    let base = needs_base_tree(cfg, mappings).then(|| format!("{}/#", topic_base(cfg)));
+    base.into_iter()
+        .chain(
+            cfg.adapters
+                .iter()
+                .filter(|adapter| !adapter.kind.is_auto())
+                .map(|adapter| format!("{}/#", trim_base(adapter.base()))),
+        )
+        .collect()
+}
+
+/// Subscriptions for the listener: the explicit filters or the implicit
+/// ones, plus the discovery and rule filters not covered yet.
+pub(crate) fn subscriptions(cfg: &MqttConfig, mappings: &[MappingRule]) -> Vec<Subscription> {
+    let mut subs: Vec<Subscription> = cfg
+        .filters
+        .iter()
+        .filter(|f| !f.filter.trim().is_empty())
+        .map(|f| Subscription::new(f.filter.trim().to_string(), f.qos.unwrap_or(cfg.qos)))
+        .collect();
+    let mut extra = Vec::new();
+    if subs.is_empty() {
+        extra.extend(implicit_filters(cfg, mappings));
+    }
+    if cfg.discovery {
+        extra.extend(DiscoveryRegistry::new(discovery_prefix(cfg)).subscriptions());
+    }
+    // Exact and wildcard rules double as filters; regex rules can only match
+    // topics that arrive through other subscriptions.
+    extra.extend(
+        mappings
+            .iter()
+            .filter(|rule| rule.match_type != TopicMatch::Regex)
+            .map(|rule| rule.topic.trim().to_string()),
+    );
+    for filter in extra {
+        if !is_covered(&subs, &filter) {
+            subs.push(Subscription::new(filter, cfg.qos));
         }
-        let (added, _skipped) = roots.add_parsable_certificates(native_result.certs);
-        if added == 0 {
-            anyhow::bail!("no native certificates available");
+    }
+    subs
+}
+
+fn discovery_prefix(cfg: &MqttConfig) -> &str {
+    cfg.discovery_prefix
+        .as_deref()
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .unwrap_or(DEFAULT_DISCOVERY_PREFIX)
+}
+
+/// Return true if an MQTT topic filter (with `+`/`#` wildcards) matches a topic.
+pub(crate) fn topic_matches(filter: &str, topic: &str) -> bool {
+    let mut topic_levels = topic.split('/');
+    for level in filter.split('/') {
+        match level {
+            "#" => return true,
+            "+" => {
+                if topic_levels.next().is_none() {
+                    return false;
+                }
+            }
+            literal => {
+                if topic_levels.next() != Some(literal) {
+                    return false;
+                }
+            }
         }
     }
-    let config = ClientConfig::builder()
-        .with_root_certificates(roots)
-        .with_no_client_auth();
-    Ok(TlsConfiguration::Rustls(Arc::new(config)))
+    topic_levels.next().is_none()
 }
 
-fn test_topic(cfg: &MqttConfig) -> String {
-    if let Some(prefix) = &cfg.topic_prefix {
-        format!("{}/status", prefix)
-    } else {
-        "homeassistant/status".to_string()
+/// Stateful mapper from incoming publishes to metric events.
+///
+/// User mapping rules are checked first, then the configured layout adapters,
+/// ESPHome status topics and discovery records; `map_sensor_kind` is only
+/// consulted for topics none of them describes.
+pub struct TopicMapper {
+    rules: RuleSet,
+    adapters: Vec<LayoutAdapter>,
+    discovery: Option<DiscoveryRegistry>,
+    new_state_topics: Vec<String>,
+}
+
+impl TopicMapper {
+    /// Create a mapper for the given MQTT settings and mapping rules.
+    pub fn new(cfg: &MqttConfig, mappings: &[MappingRule]) -> Self {
+        Self {
+            rules: RuleSet::compile(mappings),
+            adapters: cfg
+                .adapters
+                .iter()
+                .filter_map(|adapter| LayoutAdapter::new(adapter.kind, adapter.base()))
+                .collect(),
+            discovery: cfg
+                .discovery
+                .then(|| DiscoveryRegistry::new(discovery_prefix(cfg))),
+            new_state_topics: Vec::new(),
+        }
+    }
+
+    /// State topics currently bound by discovery records.
+    pub fn discovered_state_topics(&self) -> Vec<String> {
+        self.discovery
+            .as_ref()
+            .map(|d| d.state_topics().map(str::to_string).collect())
+            .unwrap_or_default()
+    }
+
+    /// Drain state topics announced since the last call.
+    pub fn take_new_state_topics(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.new_state_topics)
+    }
+
+    /// Map a publish to metric events, consuming discovery configs on the way.
+    ///
+    /// Topics that map to a metric but carry an undecodable payload yield a
+    /// `DecodeFailed` event instead of being dropped.
+    pub fn map_publish(&mut self, p: &rumqttc::Publish) -> Vec<crate::app::MqttEvent> {
+        self.route_publish(p).1
+    }
+
+    /// Like [`TopicMapper::map_publish`], also naming the route that
+    /// classified the topic.
+    pub fn route_publish(
+        &mut self,
+        p: &rumqttc::Publish,
+    ) -> (MapRoute, Vec<crate::app::MqttEvent>) {
+        let topic = p.topic.as_str();
+
+        if let Some(discovery) = self.discovery.as_mut()
+            && discovery.is_config_topic(topic)
+        {
+            match discovery.handle_config(topic, &p.payload) {
+                Some(DiscoveryUpdate::Added { state_topic, kind }) => {
+                    info!("discovered {kind} sensor on {state_topic}");
+                    self.new_state_topics.push(state_topic);
+                }
+                Some(DiscoveryUpdate::Removed { state_topic }) => {
+                    info!("discovery removed sensor on {state_topic}");
+                }
+                None => {}
+            }
+            return (MapRoute::DiscoveryConfig, Vec::new());
+        }
+
+        let rules = self.rules.matching(topic);
+        if !rules.is_empty() {
+            let events = rules
+                .into_iter()
+                .map(|rule| {
+                    let device = rule.device(topic).unwrap_or_else(|| topic_device(topic));
+                    match decode(&p.payload, rule.path.as_deref()) {
+                        Ok(decoded) => {
+                            let unit = rule.unit.clone().or(decoded.unit);
+                            let (value, unit) = units::normalize_reading(
+                                &rule.metric,
+                                decoded.value * rule.scale,
+                                unit.as_deref(),
+                            );
+                            crate::app::MqttEvent::Metric {
+                                topic: topic.to_string(),
+                                value,
+                                kind: rule.metric.clone(),
+                                unit,
+                                device,
+                            }
+                        }
+                        Err(err) => decode_failed(topic, &rule.metric, device, &err),
+                    }
+                })
+                .collect();
+            return (MapRoute::Rule, events);
+        }
+
+        if let Some(events) = self
+            .adapters
+            .iter_mut()
+            .find_map(|adapter| adapter.handle(topic, &p.payload))
+        {
+            return (MapRoute::Adapter, events);
+        }
+
+        if let Some(node) = esphome_status_device(topic)
+            && !self.is_discovery_prefix(node)
+        {
+            let online = match String::from_utf8_lossy(&p.payload).trim() {
+                "online" => true,
+                "offline" => false,
+                _ => return (MapRoute::Unmapped, Vec::new()),
+            };
+            // Readings of a discovered node are filed under the discovery
+            // device name, so its availability has to be too.
+            let device = self
+                .discovery
+                .as_ref()
+                .and_then(|d| d.device_under(node))
+                .unwrap_or(node);
+            let event = crate::app::MqttEvent::Availability {
+                device: device.to_string(),
+                online,
+            };
+            return (MapRoute::Availability, vec![event]);
+        }
+
+        let (route, kind, sensor_unit, device) =
+            match self.discovery.as_ref().and_then(|d| d.lookup(topic)) {
+                Some(sensor) => (
+                    MapRoute::Discovery,
+                    sensor.kind,
+                    sensor.unit.clone(),
+                    sensor.device.clone(),
+                ),
+                None => {
+                    let name = sensor_name(topic);
+                    match map_sensor_kind(name) {
+                        Some(kind) => (MapRoute::SensorName, kind, None, None),
+                        None => return (MapRoute::Unmapped, Vec::new()),
+                    }
+                }
+            };
+        let device = device.unwrap_or_else(|| topic_device(topic));
+        let event = match decode(&p.payload, None) {
+            Ok(decoded) => {
+                let unit = sensor_unit.or(decoded.unit);
+                let (value, unit) = units::normalize_reading(kind, decoded.value, unit.as_deref());
+                crate::app::MqttEvent::Metric {
+                    topic: topic.to_string(),
+                    value,
+                    kind: kind.to_string(),
+                    unit,
+                    device,
+                }
+            }
+            Err(err) => decode_failed(topic, kind, device, &err),
+        };
+        (route, vec![event])
     }
 }
 
-fn map_publish(p: &rumqttc::Publish) -> Option<crate::app::MqttEvent> {
-    let topic = p.topic.clone();
-    let payload = String::from_utf8_lossy(&p.payload).trim().to_string();
-    let segments = topic.split('/').collect::<Vec<_>>();
-    if segments.is_empty() {
+impl TopicMapper {
+    fn is_discovery_prefix(&self, node: &str) -> bool {
+        self.discovery.as_ref().is_some_and(|d| d.prefix() == node)
+    }
+}
+
+/// Split an ESPHome state topic `<node>/sensor/<object_id>/state` into
+/// `(node, object_id)`. The node is the full ESPHome topic prefix.
+fn esphome_state_topic(topic: &str) -> Option<(&str, &str)> {
+    let rest = topic.strip_suffix("/state")?;
+    let (head, object_id) = rest.rsplit_once('/')?;
+    let node = head.strip_suffix("/sensor")?;
+    if node.is_empty() || object_id.is_empty() {
         return None;
     }
-    let name = *segments.last()?; // sensor name is last path component
-    let kind = map_sensor_kind(name)?;
-    let value: f64 = payload.parse().ok()?;
-    Some(crate::app::MqttEvent::Metric {
-        topic,
-        value,
-        kind: kind.to_string(),
-    })
+    Some((node, object_id))
 }
 
-fn map_sensor_kind(name: &str) -> Option<&'static str> {
+/// Return the node of an ESPHome availability topic `<node>/status`.
+fn esphome_status_device(topic: &str) -> Option<&str> {
+    topic
+        .strip_suffix("/status")
+        .filter(|node| !node.is_empty())
+}
+
+/// Device a topic belongs to when nothing else names it: the ESPHome node
+/// for ESPHome state topics, otherwise the first topic segment.
+pub(crate) fn topic_device(topic: &str) -> String {
+    match esphome_state_topic(topic) {
+        Some((node, _object_id)) => node.to_string(),
+        None => topic.split('/').next().unwrap_or(topic).to_string(),
+    }
+}
+
+/// Name used for guessing the metric kind: the ESPHome object id for
+/// ESPHome state topics, otherwise the last path component.
+pub(crate) fn sensor_name(topic: &str) -> &str {
+    match esphome_state_topic(topic) {
+        Some((_node, object_id)) => object_id,
+        None => topic.rsplit('/').next().unwrap_or(topic),
+    }
+}
+
+/// Event for a mapped topic whose payload is not a number: the sensor's
+/// unavailable state, or a decode failure.
+pub(crate) fn decode_failed(
+    topic: &str,
+    kind: &str,
+    device: String,
+    err: &DecodeError,
+) -> crate::app::MqttEvent {
+    if let DecodeError::Unavailable(state) = err {
+        debug!(%topic, "sensor reported {state}");
+        return crate::app::MqttEvent::Unavailable {
+            topic: topic.to_string(),
+            kind: kind.to_string(),
+            device,
+        };
+    }
+    debug!(%topic, "payload decode failed: {err}");
+    crate::app::MqttEvent::DecodeFailed {
+        topic: topic.to_string(),
+        reason: err.to_string(),
+    }
+}
+
+/// Guess the metric kind from a sensor name; fallback for undiscovered topics.
+pub(crate) fn map_sensor_kind(name: &str) -> Option<&'static str> {
     let n = name.to_ascii_lowercase();
     if n.ends_with("pm_1mm_weight_concentration") {
         Some("pm1")
     } else if n.ends_with("pm_2_5mm_weight_concentration") {
         Some("pm25")
+    } else if n.ends_with("pm_4mm_weight_concentration") {
+        Some("pm4")
     } else if n.ends_with("pm_10mm_weight_concentration") {
         Some("pm10")
-    } else if n.contains("pm_1_to_2_5") {
-        Some("pm25")
     } else if n.contains("pm_0_3_to_1") {
-        Some("pm1")
+        // Number concentration bins (#/cm³), never mass.
+        Some("pc_0_3_1")
+    } else if n.contains("pm_1_to_2_5") {
+        Some("pc_1_2_5")
     } else if n.contains("pm_2_5_to_4") {
-        Some("pm25")
+        Some("pc_2_5_4")
     } else if n.contains("pm_4_to_10") {
-        Some("pm10")
-    } else if n.contains("voc") || n.contains("sen55_voc") {
+        Some("pc_4_10")
+    } else if n.contains("carbon_monoxide") {
+        // Checked before NOx: "carbon_monoxide" contains "nox".
+        Some("co")
+    } else if n.contains("nitrogen_dioxide") || n.contains("no2") {
+        Some("no2")
+    } else if n.contains("ethanol") {
+        Some("ethanol")
+    } else if n.contains("hydrogen") {
+        Some("h2")
+    } else if n.contains("ammonia") || n.contains("nh3") {
+        Some("nh3")
+    } else if n.contains("methane") || n.contains("ch4") {
+        Some("ch4")
+    } else if n.contains("tvoc") {
         Some("tvoc")
+    } else if n.contains("voc") {
+        // SEN55 reports a VOC index, not a concentration.
+        Some("voc_index")
+    } else if n.contains("nox") {
+        Some("nox_index")
     } else if n.contains("co2") {
         Some("co2")
+    } else if n.contains("pressure") {
+        Some("pressure")
     } else if n.contains("temp") || n.contains("temperature") {
-        Some("temp")
+        Some("temperature")
     } else if n.contains("humidity") || n.contains("hum") || n.contains("sen55_humidity") {
         Some("humidity")
     } else {
@@ -249,14 +942,441 @@ fn map_sensor_kind(name: &str) -> Option<&'static str> {
     }
 }
 
-fn socket_check(cfg: &MqttConfig) -> Result<()> {
-    let addr = format!("{}:{}", cfg.host, cfg.port);
-    let mut addrs = addr.to_socket_addrs().context("invalid host/port")?;
-    let target = addrs
-        .next()
-        .context("could not resolve host for socket check")?;
-    let timeout = Duration::from_secs(3);
-    std::net::TcpStream::connect_timeout(&target, timeout)
-        .with_context(|| format!("failed to reach {}", target))?;
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AdapterConfig, TopicAdapter};
+
+    fn filters(subs: &[Subscription]) -> Vec<String> {
+        subs.iter().map(|sub| sub.filter.clone()).collect()
+    }
+
+    fn publish(topic: &str, payload: &str) -> rumqttc::Publish {
+        rumqttc::Publish::new(topic, QoS::AtMostOnce, payload.as_bytes().to_vec())
+    }
+
+    fn metric(events: Vec<crate::app::MqttEvent>) -> Option<(String, f64)> {
+        match events.into_iter().next()? {
+            crate::app::MqttEvent::Metric { kind, value, .. } => Some((kind, value)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn topic_matches_wildcards() {
+        assert!(topic_matches(
+            "homeassistant/#",
+            "homeassistant/sensor/a/config"
+        ));
+        assert!(topic_matches(
+            "homeassistant/sensor/+/+/config",
+            "homeassistant/sensor/a/b/config"
+        ));
+        assert!(!topic_matches(
+            "homeassistant/sensor/+/config",
+            "homeassistant/sensor/a/b/config"
+        ));
+        assert!(topic_matches("a/b", "a/b"));
+        assert!(!topic_matches("a/b", "a/b/c"));
+    }
+
+    #[test]
+    fn discovery_takes_precedence_over_name_guessing() {
+        let mut mapper = TopicMapper::new(&MqttConfig::default(), &[]);
+        let config = r#"{"state_topic": "air1/sensor/particulate/state", "device_class": "pm10"}"#;
+
+        assert!(
+            metric(mapper.map_publish(&publish("air1/sensor/particulate/state", "4"))).is_none()
+        );
+        assert!(
+            mapper
+                .map_publish(&publish(
+                    "homeassistant/sensor/air1/particulate/config",
+                    config
+                ))
+                .is_empty()
+        );
+        assert_eq!(
+            mapper.take_new_state_topics(),
+            vec!["air1/sensor/particulate/state"]
+        );
+
+        let mapped = metric(mapper.map_publish(&publish("air1/sensor/particulate/state", "4.5")));
+        assert_eq!(mapped, Some(("pm10".to_string(), 4.5)));
+    }
+
+    #[test]
+    fn undiscovered_topics_fall_back_to_name_guessing() {
+        let mut mapper = TopicMapper::new(&MqttConfig::default(), &[]);
+        let mapped = metric(mapper.map_publish(&publish("air/sensor/co2", "612")));
+        assert_eq!(mapped, Some(("co2".to_string(), 612.0)));
+    }
+
+    #[test]
+    fn subscriptions_add_discovery_filters_outside_prefix() {
+        let cfg = MqttConfig {
+            topic_prefix: Some("apollo_air1/#".to_string()),
+            ..MqttConfig::default()
+        };
+        let subs = filters(&subscriptions(&cfg, &[]));
+        assert_eq!(subs[0], "apollo_air1/#");
+        assert!(subs.contains(&"homeassistant/sensor/+/+/config".to_string()));
+    }
+
+    #[test]
+    fn subscriptions_are_derived_from_discovery_and_rules() {
+        // Discovery alone needs only the config filters, not homeassistant/#.
+        let default_subs = filters(&subscriptions(&MqttConfig::default(), &[]));
+        assert_eq!(
+            default_subs,
+            vec![
+                "homeassistant/sensor/+/config".to_string(),
+                "homeassistant/sensor/+/+/config".to_string(),
+            ]
+        );
+
+        let no_discovery = MqttConfig {
+            discovery: false,
+            ..MqttConfig::default()
+        };
+        assert_eq!(
+            filters(&subscriptions(&no_discovery, &[])),
+            vec!["homeassistant/#".to_string()]
+        );
+
+        let cfg = MqttConfig {
+            discovery: false,
+            qos: 1,
+            filters: vec![
+                crate::config::TopicFilter {
+                    filter: "air1/sensor/+/state".to_string(),
+                    qos: None,
+                },
+                crate::config::TopicFilter {
+                    filter: "air1/status".to_string(),
+                    qos: Some(0),
+                },
+            ],
+            ..MqttConfig::default()
+        };
+        let subs = subscriptions(&cfg, &[]);
+        assert_eq!(
+            subs,
+            vec![
+                Subscription::new("air1/sensor/+/state".to_string(), 1),
+                Subscription::new("air1/status".to_string(), 0),
+            ]
+        );
+        assert_eq!(subs[0].qos, QoS::AtLeastOnce);
+    }
+
+    #[test]
+    fn mapping_rules_override_guessing_and_apply_scale() {
+        let rules = vec![MappingRule {
+            topic: "lab/+/particles".to_string(),
+            match_type: TopicMatch::Wildcard,
+            metric: "pm25".to_string(),
+            path: None,
+            scale: 0.1,
+            unit: Some("mg/m³".to_string()),
+            device: None,
+        }];
+        let mut mapper = TopicMapper::new(&MqttConfig::default(), &rules);
+
+        match mapper
+            .map_publish(&publish("lab/bench/particles", "120"))
+            .pop()
+        {
+            Some(crate::app::MqttEvent::Metric {
+                kind, value, unit, ..
+            }) => {
+                // 12 mg/m³ after scaling, shown in the dashboard's µg/m³.
+                assert_eq!(kind, "pm25");
+                assert!((value - 12_000.0).abs() < 1e-6);
+                assert_eq!(unit, None);
+            }
+            _ => panic!("expected a metric event"),
+        }
+
+        let subs = filters(&subscriptions(&MqttConfig::default(), &rules));
+        assert!(subs.contains(&"lab/+/particles".to_string()));
+    }
+
+    #[test]
+    fn json_rules_extract_several_metrics_from_one_message() {
+        let rule = |metric: &str, path: &str| MappingRule {
+            topic: "tele/+/SENSOR".to_string(),
+            match_type: TopicMatch::Wildcard,
+            metric: metric.to_string(),
+            path: Some(path.to_string()),
+            scale: 1.0,
+            unit: None,
+            device: None,
+        };
+        let rules = vec![
+            rule("pm25", "$.PMS5003['PM2.5']"),
+            rule("co2", "/SCD30/CarbonDioxide"),
+        ];
+        let mut mapper = TopicMapper::new(&MqttConfig::default(), &rules);
+        let payload = r#"{"PMS5003": {"PM2.5": 9}, "SCD30": {"CarbonDioxide": 701}}"#;
+
+        let events = mapper.map_publish(&publish("tele/kitchen/SENSOR", payload));
+        let metrics: Vec<(String, f64)> = events
+            .into_iter()
+            .filter_map(|evt| match evt {
+                crate::app::MqttEvent::Metric { kind, value, .. } => Some((kind, value)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            metrics,
+            vec![("pm25".to_string(), 9.0), ("co2".to_string(), 701.0)]
+        );
+    }
+
+    #[test]
+    fn undecodable_payloads_on_mapped_topics_are_reported() {
+        let mut mapper = TopicMapper::new(&MqttConfig::default(), &[]);
+
+        let events = mapper.map_publish(&publish("air/sensor/co2", "{\"level\": 1}"));
+        assert!(matches!(
+            events.as_slice(),
+            [crate::app::MqttEvent::DecodeFailed { topic, .. }] if topic == "air/sensor/co2"
+        ));
+        assert!(
+            mapper
+                .map_publish(&publish("air/sensor/uptime", "abc"))
+                .is_empty()
+        );
+
+        let events = mapper.map_publish(&publish("air1/sensor/co2/state", "unavailable"));
+        assert!(matches!(
+            events.as_slice(),
+            [crate::app::MqttEvent::Unavailable { kind, device, .. }]
+                if kind == "co2" && device == "air1"
+        ));
+    }
+
+    #[test]
+    fn esphome_state_topics_use_object_id() {
+        let mut mapper = TopicMapper::new(&MqttConfig::default(), &[]);
+
+        let mapped = metric(mapper.map_publish(&publish(
+            "apollo-air-1-12ab34/sensor/sen55_temperature/state",
+            "21.4",
+        )));
+        assert_eq!(mapped, Some(("temperature".to_string(), 21.4)));
+        assert_eq!(
+            esphome_state_topic("home/air1/sensor/co2/state"),
+            Some(("home/air1", "co2"))
+        );
+        assert_eq!(esphome_state_topic("air1/binary_sensor/x/state"), None);
+    }
+
+    #[test]
+    fn esphome_status_reports_device_availability() {
+        let mut mapper = TopicMapper::new(&MqttConfig::default(), &[]);
+
+        let events = mapper.map_publish(&publish("apollo-air-1-12ab34/status", "offline"));
+        assert!(matches!(
+            events.as_slice(),
+            [crate::app::MqttEvent::Availability { device, online: false }]
+                if device == "apollo-air-1-12ab34"
+        ));
+        // Home Assistant's own birth message is not a device.
+        assert!(
+            mapper
+                .map_publish(&publish("homeassistant/status", "online"))
+                .is_empty()
+        );
+
+        // Discovered nodes report under their discovery device name.
+        mapper.map_publish(&publish(
+            "homeassistant/sensor/air1/co2/config",
+            r#"{"state_topic": "apollo-air-1-12ab34/sensor/co2/state", "device_class": "carbon_dioxide", "device": {"name": "Office AIR-1"}}"#,
+        ));
+        let events = mapper.map_publish(&publish("apollo-air-1-12ab34/status", "online"));
+        assert!(matches!(
+            events.as_slice(),
+            [crate::app::MqttEvent::Availability { device, online: true }]
+                if device == "Office AIR-1"
+        ));
+
+        // User rules take precedence over the status heuristic.
+        let rules = vec![MappingRule {
+            topic: "lab/status".to_string(),
+            match_type: TopicMatch::Exact,
+            metric: "co2".to_string(),
+            path: None,
+            scale: 1.0,
+            unit: None,
+            device: None,
+        }];
+        let mut mapper = TopicMapper::new(&MqttConfig::default(), &rules);
+        let (route, _) = mapper.route_publish(&publish("lab/status", "offline"));
+        assert_eq!(route, MapRoute::Rule);
+    }
+
+    #[test]
+    fn received_publishes_record_route_and_outcome() {
+        let rules = vec![MappingRule {
+            topic: "lab/co2".to_string(),
+            match_type: TopicMatch::Exact,
+            metric: "co2".to_string(),
+            path: None,
+            scale: 1.0,
+            unit: Some("ppm".to_string()),
+            device: None,
+        }];
+        let mut mapper = TopicMapper::new(&MqttConfig::default(), &rules);
+
+        let mut p = publish("lab/co2", "612");
+        p.retain = true;
+        let (route, events) = mapper.route_publish(&p);
+        let received = Received::new(&p, route, &events);
+        assert_eq!(received.route, MapRoute::Rule);
+        assert_eq!(received.outcome, vec!["co2 = 612".to_string()]);
+        assert!(received.retain);
+
+        let p = publish("lab/co2", "n/a");
+        let (route, events) = mapper.route_publish(&p);
+        assert!(Received::new(&p, route, &events).decode_failed);
+
+        let p = publish("lab/fan", "on");
+        let (route, events) = mapper.route_publish(&p);
+        assert_eq!(route, MapRoute::Unmapped);
+        assert!(Received::new(&p, route, &events).outcome.is_empty());
+    }
+
+    #[test]
+    fn statestream_adapter_pairs_state_with_attributes() {
+        let cfg = MqttConfig {
+            adapters: vec![AdapterConfig {
+                kind: TopicAdapter::Statestream,
+                base: Some("homeassistant/statestream".to_string()),
+            }],
+            ..MqttConfig::default()
+        };
+        let mut mapper = TopicMapper::new(&cfg, &[]);
+        let base = "homeassistant/statestream/sensor/bedroom_co2";
+
+        assert!(
+            mapper
+                .map_publish(&publish(&format!("{base}/unit_of_measurement"), "\"%\""))
+                .is_empty()
+        );
+        let mapped = metric(mapper.map_publish(&publish(
+            &format!("{base}/device_class"),
+            "\"carbon_dioxide\"",
+        )));
+        assert_eq!(mapped, None);
+
+        let mapped = metric(mapper.map_publish(&publish(&format!("{base}/state"), "0.07")));
+        let (kind, value) = mapped.expect("state maps");
+        assert_eq!(kind, "co2");
+        assert!((value - 700.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn several_adapters_each_read_their_own_base() {
+        let cfg = MqttConfig {
+            adapters: vec![
+                AdapterConfig {
+                    kind: TopicAdapter::Zigbee2mqtt,
+                    base: None,
+                },
+                AdapterConfig {
+                    kind: TopicAdapter::Tasmota,
+                    base: Some("office/tele/#".to_string()),
+                },
+            ],
+            ..MqttConfig::default()
+        };
+        let subs = filters(&subscriptions(&cfg, &[]));
+        assert!(subs.contains(&"zigbee2mqtt/#".to_string()));
+        assert!(subs.contains(&"office/tele/#".to_string()));
+        assert!(!subs.contains(&"homeassistant/#".to_string()));
+
+        let mut mapper = TopicMapper::new(&cfg, &[]);
+        let (route, events) =
+            mapper.route_publish(&publish("zigbee2mqtt/bedroom", r#"{"humidity": 48.5}"#));
+        assert_eq!(route, MapRoute::Adapter);
+        assert_eq!(metric(events), Some(("humidity".to_string(), 48.5)));
+        let (route, events) = mapper.route_publish(&publish(
+            "office/tele/plug/SENSOR",
+            r#"{"SCD30": {"CarbonDioxide": 701}}"#,
+        ));
+        assert_eq!(route, MapRoute::Adapter);
+        assert_eq!(metric(events), Some(("co2".to_string(), 701.0)));
+    }
+
+    #[test]
+    fn readings_carry_their_device() {
+        let mut mapper = TopicMapper::new(&MqttConfig::default(), &[]);
+        let device = |events: Vec<crate::app::MqttEvent>| match events.into_iter().next() {
+            Some(crate::app::MqttEvent::Metric { device, .. }) => Some(device),
+            _ => None,
+        };
+
+        let living = mapper.map_publish(&publish("air1-living/sensor/co2/state", "610"));
+        let bedroom = mapper.map_publish(&publish("air1-bedroom/sensor/co2/state", "820"));
+        assert_eq!(device(living).as_deref(), Some("air1-living"));
+        assert_eq!(device(bedroom).as_deref(), Some("air1-bedroom"));
+
+        let config =
+            r#"{"state_topic": "air/pm", "device_class": "pm25", "device": {"name": "Hall"}}"#;
+        mapper.map_publish(&publish("homeassistant/sensor/hall/pm/config", config));
+        let hall = mapper.map_publish(&publish("air/pm", "3"));
+        assert_eq!(device(hall).as_deref(), Some("Hall"));
+    }
+
+    #[test]
+    fn websocket_transports_use_a_url_and_custom_headers() {
+        let mut cfg = MqttConfig {
+            host: "broker.example.com".to_string(),
+            port: 443,
+            transport: Some(MqttTransport::Wss),
+            ..MqttConfig::default()
+        };
+        assert_eq!(broker_address(&cfg), "wss://broker.example.com:443/mqtt");
+        cfg.transport = Some(MqttTransport::Ws);
+        cfg.ws_path = Some("ws".to_string());
+        assert_eq!(broker_address(&cfg), "ws://broker.example.com:443/ws");
+        cfg.transport = Some(MqttTransport::Tcp);
+        assert_eq!(broker_address(&cfg), "broker.example.com");
+
+        cfg.ws_headers
+            .insert("X-Api-Key".to_string(), "secret".to_string());
+        let headers = websocket_headers(&cfg).expect("valid headers");
+        assert_eq!(
+            headers.get("x-api-key").map(|v| v.as_bytes()),
+            Some(&b"secret"[..])
+        );
+        cfg.ws_headers
+            .insert("bad header".to_string(), "x".to_string());
+        assert!(websocket_headers(&cfg).is_err());
+    }
+
+    #[test]
+    fn air1_catalogue_names_map_to_their_own_kinds() {
+        let cases = [
+            ("pm_4mm_weight_concentration", "pm4"),
+            ("sen55_voc", "voc_index"),
+            ("sen55_nox", "nox_index"),
+            ("dps310_pressure", "pressure"),
+            ("carbon_monoxide", "co"),
+            ("nitrogen_dioxide", "no2"),
+            ("ethanol", "ethanol"),
+            ("hydrogen", "h2"),
+            ("ammonia", "nh3"),
+            ("methane", "ch4"),
+            ("sgp30_tvoc", "tvoc"),
+            ("pm_0_3_to_1", "pc_0_3_1"),
+            ("pm_2_5_to_4", "pc_2_5_4"),
+            ("pm_2_5mm_weight_concentration", "pm25"),
+        ];
+        for (name, kind) in cases {
+            assert_eq!(map_sensor_kind(name), Some(kind), "{name}");
+        }
+    }
 }
@@ -0,0 +1,500 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use rumqttc::Outgoing;
+use rumqttc::v5::mqttbytes::QoS;
+use rumqttc::v5::mqttbytes::v5::{
+    ConnAck, ConnectReturnCode, DisconnectReasonCode, Packet, Publish, SubAck, SubscribeReasonCode,
+};
+use rumqttc::v5::{Client, ConnectionError, Event, MqttOptions, StateError};
+use tracing::{debug, error, warn};
+
+use crate::app::MqttEvent;
+use crate::config::{MappingRule, MqttConfig};
+use crate::diagnostics::{self, Outcome, Report, SessionEvent};
+use crate::mqtt::{
+    Received, Subscription, TopicMapper, broker_address, discovered_topics, is_covered,
+    produces_reading, subscribe_discovered, subscriptions, transport, websocket_headers,
+};
+use crate::tls::{report_certificate, tls_failure};
+
+/// MQTT 5 counterpart of [`crate::mqtt::diagnose_session`]; the report
+/// carries the broker's CONNACK and SUBACK reason codes.
+pub(crate) fn diagnose_session(
+    cfg: &MqttConfig,
+    mappings: &[MappingRule],
+    password: Option<&str>,
+    key_passphrase: Option<&str>,
+    report: &mut Report,
+) {
+    let mut opts = match build_options(cfg, password, key_passphrase) {
+        Ok(opts) => opts,
+        Err(err) => {
+            report.record("CONNACK", Outcome::Failed, None, vec![format!("{err:#}")]);
+            return;
+        }
+    };
+    opts.set_clean_start(true);
+
+    let subs = subscriptions(cfg, mappings);
+    let (client, mut connection) = Client::new(opts, subs.len() + 10);
+    for sub in &subs {
+        if let Err(err) = client.subscribe(sub.filter.clone(), v5_qos(sub.qos)) {
+            report.record("SUBACK", Outcome::Failed, None, vec![err.to_string()]);
+            return;
+        }
+    }
+    let mut mapper = TopicMapper::new(cfg, mappings);
+    let filters = subs.iter().map(|sub| sub.filter.clone()).collect();
+    let mut subs = subs;
+    let mut unsubscribed = Vec::new();
+    diagnostics::record_session(report, filters, |timeout| {
+        let added = subscribe_discovered(&mut subs, &mut unsubscribed, cfg.qos, |sub| {
+            client
+                .try_subscribe(sub.filter.clone(), v5_qos(sub.qos))
+                .map_err(|err| err.to_string())
+        });
+        if !added.is_empty() {
+            return Some(Ok(SessionEvent::Subscribing(added)));
+        }
+        let event = match connection.recv_timeout(timeout) {
+            Err(_) => return None,
+            Ok(Err(err)) => return Some(Err(error_reason(&err))),
+            Ok(Ok(event)) => event,
+        };
+        Some(Ok(match event {
+            Event::Incoming(Packet::ConnAck(ack)) => SessionEvent::ConnAck {
+                accepted: ack.code == ConnectReturnCode::Success,
+                detail: describe_connack(&ack),
+            },
+            Event::Outgoing(Outgoing::Subscribe(pkid)) => SessionEvent::SubscribeSent(pkid),
+            Event::Incoming(Packet::SubAck(ack)) => SessionEvent::SubAck {
+                pkid: ack.pkid,
+                codes: suback_codes(&ack),
+            },
+            Event::Incoming(Packet::Publish(p)) => {
+                let mapped =
+                    to_v3_publish(&p).is_some_and(|p| produces_reading(&mapper.map_publish(&p)));
+                unsubscribed.extend(discovered_topics(&mut mapper));
+                SessionEvent::Publish {
+                    topic: String::from_utf8_lossy(&p.topic).into_owned(),
+                    mapped,
+                }
+            }
+            _ => SessionEvent::Other,
+        }))
+    });
+    let _ = client.disconnect();
+}
+
+/// MQTT 5 counterpart of [`crate::mqtt::scan_topics`].
+pub(crate) fn scan_topics(
+    cfg: &MqttConfig,
+    password: Option<&str>,
+    key_passphrase: Option<&str>,
+    filter: &str,
+    duration: Duration,
+    stop_rx: &mpsc::Receiver<()>,
+    mut on_publish: impl FnMut(&str, &[u8]),
+) -> Result<()> {
+    let mut opts = build_options(cfg, password, key_passphrase)?;
+    opts.set_clean_start(true);
+    let (client, mut connection) = Client::new(opts, 100);
+    client.subscribe(filter, v5_qos(crate::mqtt::qos_level(cfg.qos)))?;
+
+    let deadline = Instant::now() + duration;
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        if !matches!(stop_rx.try_recv(), Err(mpsc::TryRecvError::Empty)) {
+            break;
+        }
+        match connection.recv_timeout(remaining.min(Duration::from_millis(250))) {
+            Ok(Ok(Event::Incoming(Packet::Publish(p)))) => {
+                on_publish(&String::from_utf8_lossy(&p.topic), &p.payload)
+            }
+            Ok(Ok(_)) | Err(_) => {}
+            Ok(Err(err)) => anyhow::bail!("MQTT error during scan: {}", error_reason(&err)),
+        }
+    }
+    let _ = client.disconnect();
+    Ok(())
+}
+
+/// Run the MQTT 5 listener loop; same contract as [`crate::mqtt::run_listener`],
+/// plus CONNACK/SUBACK reason codes forwarded as session details.
+pub fn run_listener(
+    cfg: MqttConfig,
+    mappings: Vec<MappingRule>,
+    password: Option<&str>,
+    key_passphrase: Option<&str>,
+    tx: mpsc::Sender<MqttEvent>,
+    stop_rx: mpsc::Receiver<()>,
+) -> Result<()> {
+    let mut backoff = Duration::from_secs(1);
+    let mut mapper = TopicMapper::new(&cfg, &mappings);
+    let mut cert_reported = false;
+
+    loop {
+        if stop_rx.try_recv().is_ok() {
+            let _ = tx.send(MqttEvent::Status("MQTT stop requested".to_string()));
+            let _ = tx.send(MqttEvent::Disconnected("stopped".to_string()));
+            break;
+        }
+
+        let mut opts = build_options(&cfg, password, key_passphrase)?;
+        opts.set_clean_start(false);
+
+        let mut subs = subscriptions(&cfg, &mappings);
+        for topic in mapper.discovered_state_topics() {
+            if !is_covered(&subs, &topic) {
+                subs.push(Subscription::new(topic, cfg.qos));
+            }
+        }
+        // As in the 3.1.1 listener, the request channel must fit every
+        // subscribe queued before the connection is first polled.
+        let (client, mut connection) = Client::new(opts, subs.len() + 20);
+        let connect_at = Instant::now();
+        // SUBACKs only carry a packet id; remember which filter each
+        // outgoing SUBSCRIBE was for so reason codes can be reported per filter.
+        let mut queued: VecDeque<String> = VecDeque::new();
+        let mut in_flight: HashMap<u16, String> = HashMap::new();
+        for sub in &subs {
+            client.subscribe(sub.filter.clone(), v5_qos(sub.qos))?;
+            queued.push_back(sub.filter.clone());
+        }
+        let filters: Vec<&str> = subs.iter().map(|sub| sub.filter.as_str()).collect();
+        let _ = tx.send(MqttEvent::Status(format!(
+            "MQTT connected; subs: {}",
+            filters.join(", ")
+        )));
+        let _ = tx.send(MqttEvent::Connected);
+
+        let mut stopped = false;
+        let mut disconnect_reason: Option<String> = None;
+        // Discovered state topics still to subscribe to.
+        let mut unsubscribed: Vec<String> = Vec::new();
+        for notification in connection.iter() {
+            if stop_rx.try_recv().is_ok() {
+                stopped = true;
+                let _ = tx.send(MqttEvent::Status("MQTT stop requested".to_string()));
+                let _ = client.disconnect();
+                break;
+            }
+            match notification {
+                Ok(Event::Incoming(Packet::ConnAck(ack))) => {
+                    let _ = tx.send(MqttEvent::SessionDetail(describe_connack(&ack)));
+                    if !cert_reported && cfg.transport().is_secure() {
+                        cert_reported = true;
+                        report_certificate(&cfg, key_passphrase, &tx);
+                    }
+                }
+                Ok(Event::Outgoing(Outgoing::Subscribe(pkid))) => {
+                    if let Some(filter) = queued.pop_front() {
+                        in_flight.insert(pkid, filter);
+                    }
+                }
+                Ok(Event::Incoming(Packet::SubAck(ack))) => {
+                    let filter = in_flight.remove(&ack.pkid).unwrap_or_default();
+                    for (detail, rejected) in describe_suback(&filter, &ack) {
+                        if rejected {
+                            warn!("{detail}");
+                            let _ = tx.send(MqttEvent::Status(detail.clone()));
+                        }
+                        let _ = tx.send(MqttEvent::SessionDetail(detail));
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(p))) => {
+                    let Some(p) = to_v3_publish(&p) else {
+                        continue;
+                    };
+                    let (route, events) = mapper.route_publish(&p);
+                    let _ = tx.send(MqttEvent::Received(Received::new(&p, route, &events)));
+                    for evt in events {
+                        let _ = tx.send(evt);
+                    }
+                    unsubscribed.extend(discovered_topics(&mut mapper));
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    error!("MQTT connection error: {:#}", err);
+                    let reason = error_reason(&err);
+                    let _ = tx.send(MqttEvent::SessionDetail(format!("Disconnected: {reason}")));
+                    disconnect_reason = Some(reason);
+                    break;
+                }
+            }
+            let added = subscribe_discovered(&mut subs, &mut unsubscribed, cfg.qos, |sub| {
+                client
+                    .try_subscribe(sub.filter.clone(), v5_qos(sub.qos))
+                    .map_err(|err| err.to_string())
+            });
+            queued.extend(added);
+        }
+
+        if stopped {
+            let _ = tx.send(MqttEvent::Disconnected("stopped".to_string()));
+            break;
+        }
+
+        let reason = disconnect_reason.unwrap_or_else(|| "connection closed".to_string());
+        let _ = tx.send(MqttEvent::Disconnected(reason));
+
+        if connect_at.elapsed() >= Duration::from_secs(60) {
+            backoff = Duration::from_secs(1);
+        }
+
+        let wait = backoff;
+        let _ = tx.send(MqttEvent::Status(format!(
+            "Reconnecting in {}s",
+            wait.as_secs()
+        )));
+        if stop_rx.recv_timeout(wait).is_ok() {
+            let _ = tx.send(MqttEvent::Status("MQTT stop requested".to_string()));
+            let _ = tx.send(MqttEvent::Disconnected("stopped".to_string()));
+            break;
+        }
+        backoff = (backoff * 2).min(Duration::from_secs(30));
+    }
+
+    Ok(())
+}
+
+fn build_options(
+    cfg: &MqttConfig,
+    password: Option<&str>,
+    key_passphrase: Option<&str>,
+) -> Result<MqttOptions> {
+    let client_id = cfg
+        .client_id
+        .clone()
+        .unwrap_or_else(|| "air1-monitor".to_string());
+    let mut opts = MqttOptions::new(client_id, broker_address(cfg), cfg.port);
+    if let Some(user) = cfg.username.as_deref() {
+        opts.set_credentials(user, password.unwrap_or(""));
+    }
+    // The v5 client rejects keepalives below five seconds.
+    opts.set_keep_alive(Duration::from_secs(cfg.keepalive_secs.max(5).into()));
+    opts.set_session_expiry_interval(cfg.session_expiry_secs);
+    opts.set_receive_maximum(cfg.receive_maximum);
+    opts.set_user_properties(
+        cfg.user_properties
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+    );
+    opts.set_transport(transport(cfg, key_passphrase)?);
+    if cfg.transport().is_websocket() && !cfg.ws_headers.is_empty() {
+        let headers = websocket_headers(cfg)?;
+        opts.set_request_modifier(move |mut request: http::Request<()>| {
+            request.headers_mut().extend(headers.clone());
+            std::future::ready(request)
+        });
+    }
+    Ok(opts)
+}
+
+fn v5_qos(qos: rumqttc::QoS) -> QoS {
+    match qos {
+        rumqttc::QoS::AtMostOnce => QoS::AtMostOnce,
+        rumqttc::QoS::AtLeastOnce => QoS::AtLeastOnce,
+        rumqttc::QoS::ExactlyOnce => QoS::ExactlyOnce,
+    }
+}
+
+/// Convert a v5 publish into the v3 form the topic mapper consumes.
+fn to_v3_publish(p: &Publish) -> Option<rumqttc::Publish> {
+    let Ok(topic) = std::str::from_utf8(&p.topic) else {
+        debug!("dropping publish with a non UTF-8 topic");
+        return None;
+    };
+    let qos = match p.qos {
+        QoS::AtMostOnce => rumqttc::QoS::AtMostOnce,
+        QoS::AtLeastOnce => rumqttc::QoS::AtLeastOnce,
+        QoS::ExactlyOnce => rumqttc::QoS::ExactlyOnce,
+    };
+    let mut publish = rumqttc::Publish::from_bytes(topic, qos, p.payload.clone());
+    publish.retain = p.retain;
+    Some(publish)
+}
+
+/// One-line summary of a CONNACK and the session limits the broker assigned.
+fn describe_connack(ack: &ConnAck) -> String {
+    let mut parts = vec![
+        format!("CONNACK {:?}", ack.code),
+        format!(
+            "session {}",
+            if ack.session_present {
+                "resumed"
+            } else {
+                "new"
+            }
+        ),
+    ];
+    if let Some(props) = &ack.properties {
+        if let Some(secs) = props.session_expiry_interval {
+            parts.push(format!("session expiry {secs}s"));
+        }
+        if let Some(max) = props.receive_max {
+            parts.push(format!("receive maximum {max}"));
+        }
+        if let Some(secs) = props.server_keep_alive {
+            parts.push(format!("server keepalive {secs}s"));
+        }
+        if let Some(id) = &props.assigned_client_identifier {
+            parts.push(format!("assigned client id {id}"));
+        }
+        if let Some(reason) = &props.reason_string {
+            parts.push(format!("reason: {reason}"));
+        }
+        for (key, value) in &props.user_properties {
+            parts.push(format!("{key}={value}"));
+        }
+    }
+    parts.join(", ")
+}
+
+/// Per-filter SUBACK lines, flagged when the broker rejected the filter.
+fn describe_suback(filter: &str, ack: &SubAck) -> Vec<(String, bool)> {
+    let filter = if filter.is_empty() {
+        format!("packet {}", ack.pkid)
+    } else {
+        filter.to_string()
+    };
+    suback_codes(ack)
+        .into_iter()
+        .map(|code| match code {
+            Ok(granted) => (format!("SUBACK {filter}: {granted}"), false),
+            Err(rejected) => (format!("SUBACK {filter}: {rejected}"), true),
+        })
+        .collect()
+}
+
+/// Grant or rejection of each SUBACK reason code, with the broker's reason.
+fn suback_codes(ack: &SubAck) -> Vec<Result<String, String>> {
+    let reason = ack
+        .properties
+        .as_ref()
+        .and_then(|props| props.reason_string.as_deref())
+        .map(|reason| format!(" ({reason})"))
+        .unwrap_or_default();
+    ack.return_codes
+        .iter()
+        .map(|code| match code {
+            SubscribeReasonCode::Success(qos) => Ok(format!("granted {qos:?}{reason}")),
+            rejected => Err(format!("rejected with {rejected:?}{reason}")),
+        })
+        .collect()
+}
+
+/// Disconnect reason as reported by the broker, falling back to the
+/// connection error for transport failures.
+fn error_reason(err: &ConnectionError) -> String {
+    match err {
+        ConnectionError::MqttState(StateError::ServerDisconnect {
+            reason_code,
+            reason_string,
+        }) => disconnect_reason(*reason_code, reason_string.as_deref()),
+        ConnectionError::MqttState(StateError::ConnFail { reason })
+        | ConnectionError::ConnectionRefused(reason) => refusal_reason(*reason),
+        other => match tls_failure(other) {
+            Some(reason) => format!("TLS handshake failed: {reason}"),
+            None => format!("{other:#}"),
+        },
+    }
+}
+
+fn disconnect_reason(code: DisconnectReasonCode, reason: Option<&str>) -> String {
+    match reason {
+        Some(reason) => format!(
+            "broker disconnected: {reason} ({code:?}, 0x{:02X})",
+            code as u8
+        ),
+        None => format!("broker disconnected: {code:?} (0x{:02X})", code as u8),
+    }
+}
+
+fn refusal_reason(code: ConnectReturnCode) -> String {
+    format!("connection refused by broker: {code:?}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rumqttc::v5::mqttbytes::v5::{ConnAckProperties, SubAckProperties};
+
+    #[test]
+    fn broker_reason_codes_are_described() {
+        let err = ConnectionError::MqttState(StateError::ServerDisconnect {
+            reason_code: DisconnectReasonCode::SessionTakenOver,
+            reason_string: Some("client id reused".to_string()),
+        });
+        assert_eq!(
+            error_reason(&err),
+            "broker disconnected: client id reused (SessionTakenOver, 0x8E)"
+        );
+        assert_eq!(
+            error_reason(&ConnectionError::ConnectionRefused(
+                ConnectReturnCode::NotAuthorized
+            )),
+            "connection refused by broker: NotAuthorized"
+        );
+
+        let ack = SubAck {
+            pkid: 3,
+            return_codes: vec![SubscribeReasonCode::NotAuthorized],
+            properties: Some(SubAckProperties {
+                reason_string: Some("acl".to_string()),
+                user_properties: Vec::new(),
+            }),
+        };
+        assert_eq!(
+            describe_suback("air1/#", &ack),
+            vec![(
+                "SUBACK air1/#: rejected with NotAuthorized (acl)".to_string(),
+                true
+            )]
+        );
+
+        let ack = ConnAck {
+            session_present: true,
+            code: ConnectReturnCode::Success,
+            properties: Some(ConnAckProperties {
+                session_expiry_interval: Some(600),
+                receive_max: Some(20),
+                max_qos: None,
+                retain_available: None,
+                max_packet_size: None,
+                assigned_client_identifier: None,
+                topic_alias_max: None,
+                reason_string: None,
+                user_properties: Vec::new(),
+                wildcard_subscription_available: None,
+                subscription_identifiers_available: None,
+                shared_subscription_available: None,
+                server_keep_alive: None,
+                response_information: None,
+                server_reference: None,
+                authentication_method: None,
+                authentication_data: None,
+            }),
+        };
+        assert_eq!(
+            describe_connack(&ack),
+            "CONNACK Success, session resumed, session expiry 600s, receive maximum 20"
+        );
+    }
+
+    #[test]
+    fn publishes_are_converted_for_the_mapper() {
+        let mut p = Publish::new("air1/sensor/co2/state", QoS::AtLeastOnce, "612", None);
+        p.retain = true;
+        let v3 = to_v3_publish(&p).expect("utf-8 topic");
+        assert_eq!(v3.topic, "air1/sensor/co2/state");
+        assert_eq!(v3.qos, rumqttc::QoS::AtLeastOnce);
+        assert!(v3.retain);
+        assert_eq!(&v3.payload[..], b"612");
+    }
+}
@@ -3,15 +3,26 @@ use tracing::{debug, info, warn};
 
 const SERVICE_NAME: &str = "com.air1.monitor";
 const ACCOUNT_NAME: &str = "air1-mqtt";
+const KEY_ACCOUNT_NAME: &str = "air1-mqtt-key";
 
-fn open_entry() -> Result<keyring::Entry> {
-    keyring::Entry::new(SERVICE_NAME, ACCOUNT_NAME)
+/// Keyring account for a profile; the default profile keeps the account
+/// used before profiles existed.
+fn account_name(base: &str, profile: &str) -> String {
+    if profile == crate::config::DEFAULT_PROFILE {
+        base.to_string()
+    } else {
+        format!("{base}:{profile}")
+    }
+}
+
+fn open_entry(account: &str) -> Result<keyring::Entry> {
+    keyring::Entry::new(SERVICE_NAME, account)
         .with_context(|| "failed to access system keyring (Entry::new)")
 }
 
 /// Return true if the system keyring appears usable.
 pub fn keyring_available() -> bool {
-    match open_entry() {
+    match open_entry(ACCOUNT_NAME) {
         Ok(_) => true,
         Err(err) => {
             info!(keyring_available = false, reason = %err, "keyring not available");
@@ -20,43 +31,74 @@ pub fn keyring_available() -> bool {
     }
 }
 
-/// Load the MQTT password from the system keyring if present.
-pub fn load_password() -> Result<Option<String>> {
-    let entry = open_entry().context("failed to open keyring entry")?;
+/// Load a profile's MQTT password from the system keyring if present.
+pub fn load_password(profile: &str) -> Result<Option<String>> {
+    load_secret(&account_name(ACCOUNT_NAME, profile), "password")
+}
+
+/// Save a profile's MQTT password to the system keyring.
+pub fn save_password(profile: &str, secret: &str) -> Result<()> {
+    save_secret(&account_name(ACCOUNT_NAME, profile), "password", secret)
+}
+
+/// Delete a profile's MQTT password from the system keyring.
+pub fn delete_password(profile: &str) -> Result<()> {
+    delete_secret(&account_name(ACCOUNT_NAME, profile), "password")
+}
+
+/// Load a profile's client key passphrase from the system keyring if present.
+pub fn load_key_passphrase(profile: &str) -> Result<Option<String>> {
+    load_secret(&account_name(KEY_ACCOUNT_NAME, profile), "key passphrase")
+}
+
+/// Save a profile's client key passphrase to the system keyring.
+pub fn save_key_passphrase(profile: &str, secret: &str) -> Result<()> {
+    save_secret(
+        &account_name(KEY_ACCOUNT_NAME, profile),
+        "key passphrase",
+        secret,
+    )
+}
+
+/// Delete a profile's client key passphrase from the system keyring.
+pub fn delete_key_passphrase(profile: &str) -> Result<()> {
+    delete_secret(&account_name(KEY_ACCOUNT_NAME, profile), "key passphrase")
+}
+
+fn load_secret(account: &str, what: &str) -> Result<Option<String>> {
+    let entry = open_entry(account).context("failed to open keyring entry")?;
     match entry.get_password() {
         Ok(secret) => Ok(Some(secret)),
         Err(keyring::Error::NoEntry) => {
-            debug!("no password stored in keyring");
+            debug!("no {what} stored in keyring");
             Ok(None)
         }
         Err(err) => {
-            warn!("failed to read password from keyring: {:#}", err);
-            Err(err).context("failed to read password from keyring")
+            warn!("failed to read {what} from keyring: {:#}", err);
+            Err(err).with_context(|| format!("failed to read {what} from keyring"))
         }
     }
 }
 
-/// Save the MQTT password to the system keyring.
-pub fn save_password(secret: &str) -> Result<()> {
-    let entry = open_entry().context("failed to open keyring entry")?;
+fn save_secret(account: &str, what: &str, secret: &str) -> Result<()> {
+    let entry = open_entry(account).context("failed to open keyring entry")?;
     entry
         .set_password(secret)
-        .with_context(|| "failed to write password to keyring")
+        .with_context(|| format!("failed to write {what} to keyring"))
         .map(|_| ())
 }
 
-/// Delete the MQTT password from the system keyring.
-pub fn delete_password() -> Result<()> {
-    let entry = open_entry().context("failed to open keyring entry")?;
+fn delete_secret(account: &str, what: &str) -> Result<()> {
+    let entry = open_entry(account).context("failed to open keyring entry")?;
     match entry.delete_credential() {
         Ok(_) => Ok(()),
         Err(keyring::Error::NoEntry) => {
-            debug!("no keyring entry to delete");
+            debug!("no keyring entry to delete for {what}");
             Ok(())
         }
         Err(err) => {
-            warn!("failed to delete password from keyring: {:#}", err);
-            Err(err).context("failed to delete password from keyring")
+            warn!("failed to delete {what} from keyring: {:#}", err);
+            Err(err).with_context(|| format!("failed to delete {what} from keyring"))
         }
     }
 }
@@ -67,6 +109,19 @@ mod tests {
     use anyhow::Result;
     use std::time::{SystemTime, UNIX_EPOCH};
 
+    #[test]
+    fn default_profile_keeps_legacy_account() {
+        assert_eq!(
+            account_name(ACCOUNT_NAME, crate::config::DEFAULT_PROFILE),
+            ACCOUNT_NAME
+        );
+        assert_eq!(account_name(ACCOUNT_NAME, "office"), "air1-mqtt:office");
+        assert_eq!(
+            account_name(KEY_ACCOUNT_NAME, "office"),
+            "air1-mqtt-key:office"
+        );
+    }
+
     #[test]
     fn save_load_delete_password_roundtrip() -> Result<()> {
         // Skip test on CI (GitHub Actions, GitLab CI, etc.) or if explicitly requested.
@@ -85,27 +140,30 @@ mod tests {
         let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
         let secret = format!("air1-test-secret-{}", now);
 
+        // Use a throwaway profile so a real saved password is left alone
+        let profile = format!("test-{now}");
+
         // Ensure clean state
-        let _ = delete_password();
+        let _ = delete_password(&profile);
 
         // Save
-        save_password(&secret)?;
+        save_password(&profile, &secret)?;
 
         // Load and verify. If the environment cannot persist the secret even
         // though the keyring appears available, skip the assertion to avoid
         // failing CI on systems without a working keyring backend.
-        let loaded = load_password()?;
+        let loaded = load_password(&profile)?;
         if loaded.is_none() {
             eprintln!("password save did not persist; skipping strict verification");
             // best-effort cleanup
-            let _ = delete_password();
+            let _ = delete_password(&profile);
             return Ok(());
         }
         assert_eq!(loaded, Some(secret.clone()));
 
         // Delete and verify removal
-        delete_password()?;
-        let loaded_after = load_password()?;
+        delete_password(&profile)?;
+        let loaded_after = load_password(&profile)?;
         assert_eq!(loaded_after, None);
 
         Ok(())
@@ -2,58 +2,25 @@ use gtk4::cairo;
 use gtk4::glib;
 use gtk4::prelude::*;
 use gtk4::{Application, ApplicationWindow};
-use std::{cell::Cell, cell::RefCell, f64::consts::PI, rc::Rc, time::Duration};
+use std::{
+    cell::Cell,
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, VecDeque},
+    f64::consts::PI,
+    rc::Rc,
+    time::{Duration, SystemTime},
+};
 
 use crate::app::{Air1App, MqttState};
+use crate::capture;
 use crate::config;
-
-// ── Quality ranges ─────────────────────────────────────────────────────────────
-
-const PM25_RANGES: &[(f64, f64, &str)] = &[
-    (0.0, 12.0, "Good"),
-    (12.0, 35.0, "Moderate"),
-    (35.0, 55.0, "Unhealthy (Sensitive)"),
-    (55.0, 150.0, "Unhealthy"),
-    (150.0, 250.0, "Very Unhealthy"),
-];
-const PM10_RANGES: &[(f64, f64, &str)] = &[
-    (0.0, 54.0, "Good"),
-    (54.0, 154.0, "Moderate"),
-    (154.0, 254.0, "Unhealthy (Sensitive)"),
-    (254.0, 354.0, "Unhealthy"),
-    (354.0, 424.0, "Very Unhealthy"),
-];
-const PM1_RANGES: &[(f64, f64, &str)] = &[
-    (0.0, 10.0, "Good"),
-    (10.0, 25.0, "Moderate"),
-    (25.0, 50.0, "Unhealthy"),
-];
-const CO2_RANGES: &[(f64, f64, &str)] = &[
-    (0.0, 800.0, "Excellent"),
-    (800.0, 1000.0, "Good"),
-    (1000.0, 1500.0, "Acceptable"),
-    (1500.0, 2000.0, "Poor"),
-    (2000.0, 5000.0, "Bad"),
-];
-const TVOC_RANGES: &[(f64, f64, &str)] = &[
-    (0.0, 220.0, "Excellent"),
-    (220.0, 660.0, "Good"),
-    (660.0, 1430.0, "Moderate"),
-    (1430.0, 2200.0, "Poor"),
-    (2200.0, 5500.0, "Unhealthy"),
-];
-const TEMP_RANGES: &[(f64, f64, &str)] = &[
-    (32.0, 64.0, "Cool"),
-    (64.0, 75.0, "Comfortable"),
-    (75.0, 82.0, "Warm"),
-    (82.0, 104.0, "Hot"),
-];
-const HUMIDITY_RANGES: &[(f64, f64, &str)] = &[
-    (0.0, 30.0, "Dry"),
-    (30.0, 60.0, "Comfortable"),
-    (60.0, 80.0, "Humid"),
-    (80.0, 100.0, "Very Humid"),
-];
+use crate::diagnostics;
+use crate::explorer;
+use crate::inspector;
+use crate::metrics::{self, MetricDescriptor};
+use crate::mqtt;
+use crate::tls;
+use crate::wizard;
 
 // ── CSS ────────────────────────────────────────────────────────────────────────
 
@@ -64,6 +31,8 @@ const CSS: &str = r#"
 .quality-3 { color: rgb(244, 67, 54); }
 .quality-4 { color: rgb(156, 39, 176); }
 .quality-none { color: rgb(150, 150, 150); }
+.quality-unavailable { color: rgb(255, 152, 0); font-style: italic; }
+.gauge-stale { opacity: 0.45; }
 
 
 
@@ -97,12 +66,20 @@ struct GaugeWidgets {
     card: gtk4::Box,
     data_box: gtk4::Box,
     no_data_box: gtk4::Box,
+    no_data_label: gtk4::Label,
     drawing_area: gtk4::DrawingArea,
     current_value: Rc<Cell<Option<f64>>>,
     value_label: gtk4::Label,
     quality_label: gtk4::Label,
-    metric_id: &'static str,
-    ranges: &'static [(f64, f64, &'static str)],
+    age_label: gtk4::Label,
+    source_label: gtk4::Label,
+    metric: &'static MetricDescriptor,
+}
+
+struct HistogramWidgets {
+    drawing_area: gtk4::DrawingArea,
+    /// `(label, value)` per enabled bin, smallest particles first.
+    bars: Rc<RefCell<Vec<(&'static str, Option<f64>)>>>,
 }
 
 struct SectionWidget {
@@ -118,6 +95,10 @@ struct AppWidgets {
     connection_label: gtk4::Label,
     availability_label: gtk4::Label,
     last_update_label: gtk4::Label,
+    decode_failures_label: gtk4::Label,
+    cert_expiry_label: gtk4::Label,
+    device_dropdown: gtk4::DropDown,
+    profile_dropdown: gtk4::DropDown,
     overall_quality_box: gtk4::Box,
     overall_quality_label: gtk4::Label,
     overall_quality_pm25: gtk4::Label,
@@ -125,6 +106,7 @@ struct AppWidgets {
     last_topic_label: gtk4::Label,
     sections: Vec<SectionWidget>,
     gauges: Vec<GaugeWidgets>,
+    histogram: HistogramWidgets,
 }
 
 // ── Entry point ───────────────────────────────────────────────────────────────
@@ -151,13 +133,27 @@ pub fn build_ui(gtk_app: &Application, state: Rc<RefCell<Air1App>>, title: &str)
     // 100 ms polling timer
     let state_tick = state.clone();
     let widgets_tick = widgets.clone();
+    let window_tick: gtk4::Window = window.clone().upcast();
+    let pin_prompt_open = Rc::new(Cell::new(false));
     glib::timeout_add_local(Duration::from_millis(100), move || {
-        {
+        let pending_pin = {
             let mut app = state_tick.borrow_mut();
             app.poll_mqtt();
             app.poll_tests();
-        }
+            app.pending_pin.clone()
+        };
         update_widgets(&state_tick.borrow(), &widgets_tick);
+        if let Some(info) = pending_pin
+            && !pin_prompt_open.get()
+        {
+            pin_prompt_open.set(true);
+            show_pin_dialog(
+                state_tick.clone(),
+                &window_tick,
+                &info,
+                pin_prompt_open.clone(),
+            );
+        }
         glib::ControlFlow::Continue
     });
 
@@ -179,7 +175,14 @@ fn build_window_contents(window: &ApplicationWindow, state: Rc<RefCell<Air1App>>
     let view_section = gtk4::gio::Menu::new();
     view_section.append(Some("Configuration"), Some("win.show-config"));
     view_section.append(Some("Edit Layout"), Some("win.show-layout"));
+    view_section.append(Some("Topic Explorer"), Some("win.show-explorer"));
+    view_section.append(Some("Traffic Inspector"), Some("win.show-inspector"));
     menu_model.append_section(Some("View"), &view_section);
+    let capture_section = gtk4::gio::Menu::new();
+    capture_section.append(Some("Record Capture…"), Some("win.record-capture"));
+    capture_section.append(Some("Stop Recording"), Some("win.stop-recording"));
+    capture_section.append(Some("Replay Capture…"), Some("win.replay-capture"));
+    menu_model.append_section(Some("Capture"), &capture_section);
 
     let menu_btn = gtk4::MenuButton::builder()
         .label("Menu")
@@ -187,6 +190,31 @@ fn build_window_contents(window: &ApplicationWindow, state: Rc<RefCell<Air1App>>
         .build();
     header.pack_start(&menu_btn);
 
+    // Broker profile switcher
+    let profile_dropdown =
+        gtk4::DropDown::new(Some(gtk4::StringList::new(&[])), None::<gtk4::Expression>);
+    profile_dropdown.set_tooltip_text(Some("Broker profile"));
+    header.pack_start(&profile_dropdown);
+    {
+        let state_c = state.clone();
+        profile_dropdown.connect_selected_notify(move |dd| {
+            // Programmatic updates happen while the tick holds the state borrow.
+            let Ok(mut app) = state_c.try_borrow_mut() else {
+                return;
+            };
+            let Some(profile) = dd
+                .selected_item()
+                .and_downcast::<gtk4::StringObject>()
+                .map(|item| item.string().to_string())
+            else {
+                return;
+            };
+            if app.cfg.profile != profile {
+                app.switch_profile(&profile);
+            }
+        });
+    }
+
     // Status row in header
     let status_label = gtk4::Label::new(Some(""));
     header.set_title_widget(Some(&status_label));
@@ -222,6 +250,9 @@ fn build_window_contents(window: &ApplicationWindow, state: Rc<RefCell<Air1App>>
         conn_label,
         avail_label,
         update_label,
+        decode_label,
+        cert_label,
+        device_dd,
         quality_box,
         quality_lbl,
         quality_pm25,
@@ -234,14 +265,7 @@ fn build_window_contents(window: &ApplicationWindow, state: Rc<RefCell<Air1App>>
     });
 
     // Air Quality section
-    let (aq_frame, mut aq_gauges) = build_metric_section(
-        "Air Quality (Particulate Matter)",
-        &[
-            ("pm25", "PM2.5", "μg/m³", PM25_RANGES, 250.0),
-            ("pm10", "PM10", "μg/m³", PM10_RANGES, 500.0),
-            ("pm1", "PM1", "μg/m³", PM1_RANGES, 100.0),
-        ],
-    );
+    let (aq_frame, mut aq_gauges) = build_metric_section("air_quality");
     content.append(&aq_frame);
     sections_vec.push(SectionWidget {
         id: "air_quality".into(),
@@ -249,14 +273,16 @@ fn build_window_contents(window: &ApplicationWindow, state: Rc<RefCell<Air1App>>
     });
     gauges_vec.append(&mut aq_gauges);
 
+    // Particle size distribution (number concentration bins)
+    let (pc_frame, histogram) = build_histogram_section();
+    content.append(&pc_frame);
+    sections_vec.push(SectionWidget {
+        id: metrics::PARTICLE_COUNT_SECTION.into(),
+        widget: pc_frame.upcast(),
+    });
+
     // Gas section
-    let (gas_frame, mut gas_gauges) = build_metric_section(
-        "Gas Sensors",
-        &[
-            ("co2", "CO₂", "ppm", CO2_RANGES, 5000.0),
-            ("tvoc", "TVOC", "ppb", TVOC_RANGES, 5500.0),
-        ],
-    );
+    let (gas_frame, mut gas_gauges) = build_metric_section("gas");
     content.append(&gas_frame);
     sections_vec.push(SectionWidget {
         id: "gas".into(),
@@ -264,6 +290,15 @@ fn build_window_contents(window: &ApplicationWindow, state: Rc<RefCell<Air1App>>
     });
     gauges_vec.append(&mut gas_gauges);
 
+    // Other gases section (MiCS-4514 channels)
+    let (other_gas_frame, mut other_gas_gauges) = build_metric_section("other_gases");
+    content.append(&other_gas_frame);
+    sections_vec.push(SectionWidget {
+        id: "other_gases".into(),
+        widget: other_gas_frame.upcast(),
+    });
+    gauges_vec.append(&mut other_gas_gauges);
+
     // Environment section with last-topic label
     let (env_frame, mut env_gauges, last_topic_lbl) = build_environment_section();
     content.append(&env_frame);
@@ -297,12 +332,129 @@ fn build_window_contents(window: &ApplicationWindow, state: Rc<RefCell<Air1App>>
     }
     window.add_action(&show_layout_action);
 
+    let show_explorer_action = gtk4::gio::SimpleAction::new("show-explorer", None);
+    {
+        let state_c = state.clone();
+        let win_c: gtk4::Window = window.clone().upcast();
+        show_explorer_action.connect_activate(move |_, _| {
+            show_explorer_window(state_c.clone(), &win_c);
+        });
+    }
+    window.add_action(&show_explorer_action);
+
+    let show_inspector_action = gtk4::gio::SimpleAction::new("show-inspector", None);
+    {
+        let state_c = state.clone();
+        let win_c: gtk4::Window = window.clone().upcast();
+        show_inspector_action.connect_activate(move |_, _| {
+            show_inspector_window(state_c.clone(), &win_c);
+        });
+    }
+    window.add_action(&show_inspector_action);
+
+    let record_action = gtk4::gio::SimpleAction::new("record-capture", None);
+    {
+        let state_c = state.clone();
+        let win_c: gtk4::Window = window.clone().upcast();
+        record_action.connect_activate(move |_, _| {
+            let dialog = gtk4::FileChooserDialog::new(
+                Some("Record Capture"),
+                Some(&win_c),
+                gtk4::FileChooserAction::Save,
+                &[
+                    ("Cancel", gtk4::ResponseType::Cancel),
+                    ("Record", gtk4::ResponseType::Accept),
+                ],
+            );
+            dialog.set_modal(true);
+            dialog.set_current_name("air1-capture.jsonl");
+            let state_c = state_c.clone();
+            dialog.connect_response(move |d, response| {
+                if response == gtk4::ResponseType::Accept
+                    && let Some(path) = d.file().and_then(|f| f.path())
+                {
+                    state_c.borrow_mut().start_recording(&path);
+                }
+                d.close();
+            });
+            dialog.present();
+        });
+    }
+    window.add_action(&record_action);
+
+    let stop_recording_action = gtk4::gio::SimpleAction::new("stop-recording", None);
+    {
+        let state_c = state.clone();
+        stop_recording_action.connect_activate(move |_, _| {
+            state_c.borrow_mut().stop_recording();
+        });
+    }
+    window.add_action(&stop_recording_action);
+
+    let replay_action = gtk4::gio::SimpleAction::new("replay-capture", None);
+    {
+        let state_c = state.clone();
+        let win_c: gtk4::Window = window.clone().upcast();
+        replay_action.connect_activate(move |_, _| {
+            let dialog = gtk4::FileChooserDialog::new(
+                Some("Replay Capture"),
+                Some(&win_c),
+                gtk4::FileChooserAction::Open,
+                &[
+                    ("Cancel", gtk4::ResponseType::Cancel),
+                    ("Replay", gtk4::ResponseType::Accept),
+                ],
+            );
+            dialog.set_modal(true);
+            let captures = gtk4::FileFilter::new();
+            captures.set_name(Some("Captures"));
+            captures.add_pattern("*.jsonl");
+            dialog.add_filter(&captures);
+            let all = gtk4::FileFilter::new();
+            all.set_name(Some("All files"));
+            all.add_pattern("*");
+            dialog.add_filter(&all);
+            dialog.add_choice(
+                "speed",
+                "Speed",
+                &[
+                    ("1", "1x"),
+                    ("4", "4x"),
+                    ("20", "20x"),
+                    ("max", "As fast as possible"),
+                ],
+            );
+            dialog.set_choice("speed", "1");
+            let state_c = state_c.clone();
+            dialog.connect_response(move |d, response| {
+                if response == gtk4::ResponseType::Accept
+                    && let Some(path) = d.file().and_then(|f| f.path())
+                {
+                    let speed = d
+                        .choice("speed")
+                        .and_then(|choice| choice.parse().ok())
+                        .unwrap_or(capture::ReplaySpeed::Scaled(1.0));
+                    state_c.borrow_mut().start_replay(&path, speed);
+                }
+                d.close();
+            });
+            dialog.present();
+        });
+    }
+    window.add_action(&replay_action);
+
     // ── Details button action ─────────────────────────────────────────────────
     {
         let state_c = state.clone();
         let win_c: gtk4::Window = window.clone().upcast();
         details_button.connect_clicked(move |_| {
-            let msg = state_c.borrow().status.clone();
+            let app = state_c.borrow();
+            let mut msg = app.status.clone();
+            if !app.session_details.is_empty() {
+                msg.push_str("\n\nBroker session:\n");
+                msg.push_str(&app.session_details.join("\n"));
+            }
+            drop(app);
             let dlg = gtk4::MessageDialog::builder()
                 .transient_for(&win_c)
                 .modal(true)
@@ -324,6 +476,10 @@ fn build_window_contents(window: &ApplicationWindow, state: Rc<RefCell<Air1App>>
         connection_label: conn_label,
         availability_label: avail_label,
         last_update_label: update_label,
+        decode_failures_label: decode_label,
+        cert_expiry_label: cert_label,
+        device_dropdown: device_dd,
+        profile_dropdown,
         overall_quality_box: quality_box,
         overall_quality_label: quality_lbl,
         overall_quality_pm25: quality_pm25,
@@ -331,21 +487,13 @@ fn build_window_contents(window: &ApplicationWindow, state: Rc<RefCell<Air1App>>
         last_topic_label: last_topic_lbl,
         sections: sections_vec,
         gauges: gauges_vec,
+        histogram,
     }
 }
 
 // ── Section builders ──────────────────────────────────────────────────────────
 
-/// `(metric_id, label, unit, ranges, max_value)` descriptor for a gauge card.
-type GaugeSpec = (
-    &'static str,
-    &'static str,
-    &'static str,
-    &'static [(f64, f64, &'static str)],
-    f64,
-);
-
-#[allow(clippy::too_many_arguments)]
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn build_overview_section(
     state: Rc<RefCell<Air1App>>,
     window: &ApplicationWindow,
@@ -356,6 +504,9 @@ fn build_overview_section(
     gtk4::Label,
     gtk4::Label,
     gtk4::Label,
+    gtk4::Label,
+    gtk4::Label,
+    gtk4::DropDown,
     gtk4::Box,
     gtk4::Label,
     gtk4::Label,
@@ -400,6 +551,16 @@ fn build_overview_section(
     let last_update_label = gtk4::Label::new(Some(""));
     status_row.append(&last_update_label);
 
+    let decode_failures_label = gtk4::Label::new(None);
+    decode_failures_label.add_css_class("warn-label");
+    decode_failures_label.set_visible(false);
+    status_row.append(&decode_failures_label);
+
+    let cert_expiry_label = gtk4::Label::new(None);
+    cert_expiry_label.add_css_class("warn-label");
+    cert_expiry_label.set_visible(false);
+    status_row.append(&cert_expiry_label);
+
     vbox.append(&status_row);
 
     // Start / Stop buttons
@@ -409,6 +570,13 @@ fn build_overview_section(
     stop_btn.set_sensitive(false);
     btn_row.append(&start_btn);
     btn_row.append(&stop_btn);
+
+    // Device selector, filled as devices report in
+    let device_dropdown =
+        gtk4::DropDown::new(Some(gtk4::StringList::new(&[])), None::<gtk4::Expression>);
+    device_dropdown.set_tooltip_text(Some("Device shown on the dashboard"));
+    device_dropdown.set_visible(false);
+    btn_row.append(&device_dropdown);
     vbox.append(&btn_row);
 
     // Button callbacks
@@ -435,11 +603,31 @@ fn build_overview_section(
         });
     }
     {
-        let state_c = state;
+        let state_c = state.clone();
         stop_btn.connect_clicked(move |_| {
             state_c.borrow_mut().stop_mqtt();
         });
     }
+    {
+        let state_c = state;
+        device_dropdown.connect_selected_notify(move |dd| {
+            // Programmatic updates happen while the tick holds the state borrow.
+            let Ok(mut app) = state_c.try_borrow_mut() else {
+                return;
+            };
+            let Some(device) = dd
+                .selected_item()
+                .and_downcast::<gtk4::StringObject>()
+                .map(|item| item.string().to_string())
+            else {
+                return;
+            };
+            if app.selected_device() != Some(device.as_str()) {
+                app.select_device(&device);
+                app.save_all();
+            }
+        });
+    }
 
     (
         frame,
@@ -448,6 +636,9 @@ fn build_overview_section(
         connection_label,
         availability_label,
         last_update_label,
+        decode_failures_label,
+        cert_expiry_label,
+        device_dropdown,
         quality_box,
         quality_label,
         quality_pm25,
@@ -455,8 +646,9 @@ fn build_overview_section(
     )
 }
 
-fn build_metric_section(title: &str, gauges: &[GaugeSpec]) -> (gtk4::Frame, Vec<GaugeWidgets>) {
-    let frame = gtk4::Frame::new(Some(title));
+/// Build a section with a gauge card for each of its registry metrics.
+fn build_metric_section(section_id: &str) -> (gtk4::Frame, Vec<GaugeWidgets>) {
+    let frame = gtk4::Frame::new(Some(&Air1App::section_title(section_id)));
     let flow = gtk4::FlowBox::new();
     flow.set_selection_mode(gtk4::SelectionMode::None);
     flow.set_margin_top(6);
@@ -468,8 +660,8 @@ fn build_metric_section(title: &str, gauges: &[GaugeSpec]) -> (gtk4::Frame, Vec<
     frame.set_child(Some(&flow));
 
     let mut gauge_widgets = Vec::new();
-    for &(id, label, unit, ranges, max_value) in gauges {
-        let (child, gw) = build_gauge_card(id, label, unit, ranges, max_value);
+    for metric in metrics::section_metrics(section_id) {
+        let (child, gw) = build_gauge_card(metric);
         flow.insert(&child, -1);
         gauge_widgets.push(gw);
     }
@@ -477,7 +669,7 @@ fn build_metric_section(title: &str, gauges: &[GaugeSpec]) -> (gtk4::Frame, Vec<
 }
 
 fn build_environment_section() -> (gtk4::Frame, Vec<GaugeWidgets>, gtk4::Label) {
-    let frame = gtk4::Frame::new(Some("Environment"));
+    let frame = gtk4::Frame::new(Some(&Air1App::section_title("environment")));
     let vbox = gtk4::Box::new(gtk4::Orientation::Vertical, 4);
     vbox.set_margin_top(6);
     vbox.set_margin_bottom(6);
@@ -492,11 +684,8 @@ fn build_environment_section() -> (gtk4::Frame, Vec<GaugeWidgets>, gtk4::Label)
     vbox.append(&flow);
 
     let mut gauge_widgets = Vec::new();
-    for &(id, label, unit, ranges, max_value) in &[
-        ("temperature", "Temperature", "°F", TEMP_RANGES, 104.0f64),
-        ("humidity", "Humidity", "%", HUMIDITY_RANGES, 100.0f64),
-    ] {
-        let (child, gw) = build_gauge_card(id, label, unit, ranges, max_value);
+    for metric in metrics::section_metrics("environment") {
+        let (child, gw) = build_gauge_card(metric);
         flow.insert(&child, -1);
         gauge_widgets.push(gw);
     }
@@ -509,15 +698,32 @@ fn build_environment_section() -> (gtk4::Frame, Vec<GaugeWidgets>, gtk4::Label)
     (frame, gauge_widgets, last_topic_label)
 }
 
+fn build_histogram_section() -> (gtk4::Frame, HistogramWidgets) {
+    let frame = gtk4::Frame::new(Some(&Air1App::section_title(
+        metrics::PARTICLE_COUNT_SECTION,
+    )));
+    let drawing_area = gtk4::DrawingArea::new();
+    drawing_area.set_size_request(-1, 180);
+    drawing_area.set_hexpand(true);
+    drawing_area.set_margin_top(6);
+    drawing_area.set_margin_bottom(6);
+    drawing_area.set_margin_start(6);
+    drawing_area.set_margin_end(6);
+    frame.set_child(Some(&drawing_area));
+
+    let bars: Rc<RefCell<Vec<(&'static str, Option<f64>)>>> = Rc::new(RefCell::new(Vec::new()));
+    {
+        let bars_c = bars.clone();
+        drawing_area.set_draw_func(move |_, ctx, width, height| {
+            draw_histogram(ctx, width, height, &bars_c.borrow());
+        });
+    }
+    (frame, HistogramWidgets { drawing_area, bars })
+}
+
 // ── Gauge card ────────────────────────────────────────────────────────────────
 
-fn build_gauge_card(
-    metric_id: &'static str,
-    label: &str,
-    _unit: &'static str,
-    ranges: &'static [(f64, f64, &'static str)],
-    max_value: f64,
-) -> (gtk4::FlowBoxChild, GaugeWidgets) {
+fn build_gauge_card(metric: &'static MetricDescriptor) -> (gtk4::FlowBoxChild, GaugeWidgets) {
     let card = gtk4::Box::new(gtk4::Orientation::Vertical, 4);
     card.set_margin_top(8);
     card.set_margin_bottom(8);
@@ -525,7 +731,7 @@ fn build_gauge_card(
     card.set_margin_end(8);
     card.set_width_request(180);
 
-    let name_label = gtk4::Label::new(Some(label));
+    let name_label = gtk4::Label::new(Some(metric.label));
     name_label.add_css_class("metric-name");
     card.append(&name_label);
 
@@ -536,7 +742,14 @@ fn build_gauge_card(
     {
         let cv = current_value.clone();
         drawing_area.set_draw_func(move |_, ctx, width, height| {
-            draw_arc_gauge(ctx, width, height, cv.get(), ranges, max_value);
+            draw_arc_gauge(
+                ctx,
+                width,
+                height,
+                cv.get(),
+                metric.ranges,
+                metric.gauge_max,
+            );
         });
     }
     card.append(&drawing_area);
@@ -552,6 +765,19 @@ fn build_gauge_card(
     quality_label.add_css_class("quality-none");
     data_box.append(&quality_label);
 
+    // Age of a stale reading (hidden while fresh)
+    let age_label = gtk4::Label::new(None);
+    age_label.add_css_class("quality-none");
+    age_label.set_visible(false);
+    data_box.append(&age_label);
+
+    // Active source: topic, or a summary for aggregated metrics
+    let source_label = gtk4::Label::new(None);
+    source_label.add_css_class("last-topic");
+    source_label.set_ellipsize(gtk4::pango::EllipsizeMode::Start);
+    source_label.set_max_width_chars(24);
+    data_box.append(&source_label);
+
     card.append(&data_box);
 
     // No-data box (shown when value is absent)
@@ -570,12 +796,14 @@ fn build_gauge_card(
         card,
         data_box,
         no_data_box,
+        no_data_label,
         drawing_area,
         current_value,
         value_label,
         quality_label,
-        metric_id,
-        ranges,
+        age_label,
+        source_label,
+        metric,
     };
     (child, gw)
 }
@@ -602,32 +830,44 @@ fn update_widgets(app: &Air1App, w: &AppWidgets) {
     w.connection_label.set_text(conn_text);
     clear_css_classes(
         &w.connection_label,
-        &["connection-online", "connection-offline", "connection-pending"],
+        &[
+            "connection-online",
+            "connection-offline",
+            "connection-pending",
+        ],
     );
     w.connection_label.add_css_class(conn_class);
 
     // Availability
+    let all_devices_offline = !app.device_availability.is_empty()
+        && app.offline_devices().len() == app.device_availability.len();
+    // Each metric goes stale on its own timeout; summarize across them.
+    let stale = app.stale_metrics();
+    let reported = app.metrics().readings().count();
     let (avail_text, avail_class) = match app.mqtt_state {
-        MqttState::Connected => match app.metrics.last_update {
-            Some(ts) => {
-                let age = ts.elapsed().as_secs();
-                if age <= 15 {
-                    ("fresh", "avail-fresh")
-                } else if age <= 60 {
-                    ("stale", "avail-stale")
-                } else {
-                    ("stalled", "avail-stalled")
-                }
-            }
-            None => ("no data", "avail-nodata"),
-        },
-        MqttState::Starting => ("starting", "avail-reconnecting"),
-        MqttState::Reconnecting => ("reconnecting", "avail-reconnecting"),
-        MqttState::Stopping => ("stopping", "avail-offline"),
-        MqttState::Stopped => ("offline", "avail-offline"),
+        MqttState::Connected if all_devices_offline => {
+            ("device offline".to_string(), "avail-stalled")
+        }
+        MqttState::Connected if reported == 0 => ("no data".to_string(), "avail-nodata"),
+        MqttState::Connected if stale.is_empty() => ("fresh".to_string(), "avail-fresh"),
+        MqttState::Connected if stale.len() == reported => ("stalled".to_string(), "avail-stalled"),
+        MqttState::Connected => (format!("{} stale", stale.len()), "avail-stale"),
+        MqttState::Starting => ("starting".to_string(), "avail-reconnecting"),
+        MqttState::Reconnecting => ("reconnecting".to_string(), "avail-reconnecting"),
+        MqttState::Stopping => ("stopping".to_string(), "avail-offline"),
+        MqttState::Stopped => ("offline".to_string(), "avail-offline"),
     };
     w.availability_label
         .set_text(&format!("Availability: {avail_text}"));
+    let stale_labels: Vec<String> = stale.iter().map(|id| Air1App::gauge_label(id)).collect();
+    w.availability_label.set_tooltip_text(
+        if stale_labels.is_empty() {
+            None
+        } else {
+            Some(format!("Stale: {}", stale_labels.join(", ")))
+        }
+        .as_deref(),
+    );
     clear_css_classes(
         &w.availability_label,
         &[
@@ -642,19 +882,52 @@ fn update_widgets(app: &Air1App, w: &AppWidgets) {
     w.availability_label.add_css_class(avail_class);
 
     // Last update
-    if let Some(ts) = app.metrics.last_update {
-        w.last_update_label
-            .set_text(&format!("Last update: {}s ago", ts.elapsed().as_secs()));
+    if let Some(reading) = app.metrics().last_reading() {
+        w.last_update_label.set_text(&format!(
+            "Last update: {}s ago",
+            reading.timestamp.elapsed().as_secs()
+        ));
     } else {
         w.last_update_label.set_text("");
     }
 
+    // Decode failures
+    if app.decode_failures > 0 {
+        w.decode_failures_label
+            .set_text(&format!("Decode failures: {}", app.decode_failures));
+        w.decode_failures_label
+            .set_tooltip_text(app.last_decode_error.as_deref());
+        w.decode_failures_label.set_visible(true);
+    } else {
+        w.decode_failures_label.set_visible(false);
+    }
+
+    // Certificate expiry
+    let cert_warnings = app.certificate_warnings();
+    if app.connected && !cert_warnings.is_empty() {
+        let text = match cert_warnings.len() {
+            1 => cert_warnings[0].clone(),
+            n => format!("{} (+{} more)", cert_warnings[0], n - 1),
+        };
+        w.cert_expiry_label.set_text(&text);
+        w.cert_expiry_label
+            .set_tooltip_text(Some(&cert_warnings.join("\n")));
+        w.cert_expiry_label.set_visible(true);
+    } else {
+        w.cert_expiry_label.set_visible(false);
+    }
+
+    // Device selector
+    update_device_selector(app, &w.device_dropdown);
+    update_profile_selector(app, &w.profile_dropdown);
+
     // Overall quality banner
     update_quality_banner(app, w);
 
     // Last topic
-    if let Some(topic) = &app.metrics.last_topic {
-        w.last_topic_label.set_text(&format!("Last topic: {topic}"));
+    if let Some(reading) = app.metrics().last_reading() {
+        w.last_topic_label
+            .set_text(&format!("Last topic: {}", reading.topic));
         w.last_topic_label.set_visible(true);
     } else {
         w.last_topic_label.set_visible(false);
@@ -675,19 +948,78 @@ fn update_widgets(app: &Air1App, w: &AppWidgets) {
 
     // Gauge section: respect gauge-level enabled flags and update values
     for g in &w.gauges {
-        let value = metric_value(app, g.metric_id);
+        let value = app.metrics().value(g.metric.id);
+        g.card.set_visible(gauge_enabled(app, g.metric.id));
+        update_gauge(
+            g,
+            value,
+            app.metrics().unit(g.metric.id),
+            app.metrics().is_unavailable(g.metric.id),
+        );
+        update_gauge_source(app, g);
+        update_gauge_staleness(app, g);
+    }
 
-        // Check if this gauge is enabled in config
-        let enabled = app
-            .cfg
-            .dashboard
-            .sections
-            .iter()
-            .any(|s| s.gauges.iter().any(|gg| gg.id == g.metric_id && gg.enabled));
-        g.card.set_visible(enabled);
+    // Particle count histogram: one bar per enabled bin
+    let bars: Vec<(&'static str, Option<f64>)> =
+        metrics::section_metrics(metrics::PARTICLE_COUNT_SECTION)
+            .filter(|m| gauge_enabled(app, m.id))
+            .map(|m| (m.label, app.metrics().value(m.id)))
+            .collect();
+    if *w.histogram.bars.borrow() != bars {
+        *w.histogram.bars.borrow_mut() = bars;
+        w.histogram.drawing_area.queue_draw();
+    }
+}
+
+/// Whether a gauge (or histogram bin) is enabled in the dashboard config.
+fn gauge_enabled(app: &Air1App, id: &str) -> bool {
+    app.cfg
+        .dashboard
+        .sections
+        .iter()
+        .any(|s| s.gauges.iter().any(|g| g.id == id && g.enabled))
+}
 
-        update_gauge(g, value);
+fn update_profile_selector(app: &Air1App, dropdown: &gtk4::DropDown) {
+    let profiles = app.cfg.profile_names();
+    if let Some(list) = dropdown.model().and_downcast::<gtk4::StringList>() {
+        let listed: Vec<String> = (0..list.n_items())
+            .filter_map(|i| list.string(i))
+            .map(|s| s.to_string())
+            .collect();
+        if listed != profiles {
+            list.splice(0, list.n_items(), &profiles);
+        }
+    }
+    if let Some(idx) = profiles.iter().position(|p| *p == app.cfg.profile)
+        && dropdown.selected() != idx as u32
+    {
+        dropdown.set_selected(idx as u32);
+    }
+    // A single profile needs no switcher.
+    dropdown.set_visible(profiles.len() > 1);
+}
+
+fn update_device_selector(app: &Air1App, dropdown: &gtk4::DropDown) {
+    let devices: Vec<&str> = app.devices.keys().map(String::as_str).collect();
+    if let Some(list) = dropdown.model().and_downcast::<gtk4::StringList>() {
+        let listed: Vec<String> = (0..list.n_items())
+            .filter_map(|i| list.string(i))
+            .map(|s| s.to_string())
+            .collect();
+        if listed != devices {
+            list.splice(0, list.n_items(), &devices);
+        }
+    }
+    if let Some(idx) = app
+        .selected_device()
+        .and_then(|device| devices.iter().position(|d| *d == device))
+        && dropdown.selected() != idx as u32
+    {
+        dropdown.set_selected(idx as u32);
     }
+    dropdown.set_visible(!devices.is_empty());
 }
 
 fn update_quality_banner(app: &Air1App, w: &AppWidgets) {
@@ -708,26 +1040,28 @@ fn update_quality_banner(app: &Air1App, w: &AppWidgets) {
         "quality-none",
     ];
 
-    let (banner_class, quality_class, text, pm25_text) = if let Some(pm25) = app.metrics.pm25 {
-        let idx = Air1App::quality_index(pm25, PM25_RANGES);
-        let (bclass, qclass) = (banner_classes[idx], quality_classes[idx]);
-        let labels = [
-            "Excellent Air Quality",
-            "Good Air Quality",
-            "Moderate Air Quality",
-            "Poor Air Quality",
-            "Unhealthy Air Quality",
-        ];
-        let pm25_str = format!("  PM2.5: {pm25:.1} μg/m³");
-        (bclass, qclass, labels[idx], pm25_str)
-    } else {
-        (
-            "banner-unknown",
-            "quality-none",
-            "Air Quality Unknown",
-            String::new(),
-        )
-    };
+    let pm25_metric = metrics::descriptor("pm25").expect("pm25 is a built-in metric");
+    let (banner_class, quality_class, text, pm25_text) =
+        if let Some(pm25) = app.fresh_value(pm25_metric.id) {
+            let idx = Air1App::quality_index(pm25, pm25_metric.ranges);
+            let (bclass, qclass) = (banner_classes[idx], quality_classes[idx]);
+            let labels = [
+                "Excellent Air Quality",
+                "Good Air Quality",
+                "Moderate Air Quality",
+                "Poor Air Quality",
+                "Unhealthy Air Quality",
+            ];
+            let pm25_str = format!("  PM2.5: {pm25:.1} {}", app.metrics().unit(pm25_metric.id));
+            (bclass, qclass, labels[idx], pm25_str)
+        } else {
+            (
+                "banner-unknown",
+                "quality-none",
+                "Air Quality Unknown",
+                String::new(),
+            )
+        };
 
     clear_css_classes(&w.overall_quality_box, &banner_classes);
     w.overall_quality_box.add_css_class(banner_class);
@@ -740,21 +1074,30 @@ fn update_quality_banner(app: &Air1App, w: &AppWidgets) {
 
     // Warnings
     let mut warn_parts = Vec::new();
-    if let Some(co2) = app.metrics.co2
+    if let Some(co2) = app.fresh_value("co2")
         && co2 > 2000.0
     {
         warn_parts.push(format!("⚠ High CO₂ {co2:.0}ppm"));
     }
-    if let Some(tvoc) = app.metrics.tvoc
+    if let Some(tvoc) = app.fresh_value("tvoc")
         && tvoc > 2200.0
     {
         warn_parts.push(format!("⚠ High VOC {tvoc:.0}ppb"));
     }
+    for device in app.offline_devices() {
+        warn_parts.push(format!("⚠ {device} offline"));
+    }
+    for metric in app.conflicting_metrics() {
+        warn_parts.push(format!(
+            "⚠ {} has several sources",
+            Air1App::gauge_label(metric)
+        ));
+    }
     w.overall_warnings.set_text(&warn_parts.join("  "));
     w.overall_warnings.set_visible(!warn_parts.is_empty());
 }
 
-fn update_gauge(g: &GaugeWidgets, value: Option<f64>) {
+fn update_gauge(g: &GaugeWidgets, value: Option<f64>, unit: &str, unavailable: bool) {
     let quality_classes = [
         "quality-0",
         "quality-1",
@@ -771,11 +1114,10 @@ fn update_gauge(g: &GaugeWidgets, value: Option<f64>) {
         g.current_value.set(Some(v));
         g.drawing_area.queue_draw();
 
-        let idx = Air1App::quality_index(v, g.ranges);
-        let label = Air1App::get_quality_label(v, g.ranges);
-        let unit = gauge_unit(g.metric_id);
-
-        g.value_label.set_text(&format!("{v:.1} {unit}"));
+        let idx = Air1App::quality_index(v, g.metric.ranges);
+        let label = Air1App::get_quality_label(v, g.metric.ranges);
+        let precision = g.metric.precision;
+        g.value_label.set_text(&format!("{v:.precision$} {unit}"));
         g.quality_label.set_text(label);
 
         clear_css_classes(&g.quality_label, &quality_classes);
@@ -784,11 +1126,123 @@ fn update_gauge(g: &GaugeWidgets, value: Option<f64>) {
         g.data_box.set_visible(false);
         g.no_data_box.set_visible(true);
 
+        // A sensor that reported itself unavailable differs from one never heard from.
+        if unavailable {
+            g.no_data_label.set_text("Sensor unavailable");
+            g.no_data_label.remove_css_class("quality-none");
+            g.no_data_label.add_css_class("quality-unavailable");
+        } else {
+            g.no_data_label.set_text("No Data");
+            g.no_data_label.remove_css_class("quality-unavailable");
+            g.no_data_label.add_css_class("quality-none");
+        }
+
         g.current_value.set(None);
         g.drawing_area.queue_draw();
     }
 }
 
+fn update_gauge_staleness(app: &Air1App, g: &GaugeWidgets) {
+    let stale = app.is_stale(g.metric.id);
+    if stale {
+        g.card.add_css_class("gauge-stale");
+    } else {
+        g.card.remove_css_class("gauge-stale");
+    }
+    match app.metrics().reading(g.metric.id).filter(|_| stale) {
+        Some(reading) => {
+            g.age_label.set_text(&format!(
+                "Stale · updated {} ago",
+                format_age(reading.timestamp.elapsed())
+            ));
+            g.age_label.set_visible(true);
+        }
+        None => g.age_label.set_visible(false),
+    }
+}
+
+/// Compact age such as "45s", "12m" or "3h".
+fn format_age(age: std::time::Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        _ => format!("{}h", secs / 3600),
+    }
+}
+
+fn update_gauge_source(app: &Air1App, g: &GaugeWidgets) {
+    let source = app.source_label(g.metric.id).unwrap_or_default();
+    g.source_label.set_text(&source);
+
+    // Flag metrics that picked up an unexpected second topic.
+    let conflict = app.conflicting_metrics().contains(&g.metric.id);
+    if conflict {
+        g.source_label.remove_css_class("last-topic");
+        g.source_label.add_css_class("warn-label");
+        let topics: Vec<&str> = app.metrics().sources(g.metric.id).collect();
+        g.source_label.set_tooltip_text(Some(&format!(
+            "Several topics report this metric; add a source policy in Configure.\n{}",
+            topics.join("\n")
+        )));
+    } else {
+        g.source_label.remove_css_class("warn-label");
+        g.source_label.add_css_class("last-topic");
+        g.source_label.set_tooltip_text(Some(&source));
+    }
+}
+
+// ── Particle histogram drawing ────────────────────────────────────────────────
+
+fn draw_histogram(ctx: &cairo::Context, width: i32, height: i32, bars: &[(&str, Option<f64>)]) {
+    let w = width as f64;
+    let h = height as f64;
+    ctx.set_font_size(12.0);
+
+    if bars.iter().all(|(_, v)| v.is_none()) {
+        ctx.set_source_rgb(150.0 / 255.0, 150.0 / 255.0, 150.0 / 255.0);
+        let text = "No Data";
+        if let Ok(ext) = ctx.text_extents(text) {
+            ctx.move_to((w - ext.width()) / 2.0, h / 2.0);
+            let _ = ctx.show_text(text);
+        }
+        return;
+    }
+
+    // Bars share one linear scale; leave room for labels above and below.
+    let max = bars.iter().filter_map(|(_, v)| *v).fold(1.0_f64, f64::max);
+    let label_h = 18.0;
+    let plot_h = h - 2.0 * label_h;
+    let slot_w = w / bars.len() as f64;
+    let bar_w = slot_w * 0.6;
+
+    for (i, (label, value)) in bars.iter().enumerate() {
+        let x = slot_w * i as f64 + (slot_w - bar_w) / 2.0;
+        let center = slot_w * i as f64 + slot_w / 2.0;
+
+        if let Some(v) = value {
+            let bar_h = (v / max).clamp(0.0, 1.0) * plot_h;
+            let top = label_h + plot_h - bar_h;
+            ctx.set_source_rgb(33.0 / 255.0, 150.0 / 255.0, 243.0 / 255.0);
+            ctx.rectangle(x, top, bar_w, bar_h);
+            let _ = ctx.fill();
+
+            let text = format!("{v:.0}");
+            if let Ok(ext) = ctx.text_extents(&text) {
+                ctx.set_source_rgb(1.0, 1.0, 1.0);
+                ctx.move_to(center - ext.width() / 2.0, top - 4.0);
+                let _ = ctx.show_text(&text);
+            }
+        }
+
+        if let Ok(ext) = ctx.text_extents(label) {
+            ctx.set_source_rgb(150.0 / 255.0, 150.0 / 255.0, 150.0 / 255.0);
+            ctx.move_to(center - ext.width() / 2.0, h - 4.0);
+            let _ = ctx.show_text(label);
+        }
+    }
+}
+
 // ── Arc gauge drawing ─────────────────────────────────────────────────────────
 
 fn draw_arc_gauge(
@@ -875,7 +1329,8 @@ fn show_config_window(state: Rc<RefCell<Air1App>>, parent: &gtk4::Window) {
         .transient_for(parent)
         .modal(true)
         .title("Configuration")
-        .default_width(500)
+        .default_width(760)
+        .default_height(720)
         .build();
 
     let vbox = gtk4::Box::new(gtk4::Orientation::Vertical, 8);
@@ -883,7 +1338,11 @@ fn show_config_window(state: Rc<RefCell<Air1App>>, parent: &gtk4::Window) {
     vbox.set_margin_bottom(12);
     vbox.set_margin_start(12);
     vbox.set_margin_end(12);
-    win.set_child(Some(&vbox));
+    let scroll = gtk4::ScrolledWindow::builder()
+        .hscrollbar_policy(gtk4::PolicyType::Never)
+        .child(&vbox)
+        .build();
+    win.set_child(Some(&scroll));
 
     let grid = gtk4::Grid::new();
     grid.set_row_spacing(6);
@@ -904,6 +1363,44 @@ fn show_config_window(state: Rc<RefCell<Air1App>>, parent: &gtk4::Window) {
         row += 1;
     };
 
+    // Profile: the fields below edit the active profile
+    let profile_box = gtk4::Box::new(gtk4::Orientation::Horizontal, 4);
+    let profile_name = gtk4::Label::new(Some(&state.borrow().cfg.profile));
+    profile_name.add_css_class("metric-name");
+    profile_box.append(&profile_name);
+    let new_profile_entry = gtk4::Entry::new();
+    new_profile_entry.set_placeholder_text(Some("new profile name"));
+    new_profile_entry.set_hexpand(true);
+    profile_box.append(&new_profile_entry);
+    let add_profile_btn = gtk4::Button::with_label("Add profile");
+    profile_box.append(&add_profile_btn);
+    let delete_profile_btn = gtk4::Button::with_label("Delete profile");
+    delete_profile_btn.set_sensitive(!state.borrow().cfg.profiles.is_empty());
+    profile_box.append(&delete_profile_btn);
+    add_row("Profile", &profile_box.clone().upcast());
+    {
+        // Reopen the window so every field shows the new active profile.
+        let state_c = state.clone();
+        let win_c = win.clone();
+        let parent_c = parent.clone();
+        let name_e = new_profile_entry.clone();
+        add_profile_btn.connect_clicked(move |_| {
+            state_c.borrow_mut().add_profile(&name_e.text());
+            win_c.close();
+            show_config_window(state_c.clone(), &parent_c);
+        });
+    }
+    {
+        let state_c = state.clone();
+        let win_c = win.clone();
+        let parent_c = parent.clone();
+        delete_profile_btn.connect_clicked(move |_| {
+            state_c.borrow_mut().delete_profile();
+            win_c.close();
+            show_config_window(state_c.clone(), &parent_c);
+        });
+    }
+
     let host_entry = gtk4::Entry::new();
     host_entry.set_text(&cfg.host);
     host_entry.set_hexpand(true);
@@ -913,9 +1410,52 @@ fn show_config_window(state: Rc<RefCell<Air1App>>, parent: &gtk4::Window) {
     port_spin.set_value(cfg.port as f64);
     add_row("Port", &port_spin.clone().upcast());
 
-    let tls_check = gtk4::CheckButton::with_label("Enabled");
-    tls_check.set_active(cfg.tls);
-    add_row("TLS", &tls_check.clone().upcast());
+    let transport_labels: Vec<&str> = config::MqttTransport::ALL
+        .iter()
+        .map(|t| t.label())
+        .collect();
+    let transport_dd = gtk4::DropDown::from_strings(&transport_labels);
+    let transport_idx = config::MqttTransport::ALL
+        .iter()
+        .position(|t| *t == cfg.transport())
+        .unwrap_or(0);
+    transport_dd.set_selected(transport_idx as u32);
+    add_row("Transport", &transport_dd.clone().upcast());
+
+    let ws_path_entry = gtk4::Entry::new();
+    ws_path_entry.set_text(&cfg.ws_path.clone().unwrap_or_default());
+    ws_path_entry.set_placeholder_text(Some("(default: /mqtt)"));
+    ws_path_entry.set_hexpand(true);
+    add_row("WebSocket path", &ws_path_entry.clone().upcast());
+
+    let ws_headers_entry = gtk4::Entry::new();
+    ws_headers_entry.set_text(
+        &cfg.ws_headers
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join(", "),
+    );
+    ws_headers_entry.set_placeholder_text(Some("(e.g. Authorization=Bearer abc)"));
+    ws_headers_entry.set_hexpand(true);
+    add_row("HTTP headers", &ws_headers_entry.clone().upcast());
+    {
+        // Path and headers only apply to the WebSocket upgrade request.
+        let ws_widgets: [gtk4::Widget; 2] = [
+            ws_path_entry.clone().upcast(),
+            ws_headers_entry.clone().upcast(),
+        ];
+        let sensitive = move |idx: u32| {
+            let ws = config::MqttTransport::ALL
+                .get(idx as usize)
+                .is_some_and(|t| t.is_websocket());
+            for widget in &ws_widgets {
+                widget.set_sensitive(ws);
+            }
+        };
+        sensitive(transport_idx as u32);
+        transport_dd.connect_selected_notify(move |dd| sensitive(dd.selected()));
+    }
 
     let ca_entry = gtk4::Entry::new();
     ca_entry.set_text(
@@ -924,9 +1464,141 @@ fn show_config_window(state: Rc<RefCell<Air1App>>, parent: &gtk4::Window) {
             .map(|p| p.display().to_string())
             .unwrap_or_default(),
     );
-    ca_entry.set_placeholder_text(Some("(optional)"));
+    ca_entry.set_placeholder_text(Some("(optional, system roots otherwise)"));
     ca_entry.set_hexpand(true);
-    add_row("CA path", &ca_entry.clone().upcast());
+    let ca_choose_btn = gtk4::Button::with_label("Choose…");
+    let ca_box = gtk4::Box::new(gtk4::Orientation::Horizontal, 6);
+    ca_box.append(&ca_entry);
+    ca_box.append(&ca_choose_btn);
+    add_row("CA path", &ca_box.upcast());
+
+    let ca_info_label = gtk4::Label::new(None);
+    ca_info_label.set_xalign(0.0);
+    ca_info_label.set_wrap(true);
+    ca_info_label.set_selectable(true);
+    show_certificates(&ca_info_label, &ca_entry.text());
+    add_row("CA certificates", &ca_info_label.clone().upcast());
+    {
+        let info_l = ca_info_label.clone();
+        ca_entry.connect_changed(move |e| show_certificates(&info_l, &e.text()));
+    }
+    {
+        let win_c = win.clone();
+        let ca_e = ca_entry.clone();
+        ca_choose_btn.connect_clicked(move |_| {
+            let dialog = gtk4::FileChooserDialog::new(
+                Some("Choose CA File"),
+                Some(&win_c),
+                gtk4::FileChooserAction::Open,
+                &[
+                    ("Cancel", gtk4::ResponseType::Cancel),
+                    ("Open", gtk4::ResponseType::Accept),
+                ],
+            );
+            dialog.set_modal(true);
+            let certs = gtk4::FileFilter::new();
+            certs.set_name(Some("Certificates"));
+            for pattern in ["*.pem", "*.crt", "*.cer", "*.der"] {
+                certs.add_pattern(pattern);
+            }
+            dialog.add_filter(&certs);
+            let all = gtk4::FileFilter::new();
+            all.set_name(Some("All files"));
+            all.add_pattern("*");
+            dialog.add_filter(&all);
+            let current = ca_e.text();
+            if !current.trim().is_empty() {
+                let _ = dialog.set_file(&gtk4::gio::File::for_path(current.trim()));
+            }
+            let ca_e = ca_e.clone();
+            dialog.connect_response(move |d, response| {
+                if response == gtk4::ResponseType::Accept
+                    && let Some(path) = d.file().and_then(|f| f.path())
+                {
+                    ca_e.set_text(&path.display().to_string());
+                }
+                d.close();
+            });
+            dialog.present();
+        });
+    }
+
+    let broker_cert_label = gtk4::Label::new(None);
+    broker_cert_label.set_xalign(0.0);
+    broker_cert_label.set_wrap(true);
+    broker_cert_label.set_selectable(true);
+    match &state.borrow().broker_certificate {
+        Some(info) => {
+            let mut text = info.describe();
+            if let Some(warning) = info.expiry_warning(SystemTime::now()) {
+                text.push_str(&format!("\nWarning: {warning}"));
+                broker_cert_label.add_css_class("warn-label");
+            }
+            broker_cert_label.set_text(&text);
+        }
+        None => broker_cert_label.set_text("(seen once connected over TLS)"),
+    }
+    add_row("Broker certificate", &broker_cert_label.upcast());
+
+    let client_cert_entry = gtk4::Entry::new();
+    client_cert_entry.set_text(
+        &cfg.client_cert_path
+            .as_ref()
+            .map(|p| p.display().to_string())
+            .unwrap_or_default(),
+    );
+    client_cert_entry.set_placeholder_text(Some("(optional, PEM)"));
+    client_cert_entry.set_hexpand(true);
+    add_row("Client certificate", &client_cert_entry.clone().upcast());
+
+    let client_key_entry = gtk4::Entry::new();
+    client_key_entry.set_text(
+        &cfg.client_key_path
+            .as_ref()
+            .map(|p| p.display().to_string())
+            .unwrap_or_default(),
+    );
+    client_key_entry.set_placeholder_text(Some("(optional, PEM or PKCS#8)"));
+    client_key_entry.set_hexpand(true);
+    add_row("Client key", &client_key_entry.clone().upcast());
+
+    let key_passphrase_entry = gtk4::PasswordEntry::new();
+    key_passphrase_entry.set_text(&state.borrow().key_passphrase.clone().unwrap_or_default());
+    key_passphrase_entry.set_hexpand(true);
+    key_passphrase_entry.set_tooltip_text(Some("Only needed for encrypted keys"));
+    add_row("Key passphrase", &key_passphrase_entry.clone().upcast());
+
+    let tofu_check =
+        gtk4::CheckButton::with_label("Trust on first use (pin the first certificate)");
+    tofu_check.set_active(cfg.trust_on_first_use);
+    tofu_check.set_tooltip_text(Some(
+        "Accept the broker certificate without a CA and ask to pin it on first connection",
+    ));
+    add_row("", &tofu_check.clone().upcast());
+
+    let pin_box = gtk4::Box::new(gtk4::Orientation::Horizontal, 8);
+    let pin_label = gtk4::Label::new(Some(&pin_summary(cfg.pin.as_ref())));
+    pin_label.set_hexpand(true);
+    pin_label.set_xalign(0.0);
+    pin_label.set_selectable(true);
+    pin_label.set_wrap(true);
+    let clear_pin_btn = gtk4::Button::with_label("Clear Pin");
+    clear_pin_btn.set_sensitive(cfg.pin.is_some());
+    pin_box.append(&pin_label);
+    pin_box.append(&clear_pin_btn);
+    add_row("Pinned fingerprint", &pin_box.upcast());
+    {
+        let state_c = state.clone();
+        let pin_l = pin_label.clone();
+        clear_pin_btn.connect_clicked(move |btn| {
+            let mut app = state_c.borrow_mut();
+            app.cfg.mqtt.pin = None;
+            app.save_all();
+            app.restart_mqtt();
+            pin_l.set_text(&pin_summary(None));
+            btn.set_sensitive(false);
+        });
+    }
 
     let client_id_entry = gtk4::Entry::new();
     client_id_entry.set_text(&cfg.client_id.clone().unwrap_or_default());
@@ -947,10 +1619,25 @@ fn show_config_window(state: Rc<RefCell<Air1App>>, parent: &gtk4::Window) {
 
     let prefix_entry = gtk4::Entry::new();
     prefix_entry.set_text(&cfg.topic_prefix.clone().unwrap_or_default());
-    prefix_entry.set_placeholder_text(Some("(e.g. homeassistant)"));
+    prefix_entry.set_placeholder_text(Some("(default: homeassistant)"));
     prefix_entry.set_hexpand(true);
     add_row("Topic prefix", &prefix_entry.clone().upcast());
 
+    let discovery_check = gtk4::CheckButton::with_label("Use Home Assistant discovery");
+    discovery_check.set_active(cfg.discovery);
+    add_row("Discovery", &discovery_check.clone().upcast());
+
+    let discovery_prefix_entry = gtk4::Entry::new();
+    discovery_prefix_entry.set_text(&cfg.discovery_prefix.clone().unwrap_or_default());
+    discovery_prefix_entry.set_placeholder_text(Some("(default: homeassistant)"));
+    discovery_prefix_entry.set_hexpand(true);
+    discovery_prefix_entry.set_sensitive(cfg.discovery);
+    add_row("Discovery prefix", &discovery_prefix_entry.clone().upcast());
+    {
+        let dp_e = discovery_prefix_entry.clone();
+        discovery_check.connect_toggled(move |c| dp_e.set_sensitive(c.is_active()));
+    }
+
     let qos_spin = gtk4::SpinButton::with_range(0.0, 2.0, 1.0);
     qos_spin.set_value(cfg.qos as f64);
     add_row("QoS", &qos_spin.clone().upcast());
@@ -959,40 +1646,272 @@ fn show_config_window(state: Rc<RefCell<Air1App>>, parent: &gtk4::Window) {
     keepalive_spin.set_value(cfg.keepalive_secs as f64);
     add_row("Keepalive (s)", &keepalive_spin.clone().upcast());
 
-    let remember_check = gtk4::CheckButton::with_label("Remember password in system keyring");
+    let protocol_labels: Vec<&str> = config::ProtocolVersion::ALL
+        .iter()
+        .map(|p| p.label())
+        .collect();
+    let protocol_dd = gtk4::DropDown::from_strings(&protocol_labels);
+    let protocol_idx = config::ProtocolVersion::ALL
+        .iter()
+        .position(|p| *p == cfg.protocol)
+        .unwrap_or(0);
+    protocol_dd.set_selected(protocol_idx as u32);
+    add_row("Protocol", &protocol_dd.clone().upcast());
+
+    let session_expiry_spin = gtk4::SpinButton::with_range(0.0, u32::MAX as f64, 60.0);
+    session_expiry_spin.set_value(cfg.session_expiry_secs.unwrap_or(0) as f64);
+    session_expiry_spin.set_tooltip_text(Some("0 ends the session when the connection closes"));
+    add_row("Session expiry (s)", &session_expiry_spin.clone().upcast());
+
+    let receive_max_spin = gtk4::SpinButton::with_range(0.0, u16::MAX as f64, 1.0);
+    receive_max_spin.set_value(cfg.receive_maximum.unwrap_or(0) as f64);
+    receive_max_spin.set_tooltip_text(Some("0 leaves the broker default"));
+    add_row("Receive maximum", &receive_max_spin.clone().upcast());
+
+    let user_props_entry = gtk4::Entry::new();
+    user_props_entry.set_text(
+        &cfg.user_properties
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect::<Vec<_>>()
+            .join(", "),
+    );
+    user_props_entry.set_placeholder_text(Some("(e.g. site=home, app=air1)"));
+    user_props_entry.set_hexpand(true);
+    add_row("User properties", &user_props_entry.clone().upcast());
+    {
+        // Session expiry, receive maximum and user properties are MQTT 5 only.
+        let v5_widgets: [gtk4::Widget; 3] = [
+            session_expiry_spin.clone().upcast(),
+            receive_max_spin.clone().upcast(),
+            user_props_entry.clone().upcast(),
+        ];
+        let sensitive = move |idx: u32| {
+            let v5 = config::ProtocolVersion::ALL.get(idx as usize)
+                == Some(&config::ProtocolVersion::V5);
+            for widget in &v5_widgets {
+                widget.set_sensitive(v5);
+            }
+        };
+        sensitive(protocol_idx as u32);
+        protocol_dd.connect_selected_notify(move |dd| sensitive(dd.selected()));
+    }
+
+    let stale_spin = gtk4::SpinButton::with_range(1.0, 86400.0, 1.0);
+    stale_spin.set_value(state.borrow().cfg.staleness.default_secs as f64);
+    stale_spin.set_tooltip_text(Some(
+        "Readings older than this are greyed out; per-metric and per-device \
+         timeouts can be set under [staleness] in config.toml",
+    ));
+    add_row("Stale after (s)", &stale_spin.clone().upcast());
+
+    let remember_check =
+        gtk4::CheckButton::with_label("Remember password and key passphrase in system keyring");
     remember_check.set_active(cfg.remember_password);
     remember_check.set_sensitive(!keyring_unavailable);
     add_row("", &remember_check.clone().upcast());
 
-    if keyring_unavailable {
-        let warn = gtk4::Label::new(Some("Keyring unavailable — session-only"));
-        warn.add_css_class("warn-label");
-        vbox.append(&warn);
+    // Topic filters editor
+    let filters_frame = gtk4::Frame::new(Some("Topic filters"));
+    let filters_vbox = gtk4::Box::new(gtk4::Orientation::Vertical, 4);
+    filters_vbox.set_margin_top(6);
+    filters_vbox.set_margin_bottom(6);
+    filters_vbox.set_margin_start(6);
+    filters_vbox.set_margin_end(6);
+    filters_frame.set_child(Some(&filters_vbox));
+    vbox.append(&filters_frame);
+
+    let filters_hint = gtk4::Label::new(Some(
+        "Explicit filters replace the prefix subscription. \
+         When empty, filters are derived from rules and discovery.",
+    ));
+    filters_hint.set_halign(gtk4::Align::Start);
+    filters_hint.add_css_class("last-topic");
+    filters_vbox.append(&filters_hint);
+
+    let filters_list = gtk4::Box::new(gtk4::Orientation::Vertical, 4);
+    filters_vbox.append(&filters_list);
+
+    let filter_rows: Rc<RefCell<Vec<FilterRow>>> = Rc::new(RefCell::new(Vec::new()));
+    for filter in cfg.filters.iter() {
+        append_filter_row(&filters_list, &filter_rows, filter);
+    }
 
-        let help_btn = gtk4::Button::with_label("Keyring help");
-        let parent_c = parent.clone();
-        help_btn.connect_clicked(move |_| show_keyring_help(&parent_c));
-        vbox.append(&help_btn);
+    let add_filter_btn = gtk4::Button::with_label("Add filter");
+    add_filter_btn.set_halign(gtk4::Align::Start);
+    filters_vbox.append(&add_filter_btn);
+    {
+        let list_c = filters_list.clone();
+        let rows_c = filter_rows.clone();
+        add_filter_btn.connect_clicked(move |_| {
+            let blank = config::TopicFilter {
+                filter: String::new(),
+                qos: None,
+            };
+            append_filter_row(&list_c, &rows_c, &blank);
+        });
     }
 
-    // Status label
-    let status_lbl = gtk4::Label::new(None);
-    vbox.append(&status_lbl);
+    // Topic layout adapters editor
+    let adapters_frame = gtk4::Frame::new(Some("Topic layouts"));
+    let adapters_vbox = gtk4::Box::new(gtk4::Orientation::Vertical, 4);
+    adapters_vbox.set_margin_top(6);
+    adapters_vbox.set_margin_bottom(6);
+    adapters_vbox.set_margin_start(6);
+    adapters_vbox.set_margin_end(6);
+    adapters_frame.set_child(Some(&adapters_vbox));
+    vbox.append(&adapters_frame);
+
+    let adapters_hint = gtk4::Label::new(Some(
+        "Each layout reads its own base topic, checked after mapping rules.",
+    ));
+    adapters_hint.set_halign(gtk4::Align::Start);
+    adapters_hint.add_css_class("last-topic");
+    adapters_vbox.append(&adapters_hint);
+
+    let adapters_list = gtk4::Box::new(gtk4::Orientation::Vertical, 4);
+    adapters_vbox.append(&adapters_list);
+
+    let adapter_rows: Rc<RefCell<Vec<AdapterRow>>> = Rc::new(RefCell::new(Vec::new()));
+    for adapter in cfg.adapters.iter() {
+        append_adapter_row(&adapters_list, &adapter_rows, adapter);
+    }
+
+    let add_adapter_btn = gtk4::Button::with_label("Add layout");
+    add_adapter_btn.set_halign(gtk4::Align::Start);
+    adapters_vbox.append(&add_adapter_btn);
     {
-        let s = state.borrow().status.clone();
-        status_lbl.set_text(&s);
+        let list_c = adapters_list.clone();
+        let rows_c = adapter_rows.clone();
+        add_adapter_btn.connect_clicked(move |_| {
+            let blank = config::AdapterConfig {
+                kind: config::TopicAdapter::Zigbee2mqtt,
+                base: None,
+            };
+            append_adapter_row(&list_c, &rows_c, &blank);
+        });
     }
 
-    // Buttons
-    let btn_box = gtk4::Box::new(gtk4::Orientation::Horizontal, 6);
-    vbox.append(&btn_box);
+    // Mapping rules editor
+    let rules_frame = gtk4::Frame::new(Some("Mapping rules"));
+    let rules_vbox = gtk4::Box::new(gtk4::Orientation::Vertical, 4);
+    rules_vbox.set_margin_top(6);
+    rules_vbox.set_margin_bottom(6);
+    rules_vbox.set_margin_start(6);
+    rules_vbox.set_margin_end(6);
+    rules_frame.set_child(Some(&rules_vbox));
+    vbox.append(&rules_frame);
+
+    let rules_hint = gtk4::Label::new(Some(
+        "Rules are checked in order before built-in topic guessing.",
+    ));
+    rules_hint.set_halign(gtk4::Align::Start);
+    rules_hint.add_css_class("last-topic");
+    rules_vbox.append(&rules_hint);
+
+    let rules_list = gtk4::Box::new(gtk4::Orientation::Vertical, 4);
+    rules_vbox.append(&rules_list);
+
+    let rule_rows: Rc<RefCell<Vec<RuleRow>>> = Rc::new(RefCell::new(Vec::new()));
+    for rule in state.borrow().cfg.mappings.iter() {
+        append_rule_row(&rules_list, &rule_rows, rule);
+    }
 
-    let save_btn = gtk4::Button::with_label("Save settings");
-    let test_btn = gtk4::Button::with_label("Test connection");
+    let add_rule_btn = gtk4::Button::with_label("Add rule");
+    add_rule_btn.set_halign(gtk4::Align::Start);
+    rules_vbox.append(&add_rule_btn);
+    {
+        let list_c = rules_list.clone();
+        let rows_c = rule_rows.clone();
+        add_rule_btn.connect_clicked(move |_| {
+            let blank = config::MappingRule {
+                topic: String::new(),
+                match_type: config::TopicMatch::Exact,
+                metric: String::new(),
+                path: None,
+                scale: 1.0,
+                unit: None,
+                device: None,
+            };
+            append_rule_row(&list_c, &rows_c, &blank);
+        });
+    }
+
+    // Source policies editor
+    let sources_frame = gtk4::Frame::new(Some("Source policies"));
+    let sources_vbox = gtk4::Box::new(gtk4::Orientation::Vertical, 4);
+    sources_vbox.set_margin_top(6);
+    sources_vbox.set_margin_bottom(6);
+    sources_vbox.set_margin_start(6);
+    sources_vbox.set_margin_end(6);
+    sources_frame.set_child(Some(&sources_vbox));
+    vbox.append(&sources_frame);
+
+    let sources_hint = gtk4::Label::new(Some(
+        "Choose how a metric reported on several topics picks its value (default: latest wins).",
+    ));
+    sources_hint.set_halign(gtk4::Align::Start);
+    sources_hint.add_css_class("last-topic");
+    sources_vbox.append(&sources_hint);
+
+    let sources_list = gtk4::Box::new(gtk4::Orientation::Vertical, 4);
+    sources_vbox.append(&sources_list);
+
+    let source_rows: Rc<RefCell<Vec<SourceRow>>> = Rc::new(RefCell::new(Vec::new()));
+    for policy in state.borrow().cfg.sources.iter() {
+        append_source_row(&sources_list, &source_rows, policy);
+    }
+
+    let add_source_btn = gtk4::Button::with_label("Add policy");
+    add_source_btn.set_halign(gtk4::Align::Start);
+    sources_vbox.append(&add_source_btn);
+    {
+        let list_c = sources_list.clone();
+        let rows_c = source_rows.clone();
+        add_source_btn.connect_clicked(move |_| {
+            let blank = config::SourcePolicy {
+                metric: String::new(),
+                mode: config::SourceMode::Latest,
+                topic: None,
+            };
+            append_source_row(&list_c, &rows_c, &blank);
+        });
+    }
+
+    if keyring_unavailable {
+        let warn = gtk4::Label::new(Some("Keyring unavailable — session-only"));
+        warn.add_css_class("warn-label");
+        vbox.append(&warn);
+
+        let help_btn = gtk4::Button::with_label("Keyring help");
+        let parent_c = parent.clone();
+        help_btn.connect_clicked(move |_| show_keyring_help(&parent_c));
+        vbox.append(&help_btn);
+    }
+
+    // Status label
+    let status_lbl = gtk4::Label::new(None);
+    vbox.append(&status_lbl);
+    {
+        let s = state.borrow().status.clone();
+        status_lbl.set_text(&s);
+    }
+
+    // Buttons
+    let btn_box = gtk4::Box::new(gtk4::Orientation::Horizontal, 6);
+    vbox.append(&btn_box);
+
+    let save_btn = gtk4::Button::with_label("Save settings");
+    let test_btn = gtk4::Button::with_label("Test connection");
+    let discover_btn = gtk4::Button::with_label("Discover topics…");
+    discover_btn.set_tooltip_text(Some(
+        "Save settings, listen on the broker and propose mapping rules",
+    ));
     let forget_btn = gtk4::Button::with_label("Forget saved password");
     let close_btn = gtk4::Button::with_label("Close");
     btn_box.append(&save_btn);
     btn_box.append(&test_btn);
+    btn_box.append(&discover_btn);
     btn_box.append(&forget_btn);
     btn_box.append(&close_btn);
 
@@ -1001,27 +1920,128 @@ fn show_config_window(state: Rc<RefCell<Air1App>>, parent: &gtk4::Window) {
         let state_c = state.clone();
         let host_e = host_entry.clone();
         let port_s = port_spin.clone();
-        let tls_c = tls_check.clone();
+        let transport_d = transport_dd.clone();
+        let ws_path_e = ws_path_entry.clone();
+        let ws_headers_e = ws_headers_entry.clone();
         let ca_e = ca_entry.clone();
+        let cert_e = client_cert_entry.clone();
+        let key_e = client_key_entry.clone();
+        let key_pw_e = key_passphrase_entry.clone();
+        let tofu_c = tofu_check.clone();
         let cid_e = client_id_entry.clone();
         let uname_e = username_entry.clone();
         let pw_e = password_entry.clone();
         let prefix_e = prefix_entry.clone();
+        let disc_c = discovery_check.clone();
+        let disc_prefix_e = discovery_prefix_entry.clone();
+        let adapter_rows_c = adapter_rows.clone();
         let qos_s = qos_spin.clone();
         let ka_s = keepalive_spin.clone();
+        let protocol_d = protocol_dd.clone();
+        let expiry_s = session_expiry_spin.clone();
+        let recv_max_s = receive_max_spin.clone();
+        let user_props_e = user_props_entry.clone();
+        let stale_s = stale_spin.clone();
         let rem_c = remember_check.clone();
+        let rows_c = rule_rows.clone();
+        let source_rows_c = source_rows.clone();
+        let filter_rows_c = filter_rows.clone();
         let status_l = status_lbl.clone();
         save_btn.connect_clicked(move |_| {
+            let rules: Vec<config::MappingRule> =
+                rows_c.borrow().iter().map(RuleRow::to_rule).collect();
+            if let Err(err) = rules.iter().try_for_each(config::MappingRule::validate) {
+                status_l.set_text(&format!("Invalid mapping rule: {err:#}"));
+                return;
+            }
+            let filters: Vec<config::TopicFilter> = filter_rows_c
+                .borrow()
+                .iter()
+                .map(FilterRow::to_filter)
+                .collect();
+            if let Err(err) = filters.iter().try_for_each(config::TopicFilter::validate) {
+                status_l.set_text(&format!("Invalid topic filter: {err:#}"));
+                return;
+            }
+            let adapters: Vec<config::AdapterConfig> = adapter_rows_c
+                .borrow()
+                .iter()
+                .map(AdapterRow::to_adapter)
+                .collect();
+            if let Err(err) = adapters
+                .iter()
+                .try_for_each(config::AdapterConfig::validate)
+            {
+                status_l.set_text(&format!("Invalid topic layout: {err:#}"));
+                return;
+            }
+            let sources: Vec<config::SourcePolicy> = source_rows_c
+                .borrow()
+                .iter()
+                .map(SourceRow::to_policy)
+                .collect();
+            if let Err(err) = sources.iter().try_for_each(config::SourcePolicy::validate) {
+                status_l.set_text(&format!("Invalid source policy: {err:#}"));
+                return;
+            }
+            let cert = cert_e.text().trim().to_string();
+            let key = key_e.text().trim().to_string();
+            if cert.is_empty() != key.is_empty() {
+                status_l.set_text("Client certificate and key must be set together");
+                return;
+            }
+            let ws_headers = match parse_pairs(&ws_headers_e.text()) {
+                Ok(headers) => headers,
+                Err(err) => {
+                    status_l.set_text(&format!("Invalid HTTP headers: {err:#}"));
+                    return;
+                }
+            };
+            let header_check = config::MqttConfig {
+                ws_headers: ws_headers.clone(),
+                ..config::MqttConfig::default()
+            };
+            if let Err(err) = mqtt::websocket_headers(&header_check) {
+                status_l.set_text(&format!("Invalid HTTP headers: {err:#}"));
+                return;
+            }
+            let user_properties = match parse_pairs(&user_props_e.text()) {
+                Ok(props) => props,
+                Err(err) => {
+                    status_l.set_text(&format!("Invalid user properties: {err:#}"));
+                    return;
+                }
+            };
+
             let mut app = state_c.borrow_mut();
+            let previous = app.cfg.mqtt.clone();
+            let previous_credentials = (app.password.clone(), app.key_passphrase.clone());
             app.cfg.mqtt.host = host_e.text().to_string();
             app.cfg.mqtt.port = port_s.value() as u16;
-            app.cfg.mqtt.tls = tls_c.is_active();
+            let transport = config::MqttTransport::ALL
+                .get(transport_d.selected() as usize)
+                .copied()
+                .unwrap_or_default();
+            app.cfg.mqtt.transport = Some(transport);
+            app.cfg.mqtt.tls = transport.is_secure();
+            let ws_path = ws_path_e.text().trim().to_string();
+            app.cfg.mqtt.ws_path = (!ws_path.is_empty()).then_some(ws_path);
+            app.cfg.mqtt.ws_headers = ws_headers;
             let ca = ca_e.text().to_string();
             app.cfg.mqtt.ca_path = if ca.trim().is_empty() {
                 None
             } else {
                 Some(ca.into())
             };
+            app.cfg.mqtt.client_cert_path = (!cert.is_empty()).then(|| cert.into());
+            app.cfg.mqtt.client_key_path = (!key.is_empty()).then(|| key.into());
+            app.cfg.mqtt.trust_on_first_use = tofu_c.is_active();
+            let key_pw = key_pw_e.text().to_string();
+            app.key_passphrase = if key_pw.is_empty() {
+                None
+            } else {
+                Some(key_pw)
+            };
             let cid = cid_e.text().to_string();
             app.cfg.mqtt.client_id = if cid.trim().is_empty() {
                 None
@@ -1042,11 +2062,43 @@ fn show_config_window(state: Rc<RefCell<Air1App>>, parent: &gtk4::Window) {
             } else {
                 Some(prefix)
             };
+            app.cfg.mqtt.discovery = disc_c.is_active();
+            let disc_prefix = disc_prefix_e.text().to_string();
+            app.cfg.mqtt.discovery_prefix = if disc_prefix.trim().is_empty() {
+                None
+            } else {
+                Some(disc_prefix)
+            };
+            app.cfg.mqtt.adapters = adapters;
+            app.cfg.mqtt.filters = filters;
             app.cfg.mqtt.qos = qos_s.value() as u8;
             app.cfg.mqtt.keepalive_secs = ka_s.value() as u16;
+            app.cfg.mqtt.protocol = config::ProtocolVersion::ALL
+                .get(protocol_d.selected() as usize)
+                .copied()
+                .unwrap_or_default();
+            app.cfg.mqtt.session_expiry_secs =
+                Some(expiry_s.value() as u32).filter(|secs| *secs > 0);
+            app.cfg.mqtt.receive_maximum = Some(recv_max_s.value() as u16).filter(|max| *max > 0);
+            app.cfg.mqtt.user_properties = user_properties;
+            app.cfg.staleness.default_secs = stale_s.value() as u64;
             app.cfg.mqtt.remember_password = rem_c.is_active();
+            // Remembering the password does not change the session.
+            let mqtt_changed = config::MqttConfig {
+                remember_password: previous.remember_password,
+                ..app.cfg.mqtt.clone()
+            } != previous
+                || (app.password.clone(), app.key_passphrase.clone()) != previous_credentials;
+            let rules_changed = app.cfg.mappings != rules;
+            app.cfg.mappings = rules;
+            app.set_source_policies(sources);
             app.save_all();
             status_l.set_text(&app.status);
+            if rules_changed || mqtt_changed {
+                // The listener compiles rules, adapters, subscriptions and
+                // connect options once at start-up.
+                app.restart_mqtt();
+            }
         });
     }
 
@@ -1055,11 +2107,48 @@ fn show_config_window(state: Rc<RefCell<Air1App>>, parent: &gtk4::Window) {
         let state_c = state.clone();
         let test_b = test_btn.clone();
         let status_l = status_lbl.clone();
+        let win_c = win.clone();
         test_btn.connect_clicked(move |_| {
             test_b.set_sensitive(false);
-            let mut app = state_c.borrow_mut();
-            app.spawn_test_connection();
-            status_l.set_text(&app.status);
+            {
+                let mut app = state_c.borrow_mut();
+                app.spawn_test_connection();
+                status_l.set_text(&app.status);
+            }
+            // Wait for the report without blocking the main loop.
+            let state_c = state_c.clone();
+            let test_b = test_b.clone();
+            let status_l = status_l.clone();
+            let win_c = win_c.clone();
+            glib::timeout_add_local(Duration::from_millis(200), move || {
+                let Ok(mut app) = state_c.try_borrow_mut() else {
+                    return glib::ControlFlow::Continue;
+                };
+                if app.testing {
+                    return glib::ControlFlow::Continue;
+                }
+                test_b.set_sensitive(true);
+                status_l.set_text(&app.status);
+                let report = app.diagnostics.take();
+                drop(app);
+                if let Some(report) = report {
+                    show_diagnostics(&win_c, &report);
+                }
+                glib::ControlFlow::Break
+            });
+        });
+    }
+
+    // Topic discovery wizard
+    {
+        let state_c = state.clone();
+        let save_b = save_btn.clone();
+        let win_c = win.clone();
+        let parent_c = parent.clone();
+        discover_btn.connect_clicked(move |_| {
+            // The wizard connects with the settings as entered.
+            save_b.emit_clicked();
+            show_topic_wizard(state_c.clone(), &win_c, &parent_c);
         });
     }
 
@@ -1083,6 +2172,411 @@ fn show_config_window(state: Rc<RefCell<Air1App>>, parent: &gtk4::Window) {
     win.present();
 }
 
+// ── Topic filter rows ─────────────────────────────────────────────────────────
+
+/// QoS choices for a filter row; the first entry uses the connection QoS.
+const FILTER_QOS_LABELS: [&str; 4] = ["Default QoS", "QoS 0", "QoS 1", "QoS 2"];
+
+struct FilterRow {
+    row: gtk4::Box,
+    filter: gtk4::Entry,
+    qos: gtk4::DropDown,
+}
+
+impl FilterRow {
+    fn to_filter(&self) -> config::TopicFilter {
+        config::TopicFilter {
+            filter: self.filter.text().trim().to_string(),
+            qos: match self.qos.selected() {
+                0 => None,
+                idx => Some(idx as u8 - 1),
+            },
+        }
+    }
+}
+
+/// Layouts an adapter row can pick; `Auto` means no adapter at all.
+fn adapter_layouts() -> Vec<config::TopicAdapter> {
+    config::TopicAdapter::ALL
+        .into_iter()
+        .filter(|adapter| !adapter.is_auto())
+        .collect()
+}
+
+struct AdapterRow {
+    row: gtk4::Box,
+    kind: gtk4::DropDown,
+    base: gtk4::Entry,
+}
+
+impl AdapterRow {
+    fn to_adapter(&self) -> config::AdapterConfig {
+        let base = self.base.text().trim().to_string();
+        config::AdapterConfig {
+            kind: adapter_layouts()
+                .get(self.kind.selected() as usize)
+                .copied()
+                .unwrap_or_default(),
+            base: (!base.is_empty()).then_some(base),
+        }
+    }
+}
+
+/// Show the certificates in the CA file at `path`, flagging any that are
+/// expired or close to expiry.
+fn show_certificates(label: &gtk4::Label, path: &str) {
+    label.remove_css_class("warn-label");
+    let path = path.trim();
+    if path.is_empty() {
+        label.set_text("(system roots)");
+        return;
+    }
+    match tls::read_certificates(std::path::Path::new(path)) {
+        Ok(certs) => {
+            let now = SystemTime::now();
+            let mut warned = false;
+            let text = certs
+                .iter()
+                .map(|info| match info.expiry_warning(now) {
+                    Some(warning) => {
+                        warned = true;
+                        format!("{}\nWarning: {warning}", info.describe())
+                    }
+                    None => info.describe(),
+                })
+                .collect::<Vec<_>>()
+                .join("\n\n");
+            if warned {
+                label.add_css_class("warn-label");
+            }
+            label.set_text(&text);
+        }
+        Err(err) => {
+            label.add_css_class("warn-label");
+            label.set_text(&format!("{err:#}"));
+        }
+    }
+}
+
+/// Pinned fingerprint as shown in the config window.
+fn pin_summary(pin: Option<&config::CertPin>) -> String {
+    match pin {
+        Some(pin) => {
+            let what = match pin.kind {
+                config::PinKind::Certificate => "Certificate",
+                config::PinKind::Spki => "Public key",
+            };
+            format!("{what}: {}", pin.sha256)
+        }
+        None => "(none)".to_string(),
+    }
+}
+
+/// Parse `key=value` pairs separated by commas, as used for MQTT 5 user
+/// properties and WebSocket headers.
+fn parse_pairs(text: &str) -> anyhow::Result<BTreeMap<String, String>> {
+    let mut props = BTreeMap::new();
+    for pair in text.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let Some((key, value)) = pair.split_once('=') else {
+            anyhow::bail!("expected key=value, got {pair}");
+        };
+        let key = key.trim();
+        if key.is_empty() {
+            anyhow::bail!("name must be non-empty in {pair}");
+        }
+        props.insert(key.to_string(), value.trim().to_string());
+    }
+    Ok(props)
+}
+
+fn append_adapter_row(
+    list_box: &gtk4::Box,
+    rows: &Rc<RefCell<Vec<AdapterRow>>>,
+    adapter: &config::AdapterConfig,
+) {
+    let row = gtk4::Box::new(gtk4::Orientation::Horizontal, 4);
+
+    let layouts = adapter_layouts();
+    let labels: Vec<&str> = layouts.iter().map(|a| a.label()).collect();
+    let kind = gtk4::DropDown::from_strings(&labels);
+    kind.set_selected(layouts.iter().position(|a| *a == adapter.kind).unwrap_or(0) as u32);
+    row.append(&kind);
+
+    let base = gtk4::Entry::new();
+    base.set_text(adapter.base.as_deref().unwrap_or_default());
+    base.set_hexpand(true);
+    row.append(&base);
+    {
+        // Without a base the adapter reads its layout's default base topic.
+        let base_c = base.clone();
+        let placeholder = move |idx: u32| {
+            let default = adapter_layouts()
+                .get(idx as usize)
+                .copied()
+                .unwrap_or_default()
+                .default_base();
+            base_c.set_placeholder_text(Some(&format!("base topic (default: {default})")));
+        };
+        placeholder(kind.selected());
+        kind.connect_selected_notify(move |dd| placeholder(dd.selected()));
+    }
+
+    let remove_btn = gtk4::Button::with_label("Remove");
+    row.append(&remove_btn);
+    {
+        let list_c = list_box.clone();
+        let rows_c = rows.clone();
+        let row_c = row.clone();
+        remove_btn.connect_clicked(move |_| {
+            list_c.remove(&row_c);
+            rows_c.borrow_mut().retain(|r| r.row != row_c);
+        });
+    }
+
+    list_box.append(&row);
+    rows.borrow_mut().push(AdapterRow { row, kind, base });
+}
+
+fn append_filter_row(
+    list_box: &gtk4::Box,
+    rows: &Rc<RefCell<Vec<FilterRow>>>,
+    topic_filter: &config::TopicFilter,
+) {
+    let row = gtk4::Box::new(gtk4::Orientation::Horizontal, 4);
+
+    let filter = gtk4::Entry::new();
+    filter.set_text(&topic_filter.filter);
+    filter.set_placeholder_text(Some("topic filter (e.g. air1/sensor/+/state)"));
+    filter.set_hexpand(true);
+    row.append(&filter);
+
+    let qos = gtk4::DropDown::from_strings(&FILTER_QOS_LABELS);
+    qos.set_selected(topic_filter.qos.map_or(0, |q| u32::from(q.min(2)) + 1));
+    row.append(&qos);
+
+    let remove_btn = gtk4::Button::with_label("Remove");
+    row.append(&remove_btn);
+    {
+        let list_c = list_box.clone();
+        let rows_c = rows.clone();
+        let row_c = row.clone();
+        remove_btn.connect_clicked(move |_| {
+            list_c.remove(&row_c);
+            rows_c.borrow_mut().retain(|r| r.row != row_c);
+        });
+    }
+
+    list_box.append(&row);
+    rows.borrow_mut().push(FilterRow { row, filter, qos });
+}
+
+// ── Mapping rule rows ─────────────────────────────────────────────────────────
+
+struct RuleRow {
+    row: gtk4::Box,
+    topic: gtk4::Entry,
+    match_type: gtk4::DropDown,
+    metric: gtk4::Entry,
+    path: gtk4::Entry,
+    scale: gtk4::SpinButton,
+    unit: gtk4::Entry,
+    device: gtk4::Entry,
+}
+
+impl RuleRow {
+    fn to_rule(&self) -> config::MappingRule {
+        let match_type = config::TopicMatch::ALL
+            .get(self.match_type.selected() as usize)
+            .copied()
+            .unwrap_or_default();
+        let unit = self.unit.text().to_string();
+        let path = self.path.text().trim().to_string();
+        let device = self.device.text().trim().to_string();
+        config::MappingRule {
+            topic: self.topic.text().trim().to_string(),
+            match_type,
+            metric: self.metric.text().trim().to_string(),
+            path: if path.is_empty() { None } else { Some(path) },
+            scale: self.scale.value(),
+            unit: if unit.trim().is_empty() {
+                None
+            } else {
+                Some(unit)
+            },
+            device: if device.is_empty() {
+                None
+            } else {
+                Some(device)
+            },
+        }
+    }
+}
+
+fn append_rule_row(
+    list_box: &gtk4::Box,
+    rows: &Rc<RefCell<Vec<RuleRow>>>,
+    rule: &config::MappingRule,
+) {
+    let row = gtk4::Box::new(gtk4::Orientation::Horizontal, 4);
+
+    let topic = gtk4::Entry::new();
+    topic.set_text(&rule.topic);
+    topic.set_placeholder_text(Some("topic, filter or regex"));
+    topic.set_hexpand(true);
+    row.append(&topic);
+
+    let labels: Vec<&str> = config::TopicMatch::ALL.iter().map(|m| m.label()).collect();
+    let match_type = gtk4::DropDown::from_strings(&labels);
+    let selected = config::TopicMatch::ALL
+        .iter()
+        .position(|m| *m == rule.match_type)
+        .unwrap_or(0);
+    match_type.set_selected(selected as u32);
+    row.append(&match_type);
+
+    let metric = gtk4::Entry::new();
+    metric.set_text(&rule.metric);
+    metric.set_placeholder_text(Some("metric (e.g. pm25)"));
+    metric.set_width_chars(12);
+    row.append(&metric);
+
+    let path = gtk4::Entry::new();
+    path.set_text(rule.path.as_deref().unwrap_or_default());
+    path.set_placeholder_text(Some("JSON path"));
+    path.set_tooltip_text(Some(
+        "Optional JSON pointer (/a/b) or JSONPath ($.a['b']) for JSON payloads",
+    ));
+    path.set_width_chars(12);
+    row.append(&path);
+
+    let scale = gtk4::SpinButton::with_range(-1_000_000.0, 1_000_000.0, 0.1);
+    scale.set_digits(3);
+    scale.set_value(rule.scale);
+    scale.set_tooltip_text(Some("Scale factor"));
+    row.append(&scale);
+
+    let unit = gtk4::Entry::new();
+    unit.set_text(rule.unit.as_deref().unwrap_or_default());
+    unit.set_placeholder_text(Some("unit"));
+    unit.set_width_chars(6);
+    row.append(&unit);
+
+    let device = gtk4::Entry::new();
+    device.set_text(rule.device.as_deref().unwrap_or_default());
+    device.set_placeholder_text(Some("device"));
+    device.set_tooltip_text(Some(
+        "Optional device name; regex rules may use capture groups ($1, ${name})",
+    ));
+    device.set_width_chars(10);
+    row.append(&device);
+
+    let remove_btn = gtk4::Button::with_label("Remove");
+    row.append(&remove_btn);
+    {
+        let list_c = list_box.clone();
+        let rows_c = rows.clone();
+        let row_c = row.clone();
+        remove_btn.connect_clicked(move |_| {
+            list_c.remove(&row_c);
+            rows_c.borrow_mut().retain(|r| r.row != row_c);
+        });
+    }
+
+    list_box.append(&row);
+    rows.borrow_mut().push(RuleRow {
+        row,
+        topic,
+        match_type,
+        metric,
+        path,
+        scale,
+        unit,
+        device,
+    });
+}
+
+// ── Source policy rows ────────────────────────────────────────────────────────
+
+struct SourceRow {
+    row: gtk4::Box,
+    metric: gtk4::Entry,
+    mode: gtk4::DropDown,
+    topic: gtk4::Entry,
+}
+
+impl SourceRow {
+    fn to_policy(&self) -> config::SourcePolicy {
+        let mode = config::SourceMode::ALL
+            .get(self.mode.selected() as usize)
+            .copied()
+            .unwrap_or_default();
+        let topic = self.topic.text().trim().to_string();
+        config::SourcePolicy {
+            metric: self.metric.text().trim().to_string(),
+            mode,
+            topic: if topic.is_empty() { None } else { Some(topic) },
+        }
+    }
+}
+
+fn append_source_row(
+    list_box: &gtk4::Box,
+    rows: &Rc<RefCell<Vec<SourceRow>>>,
+    policy: &config::SourcePolicy,
+) {
+    let row = gtk4::Box::new(gtk4::Orientation::Horizontal, 4);
+
+    let metric = gtk4::Entry::new();
+    metric.set_text(&policy.metric);
+    metric.set_placeholder_text(Some("metric (e.g. temperature)"));
+    metric.set_width_chars(14);
+    row.append(&metric);
+
+    let labels: Vec<&str> = config::SourceMode::ALL.iter().map(|m| m.label()).collect();
+    let mode = gtk4::DropDown::from_strings(&labels);
+    let selected = config::SourceMode::ALL
+        .iter()
+        .position(|m| *m == policy.mode)
+        .unwrap_or(0);
+    mode.set_selected(selected as u32);
+    row.append(&mode);
+
+    let topic = gtk4::Entry::new();
+    topic.set_text(policy.topic.as_deref().unwrap_or_default());
+    topic.set_placeholder_text(Some("preferred topic"));
+    topic.set_hexpand(true);
+    topic.set_sensitive(policy.mode == config::SourceMode::Preferred);
+    row.append(&topic);
+    {
+        let topic_c = topic.clone();
+        mode.connect_selected_notify(move |dd| {
+            let preferred = config::SourceMode::ALL.get(dd.selected() as usize)
+                == Some(&config::SourceMode::Preferred);
+            topic_c.set_sensitive(preferred);
+        });
+    }
+
+    let remove_btn = gtk4::Button::with_label("Remove");
+    row.append(&remove_btn);
+    {
+        let list_c = list_box.clone();
+        let rows_c = rows.clone();
+        let row_c = row.clone();
+        remove_btn.connect_clicked(move |_| {
+            list_c.remove(&row_c);
+            rows_c.borrow_mut().retain(|r| r.row != row_c);
+        });
+    }
+
+    list_box.append(&row);
+    rows.borrow_mut().push(SourceRow {
+        row,
+        metric,
+        mode,
+        topic,
+    });
+}
+
 // ── Layout editor ─────────────────────────────────────────────────────────────
 
 fn show_layout_window(state: Rc<RefCell<Air1App>>, parent: &gtk4::Window) {
@@ -1250,6 +2744,876 @@ fn rebuild_layout_list(list_box: &gtk4::Box, state: Rc<RefCell<Air1App>>) {
     }
 }
 
+// ── Topic explorer ───────────────────────────────────────────────────────────
+
+const EXPLORER_COLUMNS: [&str; 7] = [
+    "Topic",
+    "Last payload",
+    "Retained",
+    "QoS",
+    "Rate",
+    "Last seen",
+    "Mapping",
+];
+/// Model column holding the full topic; empty for intermediate levels.
+const EXPLORER_TOPIC_COLUMN: u32 = 7;
+
+/// Live tree of every topic the listener receives, refreshed once a second,
+/// with a bar to map the selected topic in one step.
+fn show_explorer_window(state: Rc<RefCell<Air1App>>, parent: &gtk4::Window) {
+    let win = gtk4::Window::builder()
+        .transient_for(parent)
+        .title("Topic Explorer")
+        .default_width(1000)
+        .default_height(600)
+        .build();
+
+    let vbox = gtk4::Box::new(gtk4::Orientation::Vertical, 6);
+    vbox.set_margin_top(12);
+    vbox.set_margin_bottom(12);
+    vbox.set_margin_start(12);
+    vbox.set_margin_end(12);
+    win.set_child(Some(&vbox));
+
+    let header_row = gtk4::Box::new(gtk4::Orientation::Horizontal, 6);
+    let summary_lbl = gtk4::Label::new(None);
+    summary_lbl.set_xalign(0.0);
+    summary_lbl.set_hexpand(true);
+    header_row.append(&summary_lbl);
+    let clear_btn = gtk4::Button::with_label("Clear");
+    header_row.append(&clear_btn);
+    vbox.append(&header_row);
+
+    let store = gtk4::TreeStore::new(&[glib::Type::STRING; 8]);
+    let tree = gtk4::TreeView::with_model(&store);
+    for (idx, title) in EXPLORER_COLUMNS.iter().enumerate() {
+        let cell = gtk4::CellRendererText::new();
+        if idx == 1 || idx == 6 {
+            cell.set_ellipsize(gtk4::pango::EllipsizeMode::End);
+        }
+        let column = gtk4::TreeViewColumn::new();
+        column.set_title(title);
+        column.set_resizable(true);
+        column.set_expand(idx == 1 || idx == 6);
+        column.pack_start(&cell, true);
+        column.add_attribute(&cell, "text", idx as i32);
+        tree.append_column(&column);
+    }
+    let scroll = gtk4::ScrolledWindow::builder()
+        .vexpand(true)
+        .child(&tree)
+        .build();
+    vbox.append(&scroll);
+
+    let rule_row = gtk4::Box::new(gtk4::Orientation::Horizontal, 6);
+    let selected_lbl = gtk4::Label::new(Some("Select a topic to map it"));
+    selected_lbl.set_xalign(0.0);
+    selected_lbl.set_hexpand(true);
+    selected_lbl.set_ellipsize(gtk4::pango::EllipsizeMode::Middle);
+    rule_row.append(&selected_lbl);
+    let path_entry = gtk4::Entry::new();
+    path_entry.set_placeholder_text(Some("JSON pointer (optional)"));
+    rule_row.append(&path_entry);
+    let metric_entry = gtk4::Entry::new();
+    metric_entry.set_placeholder_text(Some("metric (e.g. pm25)"));
+    metric_entry.set_width_chars(12);
+    rule_row.append(&metric_entry);
+    let add_rule_btn = gtk4::Button::with_label("Create rule");
+    add_rule_btn.set_sensitive(false);
+    rule_row.append(&add_rule_btn);
+    vbox.append(&rule_row);
+
+    let status_lbl = gtk4::Label::new(None);
+    status_lbl.set_xalign(0.0);
+    status_lbl.add_css_class("last-topic");
+    vbox.append(&status_lbl);
+
+    // Tree rows by topic prefix, so refreshes update rows in place and keep
+    // the user's expansion and selection.
+    let nodes: Rc<RefCell<HashMap<String, gtk4::TreeIter>>> = Rc::new(RefCell::new(HashMap::new()));
+    let selected: Rc<RefCell<Option<String>>> = Rc::new(RefCell::new(None));
+
+    let refresh = {
+        let state_c = state.clone();
+        let store_c = store.clone();
+        let nodes_c = nodes.clone();
+        let summary_l = summary_lbl.clone();
+        move || {
+            let app = state_c.borrow();
+            let now = std::time::Instant::now();
+            let mut nodes = nodes_c.borrow_mut();
+            for stats in app.explorer.topics() {
+                let msg = &stats.last;
+                let iter = explorer_node(&store_c, &mut nodes, &msg.topic);
+                let age = msg.received.elapsed().unwrap_or_default();
+                store_c.set(
+                    &iter,
+                    &[
+                        (1, &explorer::payload_preview(&msg.payload, 120)),
+                        (2, &if msg.retain { "yes" } else { "" }),
+                        (3, &msg.qos.to_string()),
+                        (4, &format!("{}/min", stats.rate_per_minute(now))),
+                        (5, &format!("{} ago", format_age(age))),
+                        (6, &stats.mapping()),
+                        (EXPLORER_TOPIC_COLUMN, &msg.topic),
+                    ],
+                );
+            }
+            let mut summary = format!(
+                "{} topics, {} unmapped",
+                app.explorer.len(),
+                app.explorer.unmapped()
+            );
+            if app.explorer.skipped() > 0 {
+                summary.push_str(&format!(
+                    "; explorer full, {} messages on new topics left out",
+                    app.explorer.skipped()
+                ));
+            }
+            summary_l.set_text(&summary);
+        }
+    };
+    refresh();
+
+    {
+        let state_c = state.clone();
+        let selected_c = selected.clone();
+        let selected_l = selected_lbl.clone();
+        let path_e = path_entry.clone();
+        let metric_e = metric_entry.clone();
+        let add_b = add_rule_btn.clone();
+        tree.selection().connect_changed(move |selection| {
+            let topic = selection
+                .selected()
+                .map(|(model, iter)| model.get::<String>(&iter, EXPLORER_TOPIC_COLUMN as i32))
+                .filter(|topic| !topic.is_empty());
+            add_b.set_sensitive(topic.is_some());
+            let Some(topic) = topic else {
+                selected_l.set_text("Select a topic to map it");
+                *selected_c.borrow_mut() = None;
+                return;
+            };
+            let app = state_c.borrow();
+            let suggestion = app
+                .explorer
+                .get(&topic)
+                .and_then(|stats| stats.suggested_rule(&app.cfg.mappings));
+            path_e.set_text(
+                suggestion
+                    .as_ref()
+                    .and_then(|s| s.path.as_deref())
+                    .unwrap_or_default(),
+            );
+            metric_e.set_text(
+                suggestion
+                    .as_ref()
+                    .and_then(|s| s.proposed.as_deref())
+                    .unwrap_or_default(),
+            );
+            selected_l.set_text(&topic);
+            *selected_c.borrow_mut() = Some(topic);
+        });
+    }
+
+    {
+        let state_c = state.clone();
+        let selected_c = selected.clone();
+        let path_e = path_entry.clone();
+        let metric_e = metric_entry.clone();
+        let status_l = status_lbl.clone();
+        add_rule_btn.connect_clicked(move |_| {
+            let Some(topic) = selected_c.borrow().clone() else {
+                return;
+            };
+            let path = path_e.text().trim().to_string();
+            let rule = config::MappingRule {
+                topic: topic.clone(),
+                match_type: config::TopicMatch::Exact,
+                metric: metric_e.text().trim().to_string(),
+                path: (!path.is_empty()).then_some(path),
+                scale: 1.0,
+                unit: None,
+                device: None,
+            };
+            if let Err(err) = rule.validate() {
+                status_l.set_text(&format!("Invalid mapping rule: {err:#}"));
+                return;
+            }
+            let metric = rule.metric.clone();
+            let mut app = state_c.borrow_mut();
+            wizard::apply(&mut app.cfg, vec![rule]);
+            app.save_all();
+            app.restart_mqtt();
+            status_l.set_text(&format!("Mapped {topic} to {metric}; reconnecting"));
+        });
+    }
+
+    {
+        let state_c = state.clone();
+        let store_c = store.clone();
+        let nodes_c = nodes.clone();
+        clear_btn.connect_clicked(move |_| {
+            state_c.borrow_mut().explorer.clear();
+            nodes_c.borrow_mut().clear();
+            store_c.clear();
+        });
+    }
+
+    let closed = Rc::new(Cell::new(false));
+    {
+        let closed_c = closed.clone();
+        glib::timeout_add_local(Duration::from_secs(1), move || {
+            if closed_c.get() {
+                return glib::ControlFlow::Break;
+            }
+            refresh();
+            glib::ControlFlow::Continue
+        });
+    }
+    win.connect_close_request(move |_| {
+        closed.set(true);
+        glib::Propagation::Proceed
+    });
+
+    win.present();
+}
+
+/// Row of a topic in the explorer tree, adding rows for missing levels.
+fn explorer_node(
+    store: &gtk4::TreeStore,
+    nodes: &mut HashMap<String, gtk4::TreeIter>,
+    topic: &str,
+) -> gtk4::TreeIter {
+    let mut parent: Option<gtk4::TreeIter> = None;
+    let mut end = 0;
+    for level in topic.split('/') {
+        end += level.len();
+        let prefix = &topic[..end];
+        let iter = match nodes.get(prefix) {
+            Some(iter) => iter.clone(),
+            None => {
+                let iter = store.append(parent.as_ref());
+                store.set(&iter, &[(0, &level)]);
+                nodes.insert(prefix.to_string(), iter.clone());
+                iter
+            }
+        };
+        parent = Some(iter);
+        end += 1;
+    }
+    parent.expect("split yields at least one level")
+}
+
+// ── Traffic inspector ────────────────────────────────────────────────────────
+
+const INSPECTOR_COLUMNS: [&str; 6] = ["Time (UTC)", "Topic", "Payload", "Retain", "QoS", "Outcome"];
+
+/// Scrolling log of raw publishes, newest first, with filters, pause and
+/// JSONL export of what the filters show.
+fn show_inspector_window(state: Rc<RefCell<Air1App>>, parent: &gtk4::Window) {
+    let win = gtk4::Window::builder()
+        .transient_for(parent)
+        .title("Traffic Inspector")
+        .default_width(1000)
+        .default_height(600)
+        .build();
+
+    let vbox = gtk4::Box::new(gtk4::Orientation::Vertical, 6);
+    vbox.set_margin_top(12);
+    vbox.set_margin_bottom(12);
+    vbox.set_margin_start(12);
+    vbox.set_margin_end(12);
+    win.set_child(Some(&vbox));
+
+    let filter_row = gtk4::Box::new(gtk4::Orientation::Horizontal, 6);
+    let topic_entry = gtk4::SearchEntry::new();
+    topic_entry.set_placeholder_text(Some("Topic (text or MQTT filter)"));
+    topic_entry.set_hexpand(true);
+    filter_row.append(&topic_entry);
+    let payload_entry = gtk4::SearchEntry::new();
+    payload_entry.set_placeholder_text(Some("Payload contains"));
+    payload_entry.set_hexpand(true);
+    filter_row.append(&payload_entry);
+    let hex_check = gtk4::CheckButton::with_label("Hex");
+    hex_check.set_tooltip_text(Some("Show every payload as hex bytes"));
+    filter_row.append(&hex_check);
+    let pause_btn = gtk4::ToggleButton::with_label("Pause");
+    pause_btn.set_active(state.borrow().traffic.is_paused());
+    filter_row.append(&pause_btn);
+    let clear_btn = gtk4::Button::with_label("Clear");
+    filter_row.append(&clear_btn);
+    let export_btn = gtk4::Button::with_label("Export JSONL…");
+    filter_row.append(&export_btn);
+    vbox.append(&filter_row);
+
+    let store = gtk4::ListStore::new(&[glib::Type::STRING; 6]);
+    let tree = gtk4::TreeView::with_model(&store);
+    for (idx, title) in INSPECTOR_COLUMNS.iter().enumerate() {
+        let cell = gtk4::CellRendererText::new();
+        if idx == 2 || idx == 5 {
+            cell.set_ellipsize(gtk4::pango::EllipsizeMode::End);
+        }
+        let column = gtk4::TreeViewColumn::new();
+        column.set_title(title);
+        column.set_resizable(true);
+        column.set_expand(idx == 2 || idx == 5);
+        column.pack_start(&cell, true);
+        column.add_attribute(&cell, "text", idx as i32);
+        tree.append_column(&column);
+    }
+    let scroll = gtk4::ScrolledWindow::builder()
+        .vexpand(true)
+        .child(&tree)
+        .build();
+    vbox.append(&scroll);
+
+    let status_lbl = gtk4::Label::new(None);
+    status_lbl.set_xalign(0.0);
+    status_lbl.add_css_class("last-topic");
+    vbox.append(&status_lbl);
+
+    let filter = Rc::new(RefCell::new(inspector::TrafficFilter::default()));
+    let shown_version: Rc<Cell<Option<u64>>> = Rc::new(Cell::new(None));
+    // Numbers of the publishes in the store, newest first like its rows.
+    let shown_rows: Rc<RefCell<VecDeque<u64>>> = Rc::new(RefCell::new(VecDeque::new()));
+    // Newest publish checked against the filter.
+    let scanned = Rc::new(Cell::new(0u64));
+
+    // Prepends publishes logged since the last call and drops rows the log
+    // no longer holds; `full` starts over, for a new filter or format.
+    let refresh = Rc::new({
+        let state_c = state.clone();
+        let store_c = store.clone();
+        let filter_c = filter.clone();
+        let hex_c = hex_check.clone();
+        let status_l = status_lbl.clone();
+        let shown_c = shown_version.clone();
+        let rows_c = shown_rows.clone();
+        let scanned_c = scanned.clone();
+        move |full: bool| {
+            let app = state_c.borrow();
+            let filter = filter_c.borrow();
+            let hex = hex_c.is_active();
+            let mut rows = rows_c.borrow_mut();
+            if full {
+                store_c.clear();
+                rows.clear();
+                scanned_c.set(0);
+            }
+            let oldest = app.traffic.oldest();
+            while rows.back().is_some_and(|number| *number < oldest) {
+                if let Some(iter) = store_c.iter_nth_child(None, rows.len() as i32 - 1) {
+                    store_c.remove(&iter);
+                }
+                rows.pop_back();
+            }
+            for (number, msg) in app.traffic.matching_after(&filter, scanned_c.get()) {
+                let payload = if hex {
+                    explorer::payload_hex(&msg.payload, 64)
+                } else {
+                    explorer::payload_preview(&msg.payload, 200)
+                };
+                store_c.insert_with_values(
+                    Some(0),
+                    &[
+                        (0, &inspector::clock(msg.received)),
+                        (1, &msg.topic),
+                        (2, &payload),
+                        (3, &if msg.retain { "yes" } else { "" }),
+                        (4, &msg.qos.to_string()),
+                        (5, &msg.describe()),
+                    ],
+                );
+                rows.push_front(number);
+            }
+            scanned_c.set(app.traffic.pushed());
+            let mut status = format!("{} of {} messages shown", rows.len(), app.traffic.len());
+            if app.traffic.is_paused() {
+                status.push_str(&format!(
+                    "; paused, {} messages not logged",
+                    app.traffic.skipped()
+                ));
+            }
+            status_l.set_text(&status);
+            shown_c.set(Some(app.traffic.version()));
+        }
+    });
+    refresh(true);
+
+    {
+        let filter_c = filter.clone();
+        let refresh = refresh.clone();
+        topic_entry.connect_search_changed(move |e| {
+            filter_c.borrow_mut().topic = e.text().to_string();
+            refresh(true);
+        });
+    }
+    {
+        let filter_c = filter.clone();
+        let refresh = refresh.clone();
+        payload_entry.connect_search_changed(move |e| {
+            filter_c.borrow_mut().payload = e.text().to_string();
+            refresh(true);
+        });
+    }
+    {
+        let refresh = refresh.clone();
+        hex_check.connect_toggled(move |_| refresh(true));
+    }
+    {
+        let state_c = state.clone();
+        pause_btn.connect_toggled(move |btn| {
+            state_c.borrow_mut().traffic.set_paused(btn.is_active());
+        });
+    }
+    {
+        let state_c = state.clone();
+        clear_btn.connect_clicked(move |_| state_c.borrow_mut().traffic.clear());
+    }
+
+    {
+        let state_c = state.clone();
+        let filter_c = filter.clone();
+        let status_l = status_lbl.clone();
+        let win_c = win.clone();
+        export_btn.connect_clicked(move |_| {
+            let dialog = gtk4::FileChooserDialog::new(
+                Some("Export Traffic"),
+                Some(&win_c),
+                gtk4::FileChooserAction::Save,
+                &[
+                    ("Cancel", gtk4::ResponseType::Cancel),
+                    ("Export", gtk4::ResponseType::Accept),
+                ],
+            );
+            dialog.set_modal(true);
+            dialog.set_current_name("air1-traffic.jsonl");
+            let state_c = state_c.clone();
+            let filter_c = filter_c.clone();
+            let status_l = status_l.clone();
+            dialog.connect_response(move |d, response| {
+                if response == gtk4::ResponseType::Accept
+                    && let Some(path) = d.file().and_then(|f| f.path())
+                {
+                    let app = state_c.borrow();
+                    let filter = filter_c.borrow();
+                    match inspector::export_jsonl(&path, app.traffic.matching(&filter)) {
+                        Ok(count) => status_l
+                            .set_text(&format!("Exported {count} messages to {}", path.display())),
+                        Err(err) => status_l.set_text(&format!("Export failed: {err:#}")),
+                    }
+                }
+                d.close();
+            });
+            dialog.present();
+        });
+    }
+
+    let closed = Rc::new(Cell::new(false));
+    {
+        let state_c = state.clone();
+        let closed_c = closed.clone();
+        glib::timeout_add_local(Duration::from_millis(500), move || {
+            if closed_c.get() {
+                return glib::ControlFlow::Break;
+            }
+            if shown_version.get() != Some(state_c.borrow().traffic.version()) {
+                refresh(false);
+            }
+            glib::ControlFlow::Continue
+        });
+    }
+    {
+        let state_c = state.clone();
+        win.connect_close_request(move |_| {
+            closed.set(true);
+            // A paused log would silently miss traffic with nothing showing it.
+            state_c.borrow_mut().traffic.set_paused(false);
+            glib::Propagation::Proceed
+        });
+    }
+
+    win.present();
+}
+
+// ── Topic discovery wizard ────────────────────────────────────────────────────
+
+struct WizardRow {
+    scanned: wizard::ScannedTopic,
+    accept: gtk4::CheckButton,
+    metric: gtk4::Entry,
+}
+
+/// Listen on a topic tree, then let the user accept, edit or reject a
+/// proposed mapping rule per numeric topic. Applying reopens the config
+/// window so it shows the new rules and filters.
+fn show_topic_wizard(
+    state: Rc<RefCell<Air1App>>,
+    config_win: &gtk4::Window,
+    parent: &gtk4::Window,
+) {
+    let win = gtk4::Window::builder()
+        .transient_for(config_win)
+        .modal(true)
+        .title("Discover Topics")
+        .default_width(820)
+        .default_height(560)
+        .build();
+
+    let vbox = gtk4::Box::new(gtk4::Orientation::Vertical, 6);
+    vbox.set_margin_top(12);
+    vbox.set_margin_bottom(12);
+    vbox.set_margin_start(12);
+    vbox.set_margin_end(12);
+    win.set_child(Some(&vbox));
+
+    let scan_row = gtk4::Box::new(gtk4::Orientation::Horizontal, 6);
+    scan_row.append(&gtk4::Label::new(Some("Listen on")));
+    let filter_entry = gtk4::Entry::new();
+    let root = match state
+        .borrow()
+        .cfg
+        .mqtt
+        .topic_prefix
+        .as_deref()
+        .map(str::trim)
+    {
+        Some(prefix) if !prefix.is_empty() => format!("{prefix}/#"),
+        _ => "#".to_string(),
+    };
+    filter_entry.set_text(&root);
+    filter_entry.set_hexpand(true);
+    scan_row.append(&filter_entry);
+    scan_row.append(&gtk4::Label::new(Some("for")));
+    let secs_spin = gtk4::SpinButton::with_range(5.0, 600.0, 5.0);
+    secs_spin.set_value(30.0);
+    scan_row.append(&secs_spin);
+    scan_row.append(&gtk4::Label::new(Some("seconds")));
+    let scan_btn = gtk4::Button::with_label("Start scan");
+    scan_row.append(&scan_btn);
+    vbox.append(&scan_row);
+
+    let status_lbl = gtk4::Label::new(Some(
+        "Numeric topics seen during the scan are listed with a proposed metric.",
+    ));
+    status_lbl.set_xalign(0.0);
+    vbox.append(&status_lbl);
+
+    let list_box = gtk4::Box::new(gtk4::Orientation::Vertical, 4);
+    let scroll = gtk4::ScrolledWindow::builder()
+        .vexpand(true)
+        .hscrollbar_policy(gtk4::PolicyType::Never)
+        .child(&list_box)
+        .build();
+    vbox.append(&scroll);
+
+    let btn_row = gtk4::Box::new(gtk4::Orientation::Horizontal, 6);
+    let apply_btn = gtk4::Button::with_label("Apply accepted");
+    apply_btn.set_sensitive(false);
+    let close_btn = gtk4::Button::with_label("Close");
+    btn_row.append(&apply_btn);
+    btn_row.append(&close_btn);
+    vbox.append(&btn_row);
+
+    let rows: Rc<RefCell<Vec<WizardRow>>> = Rc::new(RefCell::new(Vec::new()));
+    // Dropping the sender stops a running scan.
+    let stop: Rc<RefCell<Option<std::sync::mpsc::Sender<()>>>> = Rc::new(RefCell::new(None));
+
+    {
+        let state_c = state.clone();
+        let filter_e = filter_entry.clone();
+        let secs_s = secs_spin.clone();
+        let status_l = status_lbl.clone();
+        let list_c = list_box.clone();
+        let rows_c = rows.clone();
+        let apply_b = apply_btn.clone();
+        let stop_c = stop.clone();
+        scan_btn.connect_clicked(move |btn| {
+            let filter = filter_e.text().trim().to_string();
+            let check = config::TopicFilter {
+                filter: filter.clone(),
+                qos: None,
+            };
+            if let Err(err) = check.validate() {
+                status_l.set_text(&format!("{err:#}"));
+                return;
+            }
+            let duration = Duration::from_secs(secs_s.value() as u64);
+            let (result_rx, stop_tx) = state_c.borrow().spawn_topic_scan(filter.clone(), duration);
+            *stop_c.borrow_mut() = Some(stop_tx);
+            btn.set_sensitive(false);
+            apply_b.set_sensitive(false);
+            while let Some(child) = list_c.first_child() {
+                list_c.remove(&child);
+            }
+            rows_c.borrow_mut().clear();
+
+            let started = std::time::Instant::now();
+            let state_c = state_c.clone();
+            let btn = btn.clone();
+            let status_l = status_l.clone();
+            let list_c = list_c.clone();
+            let rows_c = rows_c.clone();
+            let apply_b = apply_b.clone();
+            let stop_c = stop_c.clone();
+            glib::timeout_add_local(Duration::from_millis(250), move || {
+                let result = match result_rx.try_recv() {
+                    Ok(result) => result,
+                    Err(std::sync::mpsc::TryRecvError::Empty) => {
+                        let left = duration.saturating_sub(started.elapsed());
+                        status_l
+                            .set_text(&format!("Listening on {filter}… {}s left", left.as_secs()));
+                        return glib::ControlFlow::Continue;
+                    }
+                    Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                        Err(anyhow::anyhow!("scan stopped unexpectedly"))
+                    }
+                };
+                stop_c.borrow_mut().take();
+                btn.set_sensitive(true);
+                match result {
+                    Ok(scan) if scan.is_empty() => {
+                        status_l.set_text(&format!("No numeric topics seen on {filter}"));
+                    }
+                    Ok(scan) => {
+                        let topics = scan.into_topics(&state_c.borrow().cfg.mappings);
+                        status_l.set_text(&format!(
+                            "Found {} numeric values. Tick the ones to keep and check the metric.",
+                            topics.len()
+                        ));
+                        for scanned in topics {
+                            append_wizard_row(&list_c, &rows_c, scanned);
+                        }
+                        apply_b.set_sensitive(true);
+                    }
+                    Err(err) => status_l.set_text(&format!("Scan failed: {err:#}")),
+                }
+                glib::ControlFlow::Break
+            });
+        });
+    }
+
+    {
+        let state_c = state.clone();
+        let rows_c = rows.clone();
+        let status_l = status_lbl.clone();
+        let win_c = win.clone();
+        let config_c = config_win.clone();
+        let parent_c = parent.clone();
+        apply_btn.connect_clicked(move |_| {
+            let rules: Vec<config::MappingRule> = rows_c
+                .borrow()
+                .iter()
+                .filter(|row| row.accept.is_active())
+                .map(|row| row.scanned.rule(&row.metric.text()))
+                .collect();
+            if rules.is_empty() {
+                status_l.set_text("No topics accepted");
+                return;
+            }
+            if let Err(err) = rules.iter().try_for_each(config::MappingRule::validate) {
+                status_l.set_text(&format!("Invalid mapping rule: {err:#}"));
+                return;
+            }
+            {
+                let mut app = state_c.borrow_mut();
+                wizard::apply(&mut app.cfg, rules);
+                app.save_all();
+                app.restart_mqtt();
+            }
+            win_c.close();
+            config_c.close();
+            show_config_window(state_c.clone(), &parent_c);
+        });
+    }
+
+    {
+        let win_c = win.clone();
+        close_btn.connect_clicked(move |_| win_c.close());
+    }
+    win.connect_close_request(move |_| {
+        stop.borrow_mut().take();
+        glib::Propagation::Proceed
+    });
+
+    win.present();
+}
+
+fn append_wizard_row(
+    list_box: &gtk4::Box,
+    rows: &Rc<RefCell<Vec<WizardRow>>>,
+    scanned: wizard::ScannedTopic,
+) {
+    let row = gtk4::Box::new(gtk4::Orientation::Horizontal, 6);
+
+    // New values with a proposal start accepted; rejecting is unticking.
+    let accept = gtk4::CheckButton::new();
+    accept.set_active(scanned.proposed.is_some() && !scanned.mapped);
+    row.append(&accept);
+
+    let name = match &scanned.path {
+        Some(path) => format!("{} {path}", scanned.topic),
+        None => scanned.topic.clone(),
+    };
+    let topic = gtk4::Label::new(Some(&name));
+    topic.set_xalign(0.0);
+    topic.set_hexpand(true);
+    topic.set_ellipsize(gtk4::pango::EllipsizeMode::Middle);
+    topic.set_tooltip_text(Some(&name));
+    row.append(&topic);
+
+    let samples = scanned
+        .samples
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut sample_text = match &scanned.unit {
+        Some(unit) => format!("{samples} {unit}"),
+        None => samples,
+    };
+    sample_text.push_str(&format!(" ({} msgs)", scanned.messages));
+    if scanned.mapped {
+        sample_text.push_str(", already mapped");
+    }
+    let sample_lbl = gtk4::Label::new(Some(&sample_text));
+    sample_lbl.add_css_class("last-topic");
+    row.append(&sample_lbl);
+
+    let metric = gtk4::Entry::new();
+    metric.set_text(scanned.proposed.as_deref().unwrap_or_default());
+    metric.set_placeholder_text(Some("metric (e.g. pm25)"));
+    metric.set_width_chars(12);
+    row.append(&metric);
+
+    list_box.append(&row);
+    rows.borrow_mut().push(WizardRow {
+        scanned,
+        accept,
+        metric,
+    });
+}
+
+// ── Connection diagnostics ────────────────────────────────────────────────────
+
+/// Show a connection test report as copyable text.
+fn show_diagnostics(parent: &gtk4::Window, report: &diagnostics::Report) {
+    let win = gtk4::Window::builder()
+        .transient_for(parent)
+        .modal(true)
+        .title("Connection Diagnostics")
+        .default_width(640)
+        .default_height(520)
+        .build();
+
+    let vbox = gtk4::Box::new(gtk4::Orientation::Vertical, 6);
+    vbox.set_margin_top(12);
+    vbox.set_margin_bottom(12);
+    vbox.set_margin_start(12);
+    vbox.set_margin_end(12);
+    win.set_child(Some(&vbox));
+
+    let summary = gtk4::Label::new(None);
+    summary.set_xalign(0.0);
+    match report.first_failure() {
+        Some(step) => {
+            summary.set_text(&format!("Failed at {}", step.name));
+            summary.add_css_class("connection-offline");
+        }
+        None => {
+            summary.set_text("All steps passed");
+            summary.add_css_class("connection-online");
+        }
+    }
+    vbox.append(&summary);
+
+    let text = report.to_string();
+    let view = gtk4::TextView::new();
+    view.set_editable(false);
+    view.set_monospace(true);
+    view.set_wrap_mode(gtk4::WrapMode::WordChar);
+    view.buffer().set_text(&text);
+    let scroll = gtk4::ScrolledWindow::builder()
+        .vexpand(true)
+        .hscrollbar_policy(gtk4::PolicyType::Never)
+        .child(&view)
+        .build();
+    vbox.append(&scroll);
+
+    let btn_row = gtk4::Box::new(gtk4::Orientation::Horizontal, 6);
+    vbox.append(&btn_row);
+    let copy_btn = gtk4::Button::with_label("Copy to clipboard");
+    let close_btn = gtk4::Button::with_label("Close");
+    btn_row.append(&copy_btn);
+    btn_row.append(&close_btn);
+    {
+        let win_c = win.clone();
+        copy_btn.connect_clicked(move |_| win_c.clipboard().set_text(&text));
+    }
+    {
+        let win_c = win.clone();
+        close_btn.connect_clicked(move |_| win_c.close());
+    }
+
+    win.present();
+}
+
+// ── Certificate pinning ───────────────────────────────────────────────────────
+
+const PIN_CERTIFICATE: u16 = 1;
+const PIN_PUBLIC_KEY: u16 = 2;
+
+/// Ask whether to trust the broker certificate seen on first connection.
+fn show_pin_dialog(
+    state: Rc<RefCell<Air1App>>,
+    parent: &gtk4::Window,
+    info: &tls::CertInfo,
+    open: Rc<Cell<bool>>,
+) {
+    let msg = format!(
+        "The broker presented a certificate that is not pinned yet. \
+        Compare the fingerprint with the broker before trusting it.\n\n\
+        Subject: {}\n\
+        Issuer: {}\n\
+        Expires: {}\n\n\
+        SHA-256:\n{}\n\n\
+        Public key SHA-256:\n{}",
+        info.subject,
+        info.issuer,
+        tls::format_time(info.not_after),
+        info.fingerprint(config::PinKind::Certificate),
+        info.fingerprint(config::PinKind::Spki),
+    );
+    let dlg = gtk4::MessageDialog::builder()
+        .transient_for(parent)
+        .modal(true)
+        .message_type(gtk4::MessageType::Question)
+        .text("Trust Broker Certificate?")
+        .secondary_text(&msg)
+        .build();
+    dlg.add_button("Disconnect", gtk4::ResponseType::Reject);
+    dlg.add_button("Pin Public Key", gtk4::ResponseType::Other(PIN_PUBLIC_KEY));
+    dlg.add_button(
+        "Pin Certificate",
+        gtk4::ResponseType::Other(PIN_CERTIFICATE),
+    );
+    dlg.set_default_response(gtk4::ResponseType::Other(PIN_CERTIFICATE));
+    dlg.connect_response(move |d, response| {
+        let mut app = state.borrow_mut();
+        match response {
+            gtk4::ResponseType::Other(PIN_CERTIFICATE) => {
+                app.pin_certificate(config::PinKind::Certificate)
+            }
+            gtk4::ResponseType::Other(PIN_PUBLIC_KEY) => app.pin_certificate(config::PinKind::Spki),
+            _ => app.reject_certificate(),
+        }
+        drop(app);
+        open.set(false);
+        d.close();
+    });
+    dlg.present();
+}
+
 // ── Keyring help ──────────────────────────────────────────────────────────────
 
 fn show_keyring_help(parent: &gtk4::Window) {
@@ -1275,30 +3639,6 @@ fn show_keyring_help(parent: &gtk4::Window) {
 
 // ── Helpers ───────────────────────────────────────────────────────────────────
 
-fn metric_value(app: &Air1App, id: &str) -> Option<f64> {
-    match id {
-        "pm25" => app.metrics.pm25,
-        "pm10" => app.metrics.pm10,
-        "pm1" => app.metrics.pm1,
-        "co2" => app.metrics.co2,
-        "tvoc" => app.metrics.tvoc,
-        "temperature" => app.metrics.temp,
-        "humidity" => app.metrics.humidity,
-        _ => None,
-    }
-}
-
-fn gauge_unit(id: &str) -> &'static str {
-    match id {
-        "pm25" | "pm10" | "pm1" => "μg/m³",
-        "co2" => "ppm",
-        "tvoc" => "ppb",
-        "temperature" => "°F",
-        "humidity" => "%",
-        _ => "",
-    }
-}
-
 fn clear_css_classes(widget: &impl IsA<gtk4::Widget>, classes: &[&str]) {
     for c in classes {
         widget.remove_css_class(c);
@@ -0,0 +1,87 @@
+use crate::metrics;
+
+/// Convert a value reported in `unit` into the dashboard unit of `kind`
+/// (see [`metrics::MetricDescriptor::unit`]).
+///
+/// Returns `None` when the unit is unknown or cannot be converted (e.g. a
+/// TVOC reading in µg/m³, which needs a molar mass), so callers can decide
+/// whether to keep the raw value.
+pub fn to_canonical(kind: &str, value: f64, unit: &str) -> Option<f64> {
+    let unit = normalize(unit);
+    let converted = match (metrics::canonical_id(kind), unit.as_str()) {
+        ("temperature", "°f" | "f") => value,
+        ("temperature", "°c" | "c") => value * 9.0 / 5.0 + 32.0,
+        ("temperature", "k") => (value - 273.15) * 9.0 / 5.0 + 32.0,
+        ("pm1" | "pm25" | "pm4" | "pm10", "µg/m³") => value,
+        ("pm1" | "pm25" | "pm4" | "pm10", "mg/m³") => value * 1000.0,
+        ("co2", "ppm") => value,
+        ("co2", "ppb") => value / 1000.0,
+        ("co2", "%") => value * 10_000.0,
+        ("tvoc", "ppb") => value,
+        ("tvoc", "ppm") => value * 1000.0,
+        ("humidity", "%") => value,
+        ("pc_0_3_1" | "pc_1_2_5" | "pc_2_5_4" | "pc_4_10", "#/cm³" | "1/cm³") => value,
+        ("pressure", "hpa" | "mbar") => value,
+        ("pressure", "pa") => value / 100.0,
+        ("pressure", "kpa") => value * 10.0,
+        ("pressure", "inhg") => value * 33.8639,
+        ("co" | "no2" | "ethanol" | "h2" | "nh3" | "ch4", "ppm") => value,
+        ("co" | "no2" | "ethanol" | "h2" | "nh3" | "ch4", "ppb") => value / 1000.0,
+        _ => return None,
+    };
+    Some(converted)
+}
+
+/// Convert a reading into the dashboard unit when possible.
+///
+/// Returns the value and the unit to display: `None` when the value is in the
+/// dashboard unit, or the original unit when it could not be converted.
+pub fn normalize_reading(kind: &str, value: f64, unit: Option<&str>) -> (f64, Option<String>) {
+    match unit {
+        None => (value, None),
+        Some(u) => match to_canonical(kind, value, u) {
+            Some(converted) => (converted, None),
+            None => (value, Some(u.to_string())),
+        },
+    }
+}
+
+fn normalize(unit: &str) -> String {
+    unit.trim()
+        .to_lowercase()
+        // Greek mu (U+03BC) and micro sign (U+00B5) are both common.
+        .replace('μ', "µ")
+        .replace("ug/m3", "µg/m³")
+        .replace("mg/m3", "mg/m³")
+        .replace("µg/m3", "µg/m³")
+        .replace("/cm3", "/cm³")
+        .replace("º", "°")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temperatures_convert_to_fahrenheit() {
+        assert_eq!(to_canonical("temperature", 20.0, "°C"), Some(68.0));
+        assert_eq!(to_canonical("temperature", 70.0, "°F"), Some(70.0));
+        let kelvin = to_canonical("temp", 293.15, "K").expect("kelvin converts");
+        assert!((kelvin - 68.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn mass_and_mixing_ratios_convert() {
+        assert_eq!(to_canonical("pm25", 0.012, "mg/m³"), Some(12.0));
+        assert_eq!(to_canonical("pm10", 7.0, "μg/m³"), Some(7.0));
+        assert_eq!(to_canonical("tvoc", 0.25, "ppm"), Some(250.0));
+        assert_eq!(to_canonical("tvoc", 120.0, "µg/m³"), None);
+        assert_eq!(
+            normalize_reading("tvoc", 120.0, Some("µg/m³")),
+            (120.0, Some("µg/m³".to_string()))
+        );
+        assert_eq!(normalize_reading("co2", 0.05, Some("%")), (500.0, None));
+        assert_eq!(to_canonical("pressure", 101_325.0, "Pa"), Some(1013.25));
+        assert_eq!(to_canonical("no2", 120.0, "ppb"), Some(0.12));
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use crate::config::{AppConfig, MappingRule, TopicFilter, TopicMatch};
+use crate::decode::parse_number;
+use crate::mapping::RuleSet;
+use crate::{metrics, mqtt};
+
+/// Sample values kept per topic.
+const MAX_SAMPLES: usize = 5;
+
+/// A numeric value found while scanning: a plain topic, or one field of a
+/// JSON topic.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScannedTopic {
+    pub topic: String,
+    /// JSON pointer of the field in JSON payloads.
+    pub path: Option<String>,
+    /// Most recent values, oldest first.
+    pub samples: Vec<f64>,
+    /// Unit carried in the payload, if any.
+    pub unit: Option<String>,
+    /// Messages seen on the topic.
+    pub messages: u32,
+    /// Metric the value most likely is: the one an existing rule maps it
+    /// to, otherwise a guess from the sensor or field name.
+    pub proposed: Option<String>,
+    /// Whether an existing mapping rule already covers the value.
+    pub mapped: bool,
+}
+
+impl ScannedTopic {
+    /// Exact-match rule feeding `metric` from this topic.
+    pub fn rule(&self, metric: &str) -> MappingRule {
+        MappingRule {
+            topic: self.topic.clone(),
+            match_type: TopicMatch::Exact,
+            metric: metric.trim().to_string(),
+            path: self.path.clone(),
+            scale: 1.0,
+            unit: None,
+            device: None,
+        }
+    }
+}
+
+/// Numeric topics collected while listening on a topic tree.
+#[derive(Debug, Default)]
+pub struct TopicScan {
+    found: BTreeMap<(String, Option<String>), ScannedTopic>,
+}
+
+impl TopicScan {
+    /// Record a publish: plain numbers are kept as they are, JSON objects
+    /// once per numeric field. Anything else is ignored.
+    pub fn record(&mut self, topic: &str, payload: &[u8]) {
+        let text = String::from_utf8_lossy(payload);
+        let text = text.trim();
+        if let Some(decoded) = parse_number(text) {
+            let name = mqtt::sensor_name(topic);
+            self.add(topic, None, name, decoded.value, decoded.unit);
+            return;
+        }
+        if !text.starts_with('{') {
+            return;
+        }
+        let Ok(Value::Object(fields)) = serde_json::from_str::<Value>(text) else {
+            return;
+        };
+        let unit = ["unit", "unit_of_measurement"]
+            .iter()
+            .find_map(|key| fields.get(*key).and_then(Value::as_str));
+        for (key, value) in &fields {
+            let number = match value {
+                Value::Number(n) => n.as_f64(),
+                Value::String(s) => parse_number(s).map(|d| d.value),
+                _ => None,
+            };
+            if let Some(number) = number {
+                let pointer = format!("/{}", key.replace('~', "~0").replace('/', "~1"));
+                self.add(topic, Some(pointer), key, number, unit.map(str::to_string));
+            }
+        }
+    }
+
+    fn add(
+        &mut self,
+        topic: &str,
+        path: Option<String>,
+        name: &str,
+        value: f64,
+        unit: Option<String>,
+    ) {
+        let entry = self
+            .found
+            .entry((topic.to_string(), path.clone()))
+            .or_insert_with(|| ScannedTopic {
+                topic: topic.to_string(),
+                path,
+                samples: Vec::new(),
+                unit: None,
+                messages: 0,
+                proposed: propose_metric(name).map(str::to_string),
+                mapped: false,
+            });
+        if entry.samples.len() >= MAX_SAMPLES {
+            entry.samples.remove(0);
+        }
+        entry.samples.push(value);
+        entry.unit = unit.or(entry.unit.take());
+        entry.messages += 1;
+    }
+
+    /// Number of numeric values found.
+    pub fn len(&self) -> usize {
+        self.found.len()
+    }
+
+    /// Return true if no numeric topic was seen.
+    pub fn is_empty(&self) -> bool {
+        self.found.is_empty()
+    }
+
+    /// Found values in topic order, with the metric existing rules already
+    /// map them to.
+    pub fn into_topics(self, mappings: &[MappingRule]) -> Vec<ScannedTopic> {
+        let rules = RuleSet::compile(mappings);
+        self.found
+            .into_values()
+            .map(|mut scanned| {
+                let existing = rules
+                    .matching(&scanned.topic)
+                    .into_iter()
+                    .find(|rule| rule.path == scanned.path);
+                if let Some(rule) = existing {
+                    scanned.proposed = Some(rule.metric.clone());
+                    scanned.mapped = true;
+                }
+                scanned
+            })
+            .collect()
+    }
+}
+
+/// Guess the metric of a sensor or JSON field name.
+fn propose_metric(name: &str) -> Option<&'static str> {
+    mqtt::map_sensor_kind(name).or_else(|| metrics::descriptor(name).map(|m| m.id))
+}
+
+/// Write accepted rules into the config: each replaces an exact rule for the
+/// same topic and field, and its topic becomes an explicit subscription
+/// unless a filter already covers it. Without explicit filters, the implicit
+/// ones are written first so the config keeps subscribing to them.
+pub fn apply(cfg: &mut AppConfig, rules: Vec<MappingRule>) {
+    if cfg.mqtt.filters.is_empty() && !rules.is_empty() {
+        cfg.mqtt.filters = mqtt::implicit_filters(&cfg.mqtt, &cfg.mappings)
+            .into_iter()
+            .map(|filter| TopicFilter { filter, qos: None })
+            .collect();
+    }
+    for rule in rules {
+        cfg.mappings.retain(|existing| {
+            !(existing.match_type == TopicMatch::Exact
+                && existing.topic.trim() == rule.topic
+                && existing.path == rule.path)
+        });
+        let covered = cfg
+            .mqtt
+            .filters
+            .iter()
+            .any(|f| mqtt::topic_matches(f.filter.trim(), &rule.topic));
+        if !covered {
+            cfg.mqtt.filters.push(TopicFilter {
+                filter: rule.topic.clone(),
+                qos: None,
+            });
+        }
+        cfg.mappings.push(rule);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_collects_numeric_topics_and_json_fields() {
+        let mut scan = TopicScan::default();
+        for value in ["10", "11", "12", "13", "14", "15.5 µg/m³"] {
+            scan.record(
+                "air1/sensor/pm_2_5mm_weight_concentration/state",
+                value.as_bytes(),
+            );
+        }
+        scan.record(
+            "zigbee2mqtt/office",
+            br#"{"temperature": 21.5, "linkquality": 120, "state": "ON"}"#,
+        );
+        scan.record("air1/status", b"online");
+        assert_eq!(scan.len(), 3);
+
+        let topics = scan.into_topics(&[]);
+        let pm = &topics[0];
+        assert_eq!(pm.samples, vec![11.0, 12.0, 13.0, 14.0, 15.5]);
+        assert_eq!(pm.messages, 6);
+        assert_eq!(pm.unit.as_deref(), Some("µg/m³"));
+        assert_eq!(pm.proposed.as_deref(), Some("pm25"));
+
+        let fields: Vec<(Option<&str>, Option<&str>)> = topics[1..]
+            .iter()
+            .map(|t| (t.path.as_deref(), t.proposed.as_deref()))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                (Some("/linkquality"), None),
+                (Some("/temperature"), Some("temperature"))
+            ]
+        );
+    }
+
+    #[test]
+    fn existing_rules_are_reported_as_mapped() {
+        let mut scan = TopicScan::default();
+        scan.record("lab/co2", b"612");
+        let rule = ScannedTopic {
+            topic: "lab/co2".to_string(),
+            path: None,
+            samples: Vec::new(),
+            unit: None,
+            messages: 0,
+            proposed: None,
+            mapped: false,
+        }
+        .rule("co2");
+        let topics = scan.into_topics(&[rule]);
+        assert!(topics[0].mapped);
+        assert_eq!(topics[0].proposed.as_deref(), Some("co2"));
+    }
+
+    #[test]
+    fn accepted_rules_replace_old_ones_and_add_subscriptions() {
+        let mut cfg = AppConfig::default();
+        cfg.mqtt.filters.push(TopicFilter {
+            filter: "zigbee2mqtt/#".to_string(),
+            qos: None,
+        });
+        let scanned = |topic: &str, path: Option<&str>| ScannedTopic {
+            topic: topic.to_string(),
+            path: path.map(str::to_string),
+            samples: vec![1.0],
+            unit: None,
+            messages: 1,
+            proposed: None,
+            mapped: false,
+        };
+
+        apply(
+            &mut cfg,
+            vec![
+                scanned("lab/co2", None).rule("co2"),
+                scanned("zigbee2mqtt/office", Some("/temperature")).rule("temperature"),
+            ],
+        );
+        apply(&mut cfg, vec![scanned("lab/co2", None).rule("tvoc")]);
+
+        let filters: Vec<&str> = cfg.mqtt.filters.iter().map(|f| f.filter.as_str()).collect();
+        assert_eq!(filters, vec!["zigbee2mqtt/#", "lab/co2"]);
+        let rules: Vec<(&str, &str)> = cfg
+            .mappings
+            .iter()
+            .map(|r| (r.topic.as_str(), r.metric.as_str()))
+            .collect();
+        assert_eq!(
+            rules,
+            vec![("zigbee2mqtt/office", "temperature"), ("lab/co2", "tvoc")]
+        );
+    }
+
+    #[test]
+    fn first_rules_keep_the_implicit_base_subscription() {
+        let mut cfg = AppConfig::default();
+        cfg.mqtt.topic_prefix = Some("air1".to_string());
+        let rule = ScannedTopic {
+            topic: "lab/co2".to_string(),
+            path: None,
+            samples: vec![612.0],
+            unit: None,
+            messages: 1,
+            proposed: None,
+            mapped: false,
+        }
+        .rule("co2");
+        apply(&mut cfg, vec![rule]);
+
+        let filters: Vec<&str> = cfg.mqtt.filters.iter().map(|f| f.filter.as_str()).collect();
+        assert_eq!(filters, vec!["air1/#", "lab/co2"]);
+    }
+}
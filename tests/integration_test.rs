@@ -4,7 +4,10 @@ use std::{
     time::{SystemTime, UNIX_EPOCH},
 };
 
-use air1_monitor::{app::Air1App, config};
+use air1_monitor::{
+    app::{Air1App, MqttEvent},
+    capture, config,
+};
 
 fn make_unique_tempdir() -> PathBuf {
     let now = SystemTime::now()
@@ -65,3 +68,94 @@ fn test_app_default_status_is_empty() {
         app.status
     );
 }
+
+#[test]
+fn test_readings_are_kept_per_device() {
+    let mut app = Air1App::default();
+    for (device, value) in [("air1-living", 12.0), ("air1-bedroom", 30.0)] {
+        app.mqtt_tx
+            .send(MqttEvent::Metric {
+                topic: format!("{device}/sensor/pm25/state"),
+                value,
+                kind: "pm25".to_string(),
+                unit: None,
+                device: device.to_string(),
+            })
+            .expect("send metric");
+    }
+    app.poll_mqtt();
+
+    assert_eq!(app.devices.len(), 2);
+    // Without a configured device the first known one is shown.
+    assert_eq!(app.selected_device(), Some("air1-bedroom"));
+    assert_eq!(app.metrics().value("pm25"), Some(30.0));
+
+    app.select_device("air1-living");
+    assert_eq!(app.metrics().value("pm25"), Some(12.0));
+}
+
+#[test]
+fn test_second_source_is_reported_until_a_policy_is_set() {
+    let mut app = Air1App::default();
+    for topic in [
+        "air1/sensor/temperature/state",
+        "air1/sensor/temp_probe/state",
+    ] {
+        app.mqtt_tx
+            .send(MqttEvent::Metric {
+                topic: topic.to_string(),
+                value: 70.0,
+                kind: "temperature".to_string(),
+                unit: None,
+                device: "air1".to_string(),
+            })
+            .expect("send metric");
+    }
+    app.poll_mqtt();
+
+    assert_eq!(app.conflicting_metrics(), vec!["temperature"]);
+    assert_eq!(
+        app.source_label("temperature").as_deref(),
+        Some("air1/sensor/temp_probe/state")
+    );
+
+    app.set_source_policies(vec![config::SourcePolicy {
+        metric: "temp".to_string(),
+        mode: config::SourceMode::Mean,
+        topic: None,
+    }]);
+    assert!(app.conflicting_metrics().is_empty());
+}
+
+#[test]
+fn test_capture_replays_through_the_mapper() {
+    let path = make_unique_tempdir().join("capture.jsonl");
+    fs::write(
+        &path,
+        concat!(
+            r#"{"unix_ms": 1760000000000, "topic": "air1/sensor/co2/state", "payload": "610"}"#,
+            "\n",
+            r#"{"unix_ms": 1760000005000, "topic": "air1/sensor/co2/state", "payload": "612"}"#,
+            "\n",
+            r#"{"unix_ms": 1760000006000, "topic": "lab/fan", "payload": "on", "retain": true}"#,
+            "\n",
+        ),
+    )
+    .expect("write capture");
+
+    let mut app = Air1App::default();
+    assert!(app.start_replay(&path, capture::ReplaySpeed::Max));
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while app.mqtt_state.is_running() && std::time::Instant::now() < deadline {
+        app.poll_mqtt();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    let _ = fs::remove_dir_all(path.parent().unwrap());
+
+    assert!(!app.mqtt_state.is_running(), "replay did not finish");
+    assert_eq!(app.status, "Replay finished: 3 messages");
+    assert_eq!(app.metrics().value("co2"), Some(612.0));
+    assert_eq!(app.explorer.len(), 2);
+    assert_eq!(app.explorer.unmapped(), 1);
+    assert_eq!(app.traffic.len(), 3);
+}